@@ -0,0 +1,46 @@
+//! Measures how many instructions per second the emulator gets through on `tests/fib`, with
+//! integer ops emulated on native integers and with every one of them done with `BigUint`s.
+
+use std::time::{Duration, Instant};
+use pcode::binary::Binary;
+use pcode::emulator::Machine;
+
+const RUNS: usize = 10;
+
+fn run(machine: &mut Machine, native: bool) -> anyhow::Result<(u64, Duration)> {
+    let start = Instant::now();
+    let mut emulator = machine.emulate("main")?;
+    emulator.set_native(native);
+
+    let mut instructions = 0u64;
+    while let Some(next) = emulator.next() {
+        let (i, pcode) = next?;
+        if i == 0 {
+            instructions += 1;
+        }
+        let control = emulator.emulate_one(pcode)?;
+        emulator.apply(control)?;
+    }
+    Ok((instructions, start.elapsed()))
+}
+
+fn main() -> anyhow::Result<()> {
+    let binary = Binary::new(concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fib/bin"))?;
+    let mut machine = Machine::new(&binary)?;
+
+    // the workload is short, so take the best of a few runs
+    let mut rates = Vec::new();
+    for (path, native) in [("BigUint", false), ("native", true)] {
+        let mut best = Duration::MAX;
+        let mut instructions = 0;
+        for _ in 0..RUNS {
+            let (count, elapsed) = run(&mut machine, native)?;
+            (instructions, best) = (count, best.min(elapsed));
+        }
+        let rate = instructions as f64 / best.as_secs_f64();
+        eprintln!("{}: {} instructions in {:?} ({:.0} instructions/s)", path, instructions, best, rate);
+        rates.push(rate);
+    }
+    eprintln!("native is {:.1}x the BigUint path", rates[1] / rates[0]);
+    Ok(())
+}
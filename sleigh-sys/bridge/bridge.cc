@@ -26,6 +26,11 @@ AddrSpace *getVarnodeSpace(const VarnodeData &data) {
 
 uint64_t getVarnodeOffset(const VarnodeData &data) { return data.offset; }
 
+int32_t getVarnodeConstSpaceIndex(const VarnodeData &data) {
+  // the constant input0 of a LOAD or STORE is a pointer to the space accessed
+  return ((AddrSpace *)(uintp)data.offset)->getIndex();
+}
+
 uint64_t getVarnode_sizeof() {
     return sizeof(VarnodeData);
 }
@@ -117,3 +122,7 @@ void Decompiler::getRegisterList(std::vector<RegisterPair> &out) const {
         out.push_back(std::move(pair));
     }
 }
+
+void Decompiler::getUserOpList(std::vector<std::string> &out) const {
+    getUserOpNames(out);
+}
@@ -84,6 +84,10 @@ public:
   int32_t disassemble(RustAssemblyEmit *emit, uint64_t addr, uint64_t limit) const;
   ContextDatabase *getContext() { return &this->context; }
   void getRegisterList(std::vector<RegisterPair> &out) const;
+  void getUserOpList(std::vector<std::string> &out) const;
+  int32_t getSpaceCount() const { return numSpaces(); }
+  AddrSpace *getSpaceByIndex(int32_t i) const { return getSpace(i); }
+  int32_t getDefaultCodeSpaceIndex() const { return getDefaultCodeSpace()->getIndex(); }
 };
 
 unique_ptr<Decompiler> newDecompiler(RustLoadImage *loadImage,
@@ -98,4 +102,5 @@ uint32_t getVarnodeSize(const VarnodeData &data);
 unique_ptr<Address> getVarnodeDataAddress(const VarnodeData &data);
 AddrSpace *getVarnodeSpace(const VarnodeData &data);
 uint64_t getVarnodeOffset(const VarnodeData &data);
+int32_t getVarnodeConstSpaceIndex(const VarnodeData &data);
 uint64_t getVarnode_sizeof();
\ No newline at end of file
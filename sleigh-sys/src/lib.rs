@@ -195,8 +195,10 @@ pub enum Opcode {
     Extract = 71,
     ///< Count the 1-bits
     PopCount = 72,
+    ///< Count the leading 0-bits
+    LzCount = 73,
     ///< INT MAX?
-    Max = 73,
+    Max = 74,
 }
 
 impl Opcode {
@@ -298,6 +300,7 @@ impl<'a> RustPCodeEmit<'a> {
 }
 
 #[cxx::bridge]
+#[allow(clippy::missing_safety_doc)]
 pub mod ffi {
     extern "Rust" {
         type RustAssemblyEmit<'a>;
@@ -347,6 +350,9 @@ pub mod ffi {
         fn getVarnodeDataAddress(data: &VarnodeData) -> UniquePtr<Address>;
         fn getVarnodeSpace(data: &VarnodeData) -> *mut AddrSpace;
         fn getVarnodeOffset(data: &VarnodeData) -> u64;
+        /// # Safety
+        /// `data` must be the space id constant of a LOAD or STORE, while its language is alive
+        unsafe fn getVarnodeConstSpaceIndex(data: &VarnodeData) -> i32;
         fn getVarnodeSize(data: &VarnodeData) -> u32;
         fn getVarnode_sizeof() -> u64;
 
@@ -408,6 +414,10 @@ pub mod ffi {
             spec: UniquePtr<DocumentStorage>,
         ) -> UniquePtr<Decompiler>;
         unsafe fn getRegisterList(self: &Decompiler, out: Pin<&mut CxxVector<RegisterPair>>);
+        unsafe fn getUserOpList(self: &Decompiler, out: Pin<&mut CxxVector<CxxString>>);
+        fn getSpaceCount(self: &Decompiler) -> i32;
+        fn getSpaceByIndex(self: &Decompiler, i: i32) -> *mut AddrSpace;
+        fn getDefaultCodeSpaceIndex(self: &Decompiler) -> i32;
 
         type RegisterPair;
         fn getKey(self: &RegisterPair) -> &CxxString;
@@ -16,8 +16,15 @@ pub type SpaceType = sleigh_sys::SpaceType;
 #[cfg_attr(feature = "serde", derive(serde_derive::Serialize))]
 pub struct AddrSpace {
     pub name: String,
+    /// the index of the space in the language, which identifies it
+    pub index: usize,
     pub type_: SpaceType,
+    /// the number of bytes in an addressable unit
     pub wordsize: u32,
+    /// the number of bytes in an address
+    pub addrsize: u32,
+    /// the highest byte offset in the space, offsets wrap past it
+    pub highest: u64,
     pub is_big_endian: bool,
 }
 
@@ -28,7 +35,10 @@ impl From<&sleigh_sys::ffi::AddrSpace> for AddrSpace {
         let name = space.getName().to_string();
         let is_big_endian = space.isBigEndian();
         let wordsize = space.getWordSize();
-        Self { name, type_, is_big_endian, wordsize }
+        let addrsize = space.getAddrSize();
+        let highest = space.getHighest();
+        let index = space.getIndex() as usize;
+        Self { name, index, type_, is_big_endian, wordsize, addrsize, highest }
     }
 }
 
@@ -63,6 +73,8 @@ impl From<&sleigh_sys::ffi::VarnodeData> for VarnodeData {
 #[cfg_attr(feature = "serde", derive(serde_derive::Serialize))]
 pub struct PCode {
     pub address: u64,
+    /// the op. The space id constant that is input0 of a LOAD or STORE is translated to the index
+    /// of the space, rather than sleigh's pointer to it.
     pub opcode: Opcode,
     pub vars: Vec<VarnodeData>,
     pub outvar: Option<VarnodeData>,
@@ -105,7 +117,14 @@ impl sleigh_sys::PCodeEmit for PCodeEmit {
         outvar: Option<&sleigh_sys::ffi::VarnodeData>,
         vars: &[&sleigh_sys::ffi::VarnodeData],
     ) {
-        let vars = vars.iter().map(|v| VarnodeData::from(*v)).collect::<Vec<_>>();
+        let raw_vars = vars;
+        let mut vars = raw_vars.iter().map(|v| VarnodeData::from(*v)).collect::<Vec<_>>();
+        if matches!(opcode, sleigh_sys::Opcode::Load | sleigh_sys::Opcode::Store) {
+            if let (Some(raw), Some(var)) = (raw_vars.first(), vars.first_mut()) {
+                // the pointer is to a space of the language doing the translating, so it's valid
+                var.offset = unsafe { sleigh_sys::ffi::getVarnodeConstSpaceIndex(raw) } as u64;
+            }
+        }
         let outvar = outvar.map(VarnodeData::from);
         let address = address.getOffset();
         let pcode = PCode {
@@ -130,9 +149,10 @@ impl<'a> sleigh_sys::LoadImage for SliceLoader<'a> {
         let required = ptr.len() as u64;
         ptr.fill(0);
 
-        if self.start <= addr {
-            let fill_len = required.min(len) as usize;
-            let offset = (addr - self.start) as usize;
+        if self.start <= addr && addr - self.start < len {
+            let offset = addr - self.start;
+            let fill_len = required.min(len - offset) as usize;
+            let offset = offset as usize;
             ptr[..fill_len].copy_from_slice(&self.data[offset..offset + fill_len]);
         }
     }
@@ -154,7 +174,7 @@ impl sleigh_sys::LoadImage for VectorLoader {
 }
 
 #[cfg_attr(feature = "serde", derive(serde_derive::Serialize))]
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum X86Mode {
     Mode16,
     Mode32,
@@ -162,7 +182,7 @@ pub enum X86Mode {
 }
 
 #[cfg_attr(feature = "serde", derive(serde_derive::Serialize))]
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum X64Mode {
     Mode16,
     Mode32,
@@ -170,14 +190,14 @@ pub enum X64Mode {
 }
 
 #[cfg_attr(feature = "serde", derive(serde_derive::Serialize))]
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum ArmMode {
     Arm,
     Thumb,
 }
 
 #[cfg_attr(feature = "serde", derive(serde_derive::Serialize))]
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum ArmVersion {
     Arm4,
     Arm4t,
@@ -189,7 +209,7 @@ pub enum ArmVersion {
 }
 
 #[cfg_attr(feature = "serde", derive(serde_derive::Serialize))]
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum Endian {
     LittleEndian,
     BigEndian,
@@ -279,6 +299,17 @@ impl DecompilerBuilder<()> {
         }
     }
 
+    /// A language without a dedicated builder, by the name of its .sla file (like `mips32le` or
+    /// `riscv.lp64d`), or `None` if there's no such language
+    pub fn language(self, name: &str) -> Option<DecompilerBuilder<ArchState>> {
+        Some(DecompilerBuilder {
+            state: ArchState {
+                spec: sla::get_arch_sla(name)?,
+                var: HashMap::new(),
+            },
+        })
+    }
+
     pub fn dalvik(self) -> DecompilerBuilder<ArchState> {
         DecompilerBuilder {
             state: ArchState {
@@ -309,7 +340,7 @@ impl DecompilerBuilder<ArchState> {
         let mut loader = Box::new(loader);
 
         unsafe {
-            let rust_loader = std::mem::transmute::<_, &'static mut VectorLoader>(loader.as_mut());
+            let rust_loader = std::mem::transmute::<&mut VectorLoader, &'static mut VectorLoader>(loader.as_mut());
             let rust_loader = Box::new(RustLoadImage::from_internal(rust_loader));
             let rust_loader: *mut RustLoadImage<'static> = Box::leak(rust_loader);
             let mut inner = sleigh_sys::ffi::newDecompiler(rust_loader, doc);
@@ -388,6 +419,28 @@ impl Decompiler {
         }
         out
     }
+
+    /// The names of the user-defined ops, indexed by the constant input0 of a CALLOTHER
+    pub fn get_user_ops(&self) -> Vec<String> {
+        let mut vec = CxxVector::new();
+        unsafe {
+            self.inner.getUserOpList(vec.pin_mut());
+        }
+        vec.iter().map(|name| name.to_string()).collect()
+    }
+
+    /// The index of the space that code (and most data) is in
+    pub fn get_default_space(&self) -> usize {
+        self.inner.getDefaultCodeSpaceIndex() as usize
+    }
+
+    /// The address spaces defined by the language, in index order
+    pub fn get_spaces(&self) -> Vec<AddrSpace> {
+        (0..self.inner.getSpaceCount())
+            .filter_map(|i| unsafe { self.inner.getSpaceByIndex(i).as_ref() })
+            .map(AddrSpace::from)
+            .collect()
+    }
 }
 
 #[cfg(test)]
@@ -400,17 +453,17 @@ mod tests {
             .arm(ArmVersion::Arm8, Endian::LittleEndian, ArmMode::Arm)
             .build();
         for _ in 0..100 {
-            let (n, pcodes) = decompiler.translate(b"\x01\x00\x80\x00", 0x1000, 0);
+            let (n, pcodes) = decompiler.translate(b"\x01\x00\x80\x00", 0x1000, 4);
             println!("{} {:?}", n, pcodes);
-            let (n, insts) = decompiler.disassemble(b"\x01\x00\x80\x00", 0x1000, 0);
+            let (n, insts) = decompiler.disassemble(b"\x01\x00\x80\x00", 0x1000, 4);
             println!("{} {:?}", n, insts);
         }
     }
 
     fn run(decompiler: &mut Decompiler, code: &[u8], addr: u64) {
-        let (n, pcodes) = decompiler.translate(code, addr, 0);
+        let (n, pcodes) = decompiler.translate(code, addr, code.len() as u64);
         println!("{} {:?}", n, pcodes);
-        let (n, insts) = decompiler.disassemble(code, addr, 0);
+        let (n, insts) = decompiler.disassemble(code, addr, code.len() as u64);
         println!("{} {:?}", n, insts);
     }
 
@@ -446,6 +499,45 @@ mod tests {
         run(&mut decompiler, b"\x11\x44\x11\x44", 0x1000);
     }
 
+    #[test]
+    fn test_user_ops() {
+        let decompiler = Decompiler::builder().x86(X86Mode::Mode32).build();
+        let user_ops = decompiler.get_user_ops();
+        assert!(user_ops.iter().any(|name| name == "segment"));
+        assert!(user_ops.iter().any(|name| name == "rdtsc"));
+    }
+
+    #[test]
+    fn test_spaces() {
+        let decompiler = Decompiler::builder().x86(X86Mode::Mode32).build();
+        let spaces = decompiler.get_spaces();
+        let ram = spaces.iter().find(|space| space.name == "ram").expect("no ram space");
+        assert_eq!((ram.wordsize, ram.addrsize, ram.highest), (1, 4, 0xFFFF_FFFF));
+        assert!(!ram.is_big_endian);
+        assert!(spaces.iter().any(|space| space.name == "register"));
+        assert!(spaces.iter().any(|space| space.name == "unique"));
+        assert!(spaces.iter().all(|space| space.index < spaces.len()));
+        assert_eq!(decompiler.get_default_space(), ram.index);
+    }
+
+    #[test]
+    fn test_load_space_index() {
+        let mut decompiler = Decompiler::builder().x86(X86Mode::Mode32).build();
+        // mov eax, [ecx]
+        let (_, pcodes) = decompiler.translate(&[0x8B, 0x01], 0x1000, 1);
+        let load = pcodes.iter().find(|pcode| pcode.opcode == Opcode::Load).expect("no load");
+        assert_eq!(load.vars[0].space.type_, SpaceType::Constant);
+        assert_eq!(load.vars[0].offset as usize, decompiler.get_default_space());
+    }
+
+    #[test]
+    fn test_language() {
+        let mut decompiler = Decompiler::builder().language("mips32le").unwrap().build();
+        // addiu v0, zero, 1
+        run(&mut decompiler, b"\x01\x00\x02\x24", 0x1000);
+        assert!(Decompiler::builder().language("vax").is_none());
+    }
+
     #[test]
     fn test_dalvik() {
         let mut decompiler = Decompiler::builder().dalvik().build();
@@ -0,0 +1,485 @@
+//!
+//! Choosing the sleigh language a binary is decoded with.
+//!
+//! For ELF files the language comes from the ELF header: `e_machine` picks the processor,
+//! `EI_CLASS` and `EI_DATA` pick its width and byte order, and `e_flags` (or the low bit of the
+//! entry point, for Thumb) pick the variant. The version of ARM code comes from the build
+//! attributes in its `.ARM.attributes` section, falling back to the EABI version in `e_flags`.
+//! PE images are always little endian, so their `Machine` field is enough.
+
+use anyhow::{bail, Context};
+use sleigh::{ArmMode, ArmVersion, Decompiler, Endian, X86Mode};
+use crate::binary::{ElfClass, ElfHeader, PeHeader};
+
+const EM_SPARC: u16 = 2;
+const EM_386: u16 = 3;
+const EM_68K: u16 = 4;
+const EM_MIPS: u16 = 8;
+const EM_SPARC32PLUS: u16 = 18;
+const EM_PPC: u16 = 20;
+const EM_PPC64: u16 = 21;
+const EM_ARM: u16 = 40;
+const EM_SH: u16 = 42;
+const EM_SPARCV9: u16 = 43;
+const EM_X86_64: u16 = 62;
+const EM_AARCH64: u16 = 183;
+const EM_RISCV: u16 = 243;
+
+const IMAGE_FILE_MACHINE_I386: u16 = 0x14C;
+const IMAGE_FILE_MACHINE_ARM: u16 = 0x1C0;
+const IMAGE_FILE_MACHINE_THUMB: u16 = 0x1C2;
+const IMAGE_FILE_MACHINE_ARMNT: u16 = 0x1C4;
+const IMAGE_FILE_MACHINE_AMD64: u16 = 0x8664;
+const IMAGE_FILE_MACHINE_ARM64: u16 = 0xAA64;
+
+/// the bits of an ARM `e_flags` holding the EABI version, zero for the old GNU ABI
+const EF_ARM_EABIMASK: u32 = 0xFF00_0000;
+
+/// the build attribute holding the version of the ARM architecture
+const TAG_CPU_ARCH: u64 = 6;
+
+/// the bits of a MIPS `e_flags` holding the architecture level
+const EF_MIPS_ARCH: u32 = 0xF000_0000;
+const EF_MIPS_ARCH_32R6: u32 = 0x9000_0000;
+
+/// A processor the emulator can decode, along with the mode it starts in
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Architecture {
+    X86(X86Mode),
+    Arm { version: ArmVersion, endian: Endian, mode: ArmMode },
+    AArch64(Endian),
+    Mips { language: &'static str },
+    PowerPc { language: &'static str },
+    RiscV { language: &'static str },
+    Sparc { language: &'static str },
+    SuperH { language: &'static str },
+    M68k,
+}
+
+impl Architecture {
+    /// The architecture an ELF file was built for, given the contents of its `.ARM.attributes`
+    /// section if it has one
+    pub fn from_header(header: &ElfHeader, attributes: Option<&[u8]>) -> anyhow::Result<Self> {
+        let wide = header.class == ElfClass::Elf64;
+        let big = header.big_endian;
+        let endian = if big { Endian::BigEndian } else { Endian::LittleEndian };
+        let pick = |little: &'static str, big_endian: &'static str| if big { big_endian } else { little };
+
+        let architecture = match header.machine {
+            EM_386 | EM_X86_64 if big => bail!("big endian x86 isn't a thing"),
+            EM_386 => Architecture::X86(X86Mode::Mode32),
+            // x32 binaries are 32 bit ELF files, but still run in 64 bit mode
+            EM_X86_64 => Architecture::X86(X86Mode::Mode64),
+            EM_ARM => Architecture::Arm {
+                version: attributes.and_then(|attributes| attributes_version(attributes, big))
+                    .unwrap_or_else(|| eabi_version(header.flags)),
+                endian,
+                // the entry point of a Thumb program has the low bit set
+                mode: if header.entry & 1 == 1 { ArmMode::Thumb } else { ArmMode::Arm },
+            },
+            EM_AARCH64 => Architecture::AArch64(endian),
+            EM_MIPS => {
+                let language = match (wide, header.flags & EF_MIPS_ARCH) {
+                    (false, EF_MIPS_ARCH_32R6) => pick("mips32R6le", "mips32R6be"),
+                    (false, _) => pick("mips32le", "mips32be"),
+                    (true, _) => pick("mips64le", "mips64be"),
+                };
+                Architecture::Mips { language }
+            }
+            EM_PPC => Architecture::PowerPc { language: pick("ppc_32_le", "ppc_32_be") },
+            EM_PPC64 => Architecture::PowerPc { language: pick("ppc_64_le", "ppc_64_be") },
+            EM_RISCV if big => bail!("big endian RISC-V isn't supported"),
+            EM_RISCV => Architecture::RiscV { language: if wide { "riscv.lp64d" } else { "riscv.ilp32d" } },
+            EM_SPARC | EM_SPARC32PLUS => Architecture::Sparc { language: "SparcV9_32" },
+            EM_SPARCV9 => Architecture::Sparc { language: "SparcV9_64" },
+            EM_SH => Architecture::SuperH { language: pick("SuperH4_le", "SuperH4_be") },
+            EM_68K => Architecture::M68k,
+            machine => bail!("unsupported architecture: {} (e_machine {})", machine_name(machine), machine),
+        };
+        Ok(architecture)
+    }
+
+    /// The architecture a PE image was built for
+    pub fn from_pe_header(header: &PeHeader) -> anyhow::Result<Self> {
+        let architecture = match header.machine {
+            IMAGE_FILE_MACHINE_I386 => Architecture::X86(X86Mode::Mode32),
+            IMAGE_FILE_MACHINE_AMD64 => Architecture::X86(X86Mode::Mode64),
+            // Windows CE targets ARMv4T
+            IMAGE_FILE_MACHINE_ARM => Architecture::Arm {
+                version: ArmVersion::Arm4t,
+                endian: Endian::LittleEndian,
+                mode: ArmMode::Arm,
+            },
+            IMAGE_FILE_MACHINE_THUMB => Architecture::Arm {
+                version: ArmVersion::Arm4t,
+                endian: Endian::LittleEndian,
+                mode: ArmMode::Thumb,
+            },
+            // Windows on ARM only runs Thumb-2 code on ARMv7
+            IMAGE_FILE_MACHINE_ARMNT => Architecture::Arm {
+                version: ArmVersion::Arm7,
+                endian: Endian::LittleEndian,
+                mode: ArmMode::Thumb,
+            },
+            IMAGE_FILE_MACHINE_ARM64 => Architecture::AArch64(Endian::LittleEndian),
+            machine => bail!("unsupported architecture: {} (PE machine {:#X})", pe_machine_name(machine), machine),
+        };
+        Ok(architecture)
+    }
+
+    /// A decompiler for the architecture's sleigh language, which fails if the language isn't
+    /// one of the bundled sleigh specifications
+    pub fn decompiler(&self) -> anyhow::Result<Decompiler> {
+        let builder = Decompiler::builder();
+        let builder = match *self {
+            Architecture::X86(mode) => builder.x86(mode),
+            Architecture::Arm { version, endian, mode } => builder.arm(version, endian, mode),
+            Architecture::AArch64(endian) => builder.aarch64(endian),
+            Architecture::M68k => builder.language("68040").context("no 68040 language")?,
+            Architecture::Mips { language }
+            | Architecture::PowerPc { language }
+            | Architecture::RiscV { language }
+            | Architecture::Sparc { language }
+            | Architecture::SuperH { language } => builder.language(language)
+                .with_context(|| format!("no {} language", language))?,
+        };
+        Ok(builder.build())
+    }
+
+    /// The register holding the stack pointer
+    pub fn stack_pointer(&self) -> &'static str {
+        match self {
+            Architecture::X86(X86Mode::Mode64) => "RSP",
+            Architecture::X86(_) => "ESP",
+            Architecture::PowerPc { .. } => "r1",
+            Architecture::SuperH { .. } => "r15",
+            Architecture::M68k => "SP",
+            _ => "sp",
+        }
+    }
+
+    /// The register holding the address of the current instruction
+    pub fn program_counter(&self) -> &'static str {
+        match self {
+            Architecture::X86(X86Mode::Mode64) => "RIP",
+            Architecture::X86(_) => "EIP",
+            Architecture::Sparc { .. } | Architecture::SuperH { .. } | Architecture::M68k => "PC",
+            _ => "pc",
+        }
+    }
+
+    /// The register a function returns an integer in
+    pub fn return_register(&self) -> &'static str {
+        match self {
+            Architecture::X86(X86Mode::Mode64) => "RAX",
+            Architecture::X86(_) => "EAX",
+            Architecture::Arm { .. } | Architecture::SuperH { .. } => "r0",
+            Architecture::AArch64(_) => "x0",
+            Architecture::Mips { .. } => "v0",
+            Architecture::PowerPc { .. } => "r3",
+            Architecture::RiscV { .. } => "a0",
+            // the callee's i0 is the caller's o0 once the register window is restored
+            Architecture::Sparc { .. } => "o0",
+            Architecture::M68k => "D0",
+        }
+    }
+
+    /// The registers worth showing in a register dump, in order. Some of them might not exist in
+    /// every variant of the language.
+    pub fn general_registers(&self) -> Vec<String> {
+        let mut registers = match self {
+            Architecture::X86(X86Mode::Mode64) => {
+                ["RAX", "RCX", "RDX", "RBX", "RSP", "RBP", "RSI", "RDI"].map(String::from).into_iter()
+                    .chain(numbered("R", 16).skip(8))
+                    .collect()
+            }
+            Architecture::X86(_) => {
+                ["EAX", "ECX", "EDX", "EBX", "ESP", "EBP", "ESI", "EDI"].map(String::from).to_vec()
+            }
+            Architecture::Arm { .. } => numbered("r", 13).chain(["sp".to_string(), "lr".to_string()]).collect(),
+            Architecture::AArch64(_) => numbered("x", 31).chain(["sp".to_string()]).collect(),
+            Architecture::Mips { .. } => [
+                "at", "v0", "v1", "a0", "a1", "a2", "a3", "t0", "t1", "t2", "t3", "t4", "t5", "t6", "t7",
+                "s0", "s1", "s2", "s3", "s4", "s5", "s6", "s7", "t8", "t9", "gp", "sp", "s8", "ra",
+            ].map(String::from).to_vec(),
+            Architecture::PowerPc { .. } => numbered("r", 32).chain(["LR".to_string(), "CTR".to_string()]).collect(),
+            Architecture::RiscV { .. } => [
+                "ra", "sp", "gp", "tp", "t0", "t1", "t2", "s0", "s1", "a0", "a1", "a2", "a3", "a4", "a5",
+                "a6", "a7", "s2", "s3", "s4", "s5", "s6", "s7", "s8", "s9", "s10", "s11", "t3", "t4", "t5", "t6",
+            ].map(String::from).to_vec(),
+            // sleigh calls o6 and i6 by their roles
+            Architecture::Sparc { .. } => numbered("g", 8)
+                .chain(numbered("o", 8).map(|name| if name == "o6" { "sp".to_string() } else { name }))
+                .chain(numbered("l", 8))
+                .chain(numbered("i", 8).map(|name| if name == "i6" { "fp".to_string() } else { name }))
+                .collect(),
+            Architecture::SuperH { .. } => numbered("r", 16).chain(["PR".to_string()]).collect(),
+            Architecture::M68k => numbered("D", 8).chain(numbered("A", 7)).chain(["SP".to_string()]).collect(),
+        };
+        registers.push(self.program_counter().to_string());
+        registers
+    }
+
+    /// The condition flags sleigh models as separate registers
+    pub fn flag_registers(&self) -> Vec<String> {
+        let flags: &[&str] = match self {
+            Architecture::X86(_) => &["CF", "PF", "AF", "ZF", "SF", "OF"],
+            Architecture::Arm { .. } | Architecture::AArch64(_) => &["NG", "ZR", "CY", "OV"],
+            _ => &[],
+        };
+        flags.iter().map(|flag| flag.to_string()).collect()
+    }
+}
+
+/// The ARM version for a file without build attributes. The old GNU ABI predates ARMv6, while EABI
+/// files could be any version, so they get the newest, which decodes the most instructions.
+fn eabi_version(flags: u32) -> ArmVersion {
+    if flags & EF_ARM_EABIMASK == 0 { ArmVersion::Arm5t } else { ArmVersion::Arm8 }
+}
+
+/// The ARM version in the `Tag_CPU_arch` of an `.ARM.attributes` section, if it has one
+fn attributes_version(attributes: &[u8], big_endian: bool) -> Option<ArmVersion> {
+    let arch = cpu_arch(attributes, big_endian)?;
+    let version = match arch {
+        // pre-v4 and v4
+        0 | 1 => ArmVersion::Arm4,
+        2 => ArmVersion::Arm4t,
+        // v5T, v5TE and v5TEJ
+        3..=5 => ArmVersion::Arm5t,
+        // v6 and its variants, including v6-M
+        6..=9 | 11 | 12 => ArmVersion::Arm6,
+        // v7 and v7E-M
+        10 | 13 => ArmVersion::Arm7,
+        _ => ArmVersion::Arm8,
+    };
+    Some(version)
+}
+
+/// Finds `Tag_CPU_arch` in the "aeabi" subsection of the build attributes. The section is a
+/// version byte followed by subsections, each a length, a vendor name and then tagged groups of
+/// attributes, whose tags and values are ULEB128 numbers or NUL terminated strings.
+fn cpu_arch(attributes: &[u8], big_endian: bool) -> Option<u64> {
+    let (&version, mut rest) = attributes.split_first()?;
+    if version != b'A' {
+        return None;
+    }
+    while rest.len() >= 4 {
+        let length = read_u32(rest, big_endian)? as usize;
+        let subsection = rest.get(4..length)?;
+        rest = &rest[length..];
+        let name_end = subsection.iter().position(|byte| *byte == 0)?;
+        if &subsection[..name_end] != b"aeabi" {
+            continue;
+        }
+
+        let mut groups = &subsection[name_end + 1..];
+        while groups.len() >= 5 {
+            let (tag, size) = (groups[0], read_u32(&groups[1..], big_endian)? as usize);
+            let mut group = groups.get(5..size)?;
+            groups = &groups[size..];
+            // only attributes of the whole file are interesting, not of its sections or symbols
+            if tag != 1 {
+                continue;
+            }
+            while !group.is_empty() {
+                let tag = uleb128(&mut group)?;
+                match tag {
+                    TAG_CPU_ARCH => return uleb128(&mut group),
+                    // Tag_compatibility is a number and a string
+                    32 => {
+                        uleb128(&mut group)?;
+                        skip_string(&mut group)?;
+                    }
+                    // Tag_CPU_raw_name, Tag_CPU_name, and odd tags past 32 are strings
+                    4 | 5 => skip_string(&mut group)?,
+                    tag if tag > 32 && tag % 2 == 1 => skip_string(&mut group)?,
+                    _ => {
+                        uleb128(&mut group)?;
+                    }
+                }
+            }
+        }
+    }
+    None
+}
+
+fn read_u32(bytes: &[u8], big_endian: bool) -> Option<u32> {
+    let bytes = bytes.get(..4)?.try_into().ok()?;
+    Some(if big_endian { u32::from_be_bytes(bytes) } else { u32::from_le_bytes(bytes) })
+}
+
+/// Reads a ULEB128 number from the front of `bytes`
+fn uleb128(bytes: &mut &[u8]) -> Option<u64> {
+    let mut value = 0u64;
+    for (i, byte) in bytes.iter().enumerate().take(10) {
+        value |= u64::from(byte & 0x7F) << (7 * i);
+        if byte & 0x80 == 0 {
+            *bytes = &bytes[i + 1..];
+            return Some(value);
+        }
+    }
+    None
+}
+
+/// Skips a NUL terminated string at the front of `bytes`
+fn skip_string(bytes: &mut &[u8]) -> Option<()> {
+    let end = bytes.iter().position(|byte| *byte == 0)?;
+    *bytes = &bytes[end + 1..];
+    Some(())
+}
+
+/// `count` registers named by a prefix and their number
+fn numbered(prefix: &str, count: usize) -> impl Iterator<Item = String> + '_ {
+    (0..count).map(move |i| format!("{}{}", prefix, i))
+}
+
+/// A readable name for machines that aren't supported, so errors say what the file is
+fn machine_name(machine: u16) -> &'static str {
+    match machine {
+        0 => "no machine",
+        7 => "Intel 80860",
+        22 => "IBM S/390",
+        50 => "Intel IA-64",
+        83 => "Atmel AVR",
+        105 => "TI MSP430",
+        164 => "Qualcomm Hexagon",
+        247 => "eBPF",
+        258 => "LoongArch",
+        _ => "unknown machine",
+    }
+}
+
+/// A readable name for PE machines that aren't supported
+fn pe_machine_name(machine: u16) -> &'static str {
+    match machine {
+        0 => "no machine",
+        0x200 => "Intel IA-64",
+        0x5032 | 0x5064 | 0x5128 => "RISC-V",
+        0x6232 | 0x6264 => "LoongArch",
+        0xA641 => "ARM64EC",
+        0xEBC => "EFI byte code",
+        _ => "unknown machine",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn header(machine: u16, class: ElfClass, big_endian: bool) -> ElfHeader {
+        ElfHeader {
+            class,
+            big_endian,
+            os_abi: 0,
+            abi_version: 0,
+            kind: 2,
+            machine,
+            version: 1,
+            entry: 0x1000,
+            flags: 0,
+        }
+    }
+
+    #[test]
+    fn test_from_header() {
+        use ElfClass::*;
+        let arch = |header: &ElfHeader| Architecture::from_header(header, None).unwrap();
+        assert_eq!(arch(&header(EM_386, Elf32, false)), Architecture::X86(X86Mode::Mode32));
+        assert_eq!(arch(&header(EM_X86_64, Elf64, false)), Architecture::X86(X86Mode::Mode64));
+        assert_eq!(arch(&header(EM_AARCH64, Elf64, true)), Architecture::AArch64(Endian::BigEndian));
+        assert_eq!(arch(&header(EM_MIPS, Elf64, false)), Architecture::Mips { language: "mips64le" });
+        assert_eq!(arch(&header(EM_RISCV, Elf32, false)), Architecture::RiscV { language: "riscv.ilp32d" });
+
+        let mut arm = header(EM_ARM, Elf32, true);
+        assert_eq!(arch(&arm), Architecture::Arm { version: ArmVersion::Arm5t, endian: Endian::BigEndian, mode: ArmMode::Arm });
+        arm.entry |= 1;
+        arm.flags = 0x0500_0000;
+        assert_eq!(arch(&arm), Architecture::Arm { version: ArmVersion::Arm8, endian: Endian::BigEndian, mode: ArmMode::Thumb });
+
+        let mut mips = header(EM_MIPS, Elf32, true);
+        mips.flags = EF_MIPS_ARCH_32R6 | 0x1007;
+        assert_eq!(arch(&mips), Architecture::Mips { language: "mips32R6be" });
+
+        let error = Architecture::from_header(&header(83, Elf32, false), None).unwrap_err();
+        assert_eq!(error.to_string(), "unsupported architecture: Atmel AVR (e_machine 83)");
+        assert!(Architecture::from_header(&header(EM_386, Elf32, true), None).is_err());
+    }
+
+    #[test]
+    fn test_arm_attributes() {
+        // what GCC writes for -march=armv7-a: Tag_CPU_name "7-A", Tag_CPU_arch v7, Tag_CPU_arch_profile 'A'
+        let u32_bytes = |value: u32, big_endian: bool| if big_endian { value.to_be_bytes() } else { value.to_le_bytes() };
+        let attributes = |big_endian: bool| {
+            let attributes = [&[5][..], b"7-A\0", &[6, 10, 7, b'A', 8, 1, 9, 2]].concat();
+            let group = [&[1][..], &u32_bytes(5 + attributes.len() as u32, big_endian), &attributes].concat();
+            let subsection = [&u32_bytes(10 + group.len() as u32, big_endian)[..], b"aeabi\0", &group].concat();
+            // another vendor's subsection comes first, and is skipped
+            let vendor = [&u32_bytes(12, big_endian)[..], b"gnu\0", &[1, 2, 3, 4]].concat();
+            [&[b'A'][..], &vendor, &subsection].concat()
+        };
+        assert_eq!(attributes_version(&attributes(false), false), Some(ArmVersion::Arm7));
+        assert_eq!(attributes_version(&attributes(true), true), Some(ArmVersion::Arm7));
+        assert_eq!(attributes_version(b"A", false), None);
+        assert_eq!(attributes_version(&attributes(false)[..20], false), None);
+
+        let arm = header(EM_ARM, ElfClass::Elf32, false);
+        let arch = Architecture::from_header(&arm, Some(&attributes(false))).unwrap();
+        assert_eq!(arch, Architecture::Arm { version: ArmVersion::Arm7, endian: Endian::LittleEndian, mode: ArmMode::Arm });
+    }
+
+    #[test]
+    fn test_from_pe_header() {
+        let header = |machine: u16| PeHeader {
+            machine,
+            wide: false,
+            characteristics: 0x102,
+            timestamp: 0,
+            image_base: 0x400000,
+            entry: 0x1000,
+            section_alignment: 0x1000,
+            file_alignment: 0x200,
+            size_of_image: 0x2000,
+            size_of_headers: 0x400,
+            subsystem: 3,
+            dll_characteristics: 0,
+            directories: vec![],
+        };
+        let arch = |machine: u16| Architecture::from_pe_header(&header(machine)).unwrap();
+        assert_eq!(arch(IMAGE_FILE_MACHINE_I386), Architecture::X86(X86Mode::Mode32));
+        assert_eq!(arch(IMAGE_FILE_MACHINE_AMD64), Architecture::X86(X86Mode::Mode64));
+        assert_eq!(arch(IMAGE_FILE_MACHINE_ARMNT), Architecture::Arm {
+            version: ArmVersion::Arm7,
+            endian: Endian::LittleEndian,
+            mode: ArmMode::Thumb,
+        });
+        assert_eq!(arch(IMAGE_FILE_MACHINE_ARM64), Architecture::AArch64(Endian::LittleEndian));
+
+        let error = Architecture::from_pe_header(&header(0x200)).unwrap_err();
+        assert_eq!(error.to_string(), "unsupported architecture: Intel IA-64 (PE machine 0x200)");
+    }
+
+    #[test]
+    fn test_registers_exist() {
+        let architectures = [
+            Architecture::X86(X86Mode::Mode32),
+            Architecture::X86(X86Mode::Mode64),
+            Architecture::Arm { version: ArmVersion::Arm8, endian: Endian::LittleEndian, mode: ArmMode::Thumb },
+            Architecture::Arm { version: ArmVersion::Arm4t, endian: Endian::BigEndian, mode: ArmMode::Arm },
+            Architecture::AArch64(Endian::LittleEndian),
+            Architecture::Mips { language: "mips32le" },
+            Architecture::PowerPc { language: "ppc_32_be" },
+            Architecture::RiscV { language: "riscv.lp64d" },
+            Architecture::Sparc { language: "SparcV9_64" },
+            Architecture::SuperH { language: "SuperH4_le" },
+            Architecture::M68k,
+        ];
+        for architecture in architectures {
+            let registers = architecture.decompiler().unwrap().get_all_registers();
+            let names = registers.values().collect::<Vec<_>>();
+            let expected = [architecture.stack_pointer().to_string(), architecture.program_counter().to_string()];
+            for name in expected.iter().chain(&architecture.general_registers()).chain(&architecture.flag_registers()) {
+                assert!(names.contains(&name), "{:?} has no {}", architecture, name);
+            }
+        }
+    }
+}
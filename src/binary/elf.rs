@@ -0,0 +1,629 @@
+//!
+//! A parser for ELF files, covering the header, program headers, section headers and symbol
+//! tables of 32 and 64 bit files in either byte order.
+//!
+//! Segment and section types, their flags and symbol attributes are named the way `llvm-readobj`
+//! names them, so the results can be compared with its output.
+
+use anyhow::{bail, ensure, Context};
+use crate::binary::{Section, Segment, Symbol};
+
+/// the magic bytes every ELF file starts with
+const MAGIC: &[u8; 4] = b"\x7FELF";
+
+const SHT_SYMTAB: u32 = 2;
+const SHT_NOBITS: u32 = 8;
+
+const SHN_UNDEF: u16 = 0;
+const SHN_LORESERVE: u16 = 0xFF00;
+const SHN_ABS: u16 = 0xFFF1;
+const SHN_COMMON: u16 = 0xFFF2;
+const SHN_XINDEX: u16 = 0xFFFF;
+
+/// Whether a file uses 32 or 64 bit addresses and offsets
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ElfClass {
+    Elf32,
+    Elf64,
+}
+
+/// The fields of the ELF header that describe the file, rather than where its tables are
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ElfHeader {
+    pub class: ElfClass,
+    pub big_endian: bool,
+    /// `EI_OSABI`, the operating system or ABI the file targets
+    pub os_abi: u8,
+    /// `EI_ABIVERSION`
+    pub abi_version: u8,
+    /// `e_type`, whether the file is relocatable, an executable, a shared object or a core dump
+    pub kind: u16,
+    /// `e_machine`, the architecture of the file
+    pub machine: u16,
+    /// `e_version`
+    pub version: u32,
+    /// the address execution starts at
+    pub entry: u64,
+    /// `e_flags`, which are specific to the architecture
+    pub flags: u32,
+}
+
+/// The contents of an ELF file, in the order they appear in it
+#[derive(Debug)]
+pub struct Elf {
+    pub header: ElfHeader,
+    pub segments: Vec<Segment>,
+    pub sections: Vec<(String, Section)>,
+    pub symbols: Vec<(String, Symbol)>,
+}
+
+/// Reads fields one after another in the byte order and class of a file
+struct Cursor<'a> {
+    bytes: &'a [u8],
+    offset: usize,
+    big_endian: bool,
+    class: ElfClass,
+}
+
+impl<'a> Cursor<'a> {
+    fn new(bytes: &'a [u8], offset: u64, header: &ElfHeader) -> anyhow::Result<Self> {
+        let offset = usize::try_from(offset).ok()
+            .filter(|offset| *offset <= bytes.len())
+            .with_context(|| format!("offset {:#X} is past the end of the file", offset))?;
+        Ok(Self { bytes, offset, big_endian: header.big_endian, class: header.class })
+    }
+
+    fn take<const N: usize>(&mut self) -> anyhow::Result<[u8; N]> {
+        let bytes = self.bytes.get(self.offset..self.offset + N)
+            .context("unexpected end of file")?;
+        self.offset += N;
+        Ok(bytes.try_into().unwrap())
+    }
+
+    fn u8(&mut self) -> anyhow::Result<u8> {
+        Ok(self.take::<1>()?[0])
+    }
+
+    fn u16(&mut self) -> anyhow::Result<u16> {
+        let bytes = self.take()?;
+        Ok(if self.big_endian { u16::from_be_bytes(bytes) } else { u16::from_le_bytes(bytes) })
+    }
+
+    fn u32(&mut self) -> anyhow::Result<u32> {
+        let bytes = self.take()?;
+        Ok(if self.big_endian { u32::from_be_bytes(bytes) } else { u32::from_le_bytes(bytes) })
+    }
+
+    fn u64(&mut self) -> anyhow::Result<u64> {
+        let bytes = self.take()?;
+        Ok(if self.big_endian { u64::from_be_bytes(bytes) } else { u64::from_le_bytes(bytes) })
+    }
+
+    /// An address, offset or size, which is as wide as the class of the file
+    fn word(&mut self) -> anyhow::Result<u64> {
+        match self.class {
+            ElfClass::Elf32 => self.u32().map(u64::from),
+            ElfClass::Elf64 => self.u64(),
+        }
+    }
+}
+
+/// A section header, with the fields needed to find the tables it holds
+struct SectionHeader {
+    name: u32,
+    kind: u32,
+    flags: u64,
+    address: u64,
+    offset: u64,
+    size: u64,
+    link: u32,
+    alignment: u64,
+    entry_size: u64,
+}
+
+impl SectionHeader {
+    fn read(cursor: &mut Cursor) -> anyhow::Result<Self> {
+        let name = cursor.u32()?;
+        let kind = cursor.u32()?;
+        let flags = cursor.word()?;
+        let address = cursor.word()?;
+        let offset = cursor.word()?;
+        let size = cursor.word()?;
+        let link = cursor.u32()?;
+        let _info = cursor.u32()?;
+        let alignment = cursor.word()?;
+        let entry_size = cursor.word()?;
+        Ok(Self { name, kind, flags, address, offset, size, link, alignment, entry_size })
+    }
+
+    /// The bytes of the section in the file, which is nothing for sections like `.bss`
+    fn data<'a>(&self, bytes: &'a [u8]) -> anyhow::Result<&'a [u8]> {
+        if self.kind == SHT_NOBITS {
+            return Ok(&[]);
+        }
+        usize::try_from(self.offset).ok()
+            .zip(usize::try_from(self.size).ok())
+            .and_then(|(start, size)| bytes.get(start..start.checked_add(size)?))
+            .with_context(|| format!("section at {:#X} is past the end of the file", self.offset))
+    }
+}
+
+/// Where the header tables are, from the ELF header
+struct Tables {
+    program_offset: u64,
+    program_header_size: u16,
+    program_count: u16,
+    section_offset: u64,
+    section_header_size: u16,
+    section_count: u16,
+    names_index: u16,
+}
+
+/// Parses the header, sections and symbols of an ELF file
+pub fn parse(bytes: &[u8]) -> anyhow::Result<Elf> {
+    let (header, tables) = read_header(bytes)?;
+
+    let mut segments = Vec::new();
+    if tables.program_offset != 0 {
+        for i in 0..u64::from(tables.program_count) {
+            let offset = tables.program_offset + i * u64::from(tables.program_header_size);
+            segments.push(read_segment(&mut Cursor::new(bytes, offset, &header)?)?);
+        }
+    }
+
+    let section_offset = tables.section_offset;
+    let mut section_count = u64::from(tables.section_count);
+    let mut names_index = u32::from(tables.names_index);
+
+    let mut headers = Vec::new();
+    if section_offset != 0 {
+        // files with a lot of sections keep the real counts in the first section header
+        let first = SectionHeader::read(&mut Cursor::new(bytes, section_offset, &header)?)?;
+        if section_count == 0 {
+            section_count = first.size;
+        }
+        if names_index == u32::from(SHN_XINDEX) {
+            names_index = first.link;
+        }
+
+        for i in 0..section_count {
+            let offset = section_offset + i * u64::from(tables.section_header_size);
+            headers.push(SectionHeader::read(&mut Cursor::new(bytes, offset, &header)?)?);
+        }
+    }
+
+    let names = match headers.get(names_index as usize) {
+        Some(names) => names.data(bytes)?,
+        None => &[],
+    };
+    let section_names = headers.iter()
+        .map(|section| string(names, section.name))
+        .collect::<anyhow::Result<Vec<_>>>()?;
+
+    // the null section at index 0 isn't a real section
+    let sections = headers.iter()
+        .zip(&section_names)
+        .skip(1)
+        .map(|(section, name)| (name.clone(), Section {
+            kind: section_kind(section.kind),
+            flags: section_flags(section.flags),
+            address: section.address,
+            offset: section.offset,
+            size: section.size,
+            alignment: section.alignment,
+        }))
+        .collect();
+
+    let mut symbols = Vec::new();
+    for table in headers.iter().filter(|section| section.kind == SHT_SYMTAB) {
+        let strings = headers.get(table.link as usize)
+            .context("symbol table links to a missing string table")?
+            .data(bytes)?;
+        let entry_size = match (table.entry_size, header.class) {
+            (0, ElfClass::Elf32) => 16,
+            (0, ElfClass::Elf64) => 24,
+            (size, _) => size,
+        };
+
+        // the first symbol is always null
+        for i in 1..table.size / entry_size {
+            let mut cursor = Cursor::new(bytes, table.offset + i * entry_size, &header)?;
+            let (name, value, size, info, other, index) = match header.class {
+                ElfClass::Elf32 => {
+                    let (name, value, size) = (cursor.u32()?, cursor.word()?, cursor.word()?);
+                    (name, value, size, cursor.u8()?, cursor.u8()?, cursor.u16()?)
+                }
+                ElfClass::Elf64 => {
+                    let (name, info, other, index) = (cursor.u32()?, cursor.u8()?, cursor.u8()?, cursor.u16()?);
+                    (name, cursor.word()?, cursor.word()?, info, other, index)
+                }
+            };
+
+            let section = match index {
+                SHN_UNDEF => "Undefined".to_string(),
+                SHN_ABS => "Absolute".to_string(),
+                SHN_COMMON => "Common".to_string(),
+                index if index >= SHN_LORESERVE => "Reserved".to_string(),
+                index => section_names.get(index as usize)
+                    .with_context(|| format!("symbol refers to missing section {}", index))?
+                    .clone(),
+            };
+            symbols.push((string(strings, name)?, Symbol {
+                address: value,
+                size,
+                kind: symbol_kind(info & 0xF),
+                binding: symbol_binding(info >> 4),
+                flags: symbol_flags(other),
+                section,
+            }));
+        }
+    }
+
+    Ok(Elf { header, segments, sections, symbols })
+}
+
+/// Parses the ELF header, and finds where the header tables are
+fn read_header(bytes: &[u8]) -> anyhow::Result<(ElfHeader, Tables)> {
+    ensure!(bytes.starts_with(MAGIC), "not an ELF file");
+    let ident = bytes.get(..16).context("truncated ELF header")?;
+    let class = match ident[4] {
+        1 => ElfClass::Elf32,
+        2 => ElfClass::Elf64,
+        class => bail!("unknown ELF class {}", class),
+    };
+    let big_endian = match ident[5] {
+        1 => false,
+        2 => true,
+        encoding => bail!("unknown ELF data encoding {}", encoding),
+    };
+
+    let mut header = ElfHeader {
+        class,
+        big_endian,
+        os_abi: ident[7],
+        abi_version: ident[8],
+        kind: 0,
+        machine: 0,
+        version: 0,
+        entry: 0,
+        flags: 0,
+    };
+    let mut cursor = Cursor::new(bytes, 16, &header)?;
+    header.kind = cursor.u16()?;
+    header.machine = cursor.u16()?;
+    header.version = cursor.u32()?;
+    header.entry = cursor.word()?;
+    let program_offset = cursor.word()?;
+    let section_offset = cursor.word()?;
+    header.flags = cursor.u32()?;
+    let _header_size = cursor.u16()?;
+    let tables = Tables {
+        program_offset,
+        program_header_size: cursor.u16()?,
+        program_count: cursor.u16()?,
+        section_offset,
+        section_header_size: cursor.u16()?,
+        section_count: cursor.u16()?,
+        names_index: cursor.u16()?,
+    };
+    Ok((header, tables))
+}
+
+/// Reads a program header, whose flags come before the offset in 64 bit files
+fn read_segment(cursor: &mut Cursor) -> anyhow::Result<Segment> {
+    let kind = cursor.u32()?;
+    let mut flags = 0;
+    if cursor.class == ElfClass::Elf64 {
+        flags = cursor.u32()?;
+    }
+    let offset = cursor.word()?;
+    let address = cursor.word()?;
+    let physical_address = cursor.word()?;
+    let file_size = cursor.word()?;
+    let memory_size = cursor.word()?;
+    if cursor.class == ElfClass::Elf32 {
+        flags = cursor.u32()?;
+    }
+    let alignment = cursor.word()?;
+    Ok(Segment {
+        kind: segment_kind(kind),
+        flags: segment_flags(flags),
+        offset,
+        address,
+        physical_address,
+        file_size,
+        memory_size,
+        alignment,
+    })
+}
+
+/// The null terminated string at `offset` in a string table
+fn string(table: &[u8], offset: u32) -> anyhow::Result<String> {
+    let bytes = table.get(offset as usize..)
+        .with_context(|| format!("string at {:#X} is past the end of its table", offset))?;
+    let end = bytes.iter().position(|byte| *byte == 0).unwrap_or(bytes.len());
+    Ok(String::from_utf8_lossy(&bytes[..end]).into_owned())
+}
+
+fn segment_kind(kind: u32) -> String {
+    let name = match kind {
+        0 => "PT_NULL",
+        1 => "PT_LOAD",
+        2 => "PT_DYNAMIC",
+        3 => "PT_INTERP",
+        4 => "PT_NOTE",
+        5 => "PT_SHLIB",
+        6 => "PT_PHDR",
+        7 => "PT_TLS",
+        0x6474_E550 => "PT_GNU_EH_FRAME",
+        0x6474_E551 => "PT_GNU_STACK",
+        0x6474_E552 => "PT_GNU_RELRO",
+        0x6474_E553 => "PT_GNU_PROPERTY",
+        kind => return format!("{:#X}", kind),
+    };
+    name.to_string()
+}
+
+fn segment_flags(flags: u32) -> Vec<String> {
+    const NAMES: [(u32, &str); 3] = [(0x1, "PF_X"), (0x2, "PF_W"), (0x4, "PF_R")];
+    NAMES.iter()
+        .filter(|(flag, _)| flags & flag != 0)
+        .map(|(_, name)| name.to_string())
+        .collect()
+}
+
+fn section_kind(kind: u32) -> String {
+    let name = match kind {
+        0 => "SHT_NULL",
+        1 => "SHT_PROGBITS",
+        2 => "SHT_SYMTAB",
+        3 => "SHT_STRTAB",
+        4 => "SHT_RELA",
+        5 => "SHT_HASH",
+        6 => "SHT_DYNAMIC",
+        7 => "SHT_NOTE",
+        8 => "SHT_NOBITS",
+        9 => "SHT_REL",
+        10 => "SHT_SHLIB",
+        11 => "SHT_DYNSYM",
+        14 => "SHT_INIT_ARRAY",
+        15 => "SHT_FINI_ARRAY",
+        16 => "SHT_PREINIT_ARRAY",
+        17 => "SHT_GROUP",
+        18 => "SHT_SYMTAB_SHNDX",
+        0x6FFF_FFF5 => "SHT_GNU_ATTRIBUTES",
+        0x6FFF_FFF6 => "SHT_GNU_HASH",
+        0x6FFF_FFFD => "SHT_GNU_verdef",
+        0x6FFF_FFFE => "SHT_GNU_verneed",
+        0x6FFF_FFFF => "SHT_GNU_versym",
+        kind => return format!("{:#X}", kind),
+    };
+    name.to_string()
+}
+
+fn section_flags(flags: u64) -> Vec<String> {
+    const NAMES: [(u64, &str); 12] = [
+        (0x1, "SHF_WRITE"),
+        (0x2, "SHF_ALLOC"),
+        (0x4, "SHF_EXECINSTR"),
+        (0x10, "SHF_MERGE"),
+        (0x20, "SHF_STRINGS"),
+        (0x40, "SHF_INFO_LINK"),
+        (0x80, "SHF_LINK_ORDER"),
+        (0x100, "SHF_OS_NONCONFORMING"),
+        (0x200, "SHF_GROUP"),
+        (0x400, "SHF_TLS"),
+        (0x800, "SHF_COMPRESSED"),
+        (0x8000_0000, "SHF_EXCLUDE"),
+    ];
+    NAMES.iter()
+        .filter(|(flag, _)| flags & flag != 0)
+        .map(|(_, name)| name.to_string())
+        .collect()
+}
+
+fn symbol_kind(kind: u8) -> String {
+    let name = match kind {
+        0 => "None",
+        1 => "Object",
+        2 => "Function",
+        3 => "Section",
+        4 => "File",
+        5 => "Common",
+        6 => "TLS",
+        10 => "GNU_IFunc",
+        kind => return format!("{:#X}", kind),
+    };
+    name.to_string()
+}
+
+fn symbol_binding(binding: u8) -> String {
+    let name = match binding {
+        0 => "Local",
+        1 => "Global",
+        2 => "Weak",
+        10 => "Unique",
+        binding => return format!("{:#X}", binding),
+    };
+    name.to_string()
+}
+
+/// The visibility of a symbol, which is left out when it's the default
+fn symbol_flags(other: u8) -> Vec<String> {
+    match other & 0x3 {
+        1 => vec!["STV_INTERNAL".to_string()],
+        2 => vec!["STV_HIDDEN".to_string()],
+        3 => vec!["STV_PROTECTED".to_string()],
+        _ => vec![],
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Builds an ELF file with a `.text` section holding `code` and a symbol `start` covering it
+    fn build(class: ElfClass, big_endian: bool, code: &[u8]) -> Vec<u8> {
+        let wide = class == ElfClass::Elf64;
+        let put = |out: &mut Vec<u8>, value: u64, size: usize| {
+            let bytes = if big_endian { value.to_be_bytes() } else { value.to_le_bytes() };
+            if big_endian {
+                out.extend_from_slice(&bytes[8 - size..]);
+            } else {
+                out.extend_from_slice(&bytes[..size]);
+            }
+        };
+        let word = if wide { 8 } else { 4 };
+        let header_size = if wide { 64 } else { 52 };
+
+        let names = b"\0.text\0.symtab\0.strtab\0.shstrtab\0";
+        let strings = b"\0start\0";
+        let mut symtab = vec![0; if wide { 24 } else { 16 }];
+        if wide {
+            put(&mut symtab, 1, 4);
+            symtab.extend([0x12, 0x2]);
+            put(&mut symtab, 1, 2);
+            put(&mut symtab, 0x1000, 8);
+            put(&mut symtab, code.len() as u64, 8);
+        } else {
+            put(&mut symtab, 1, 4);
+            put(&mut symtab, 0x1000, 4);
+            put(&mut symtab, code.len() as u64, 4);
+            symtab.extend([0x12, 0x2]);
+            put(&mut symtab, 1, 2);
+        }
+
+        let mut data = Vec::new();
+        let mut place = |bytes: &[u8]| {
+            let offset = header_size + data.len();
+            data.extend_from_slice(bytes);
+            offset as u64
+        };
+        let text = place(code);
+        let symbols = place(&symtab);
+        let string_table = place(strings);
+        let name_table = place(names);
+        let section_offset = (header_size + data.len()) as u64;
+        let program_offset = section_offset + 5 * if wide { 64 } else { 40 };
+
+        let mut out = Vec::new();
+        out.extend_from_slice(MAGIC);
+        out.extend([if wide { 2 } else { 1 }, if big_endian { 2 } else { 1 }, 1, 3, 0]);
+        out.resize(16, 0);
+        put(&mut out, 2, 2);
+        put(&mut out, 0x3E, 2);
+        put(&mut out, 1, 4);
+        put(&mut out, 0x1000, word);
+        put(&mut out, program_offset, word);
+        put(&mut out, section_offset, word);
+        put(&mut out, 0x42, 4);
+        put(&mut out, header_size as u64, 2);
+        put(&mut out, if wide { 56 } else { 32 }, 2);
+        put(&mut out, 1, 2);
+        put(&mut out, if wide { 64 } else { 40 }, 2);
+        put(&mut out, 5, 2);
+        put(&mut out, 4, 2);
+        out.extend(data);
+
+        // name, type, flags, address, offset, size, link, entry size
+        let sections = [
+            (0, 0, 0, 0, 0, 0, 0, 0),
+            (1, 1, 0x6, 0x1000, text, code.len() as u64, 0, 0),
+            (7, 2, 0, 0, symbols, symtab.len() as u64, 3, symtab.len() as u64 / 2),
+            (15, 3, 0, 0, string_table, strings.len() as u64, 0, 0),
+            (23, 3, 0, 0, name_table, names.len() as u64, 0, 0),
+        ];
+        for (name, kind, flags, address, offset, size, link, entry_size) in sections {
+            put(&mut out, name, 4);
+            put(&mut out, kind, 4);
+            put(&mut out, flags, word);
+            put(&mut out, address, word);
+            put(&mut out, offset, word);
+            put(&mut out, size, word);
+            put(&mut out, link, 4);
+            put(&mut out, 0, 4);
+            put(&mut out, 1, word);
+            put(&mut out, entry_size, word);
+        }
+
+        // one read and execute segment holding the code, with room for more after it
+        let (kind, flags, size) = (1, 0x5, code.len() as u64);
+        put(&mut out, kind, 4);
+        if wide {
+            put(&mut out, flags, 4);
+        }
+        for value in [text, 0x1000, 0x1000, size, size + 0x10] {
+            put(&mut out, value, word);
+        }
+        if !wide {
+            put(&mut out, flags, 4);
+        }
+        put(&mut out, 0x1000, word);
+        out
+    }
+
+    #[test]
+    fn test_parse_all_classes() {
+        for class in [ElfClass::Elf32, ElfClass::Elf64] {
+            for big_endian in [false, true] {
+                let bytes = build(class, big_endian, &[0x90; 7]);
+                let elf = parse(&bytes).unwrap();
+                assert_eq!(elf.header, ElfHeader {
+                    class,
+                    big_endian,
+                    os_abi: 3,
+                    abi_version: 0,
+                    kind: 2,
+                    machine: 0x3E,
+                    version: 1,
+                    entry: 0x1000,
+                    flags: 0x42,
+                });
+
+                let [segment] = elf.segments.as_slice() else { panic!("expected one segment") };
+                assert_eq!(segment.kind, "PT_LOAD");
+                assert_eq!(segment.flags, ["PF_X", "PF_R"]);
+                assert_eq!((segment.address, segment.file_size, segment.memory_size), (0x1000, 7, 0x17));
+                assert_eq!(&bytes[segment.offset as usize..][..7], [0x90; 7]);
+
+                let names = elf.sections.iter().map(|(name, _)| name.as_str()).collect::<Vec<_>>();
+                assert_eq!(names, [".text", ".symtab", ".strtab", ".shstrtab"]);
+                let text = &elf.sections[0].1;
+                assert_eq!((text.kind.as_str(), text.address, text.size), ("SHT_PROGBITS", 0x1000, 7));
+                assert_eq!(text.flags, ["SHF_ALLOC", "SHF_EXECINSTR"]);
+                assert_eq!(&bytes[text.offset as usize..][..7], [0x90; 7]);
+
+                let [(name, symbol)] = elf.symbols.as_slice() else { panic!("expected one symbol") };
+                assert_eq!(name, "start");
+                assert_eq!((symbol.address, symbol.size), (0x1000, 7));
+                assert_eq!((symbol.kind.as_str(), symbol.binding.as_str()), ("Function", "Global"));
+                assert_eq!(symbol.flags, ["STV_HIDDEN"]);
+                assert_eq!(symbol.section, ".text");
+            }
+        }
+    }
+
+    #[test]
+    fn test_parse_fib() {
+        let bytes = crate::util::read_file_as_bytes(concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fib/bin")).unwrap();
+        let elf = parse(&bytes).unwrap();
+        assert_eq!((elf.header.class, elf.header.big_endian, elf.header.machine), (ElfClass::Elf32, false, 3));
+        assert_eq!(elf.header.entry, 0x80495B0);
+
+        let (_, text) = elf.sections.iter().find(|(name, _)| name == ".text").unwrap();
+        assert_eq!((text.address, text.offset, text.size), (0x80490A0, 0x10A0, 0x6E037));
+        let loads = elf.segments.iter().filter(|segment| segment.kind == "PT_LOAD").collect::<Vec<_>>();
+        assert_eq!(loads.len(), 4);
+        assert_eq!((loads[3].address, loads[3].file_size, loads[3].memory_size), (0x80ECCA8, 0x3650, 0x652C));
+        assert_eq!(loads[3].flags, ["PF_W", "PF_R"]);
+
+        let (_, main) = elf.symbols.iter().find(|(name, _)| name == "main").unwrap();
+        assert_eq!((main.address, main.size, main.section.as_str()), (0x80497A0, 50, ".text"));
+    }
+
+    #[test]
+    fn test_not_elf() {
+        assert!(parse(b"MZ\x90\x00").is_err());
+        assert!(parse(b"\x7FELF\x01\x01").is_err());
+    }
+}
@@ -0,0 +1,205 @@
+//!
+//! Looking up which symbol or section an address falls in.
+//!
+//! Symbols and sections are intervals sorted by start address. Intervals can nest or overlap
+//! (an alias covering part of a function, say), so each entry also records the furthest end of
+//! any interval up to it, which tells a lookup walking backwards when it can stop.
+
+use std::ops::Range;
+use crate::binary::{Section, Symbol};
+
+#[derive(Debug, Clone)]
+struct Entry {
+    range: Range<u64>,
+    name: String,
+    /// lower is preferred when several entries cover an address equally well
+    rank: u8,
+    /// the largest `range.end` of this entry and every entry before it
+    max_end: u64,
+}
+
+/// An interval index over the symbols and allocated sections of a binary
+#[derive(Debug, Clone, Default)]
+pub struct SymbolIndex {
+    symbols: Vec<Entry>,
+    sections: Vec<Entry>,
+}
+
+impl SymbolIndex {
+    /// Indexes the symbols that name code or data, and the sections that are loaded into memory
+    pub fn new<'a>(
+        symbols: impl IntoIterator<Item = (&'a str, &'a Symbol)>,
+        sections: impl IntoIterator<Item = (&'a str, &'a Section)>,
+    ) -> Self {
+        let symbols = symbols.into_iter()
+            .filter(|(name, symbol)| {
+                !name.is_empty()
+                    && !matches!(symbol.kind.as_str(), "Section" | "File")
+                    && !matches!(symbol.section.as_str(), "Undefined" | "Absolute" | "Common")
+            })
+            .map(|(name, symbol)| {
+                let rank = match (symbol.kind.as_str(), symbol.binding.as_str()) {
+                    ("Function", "Global") => 0,
+                    ("Function", _) => 1,
+                    (_, "Global") => 2,
+                    _ => 3,
+                };
+                (name, symbol.address..symbol.address.saturating_add(symbol.size), rank)
+            });
+        let sections = sections.into_iter()
+            .filter(|(_, section)| section.address != 0 && section.allocated())
+            .map(|(name, section)| (name, section.address..section.address.saturating_add(section.size), 0));
+
+        Self {
+            symbols: entries(symbols),
+            sections: entries(sections),
+        }
+    }
+
+    /// The symbol whose extent contains `address` and its start address. The smallest symbol
+    /// wins when they nest.
+    pub fn containing(&self, address: u64) -> Option<(&str, u64)> {
+        containing(&self.symbols, address).map(|entry| (entry.name.as_str(), entry.range.start))
+    }
+
+    /// The section containing `address` and its start address
+    pub fn section(&self, address: u64) -> Option<(&str, u64)> {
+        containing(&self.sections, address).map(|entry| (entry.name.as_str(), entry.range.start))
+    }
+
+    /// Where a symbol at `address` that doesn't say how big it is ends: at the next symbol after
+    /// it in the same section, or at the end of the section
+    pub fn implied_end(&self, address: u64) -> Option<u64> {
+        let section = containing(&self.sections, address)?;
+        let after = self.symbols.partition_point(|entry| entry.range.start <= address);
+        let next = self.symbols.get(after).map(|entry| entry.range.start);
+        Some(next.map_or(section.range.end, |next| next.min(section.range.end)))
+    }
+
+    /// The symbol that best describes `address`, and how far past its start the address is. That's
+    /// the containing symbol if there is one, otherwise the closest symbol before it in the same
+    /// section, which covers labels that have no size.
+    pub fn nearest(&self, address: u64) -> Option<(&str, u64)> {
+        if let Some((name, start)) = self.containing(address) {
+            return Some((name, address - start));
+        }
+
+        let (_, section_start) = self.section(address)?;
+        let before = self.symbols.partition_point(|entry| entry.range.start <= address);
+        let start = self.symbols[..before].last()?.range.start;
+        if start < section_start {
+            return None;
+        }
+        // among symbols starting at the same address, the best ranked one comes first
+        let first = self.symbols[..before].partition_point(|entry| entry.range.start < start);
+        let entry = &self.symbols[first];
+        Some((entry.name.as_str(), address - start))
+    }
+
+    /// Formats an address as `symbol+0x1c`, falling back on `section+0x1c` and then on the bare
+    /// address
+    pub fn symbolize(&self, address: u64) -> String {
+        self.describe(address).unwrap_or_else(|| format!("{:#X}", address))
+    }
+
+    /// Formats an address relative to a symbol or section, if it's in one
+    pub fn describe(&self, address: u64) -> Option<String> {
+        let (name, offset) = self.nearest(address)
+            .or_else(|| self.section(address).map(|(name, start)| (name, address - start)))?;
+        Some(if offset == 0 {
+            name.to_string()
+        } else {
+            format!("{}+{:#x}", name, offset)
+        })
+    }
+}
+
+/// Sorts intervals by start address and rank, and records the running maximum of their ends
+fn entries<'a>(intervals: impl Iterator<Item = (&'a str, Range<u64>, u8)>) -> Vec<Entry> {
+    let mut entries = intervals
+        .map(|(name, range, rank)| Entry { range, name: name.to_string(), rank, max_end: 0 })
+        .collect::<Vec<_>>();
+    entries.sort_by(|a, b| {
+        (a.range.start, a.rank, &a.name).cmp(&(b.range.start, b.rank, &b.name))
+    });
+    let mut max_end = 0;
+    for entry in &mut entries {
+        max_end = max_end.max(entry.range.end);
+        entry.max_end = max_end;
+    }
+    entries
+}
+
+/// The smallest, then best ranked, entry containing `address`
+fn containing(entries: &[Entry], address: u64) -> Option<&Entry> {
+    let before = entries.partition_point(|entry| entry.range.start <= address);
+    entries[..before].iter()
+        .rev()
+        .take_while(|entry| entry.max_end > address)
+        .filter(|entry| entry.range.contains(&address))
+        .min_by_key(|entry| (entry.range.end - entry.range.start, entry.rank, entry.range.start))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn symbol(address: u64, size: u64, kind: &str, binding: &str) -> Symbol {
+        Symbol {
+            address,
+            size,
+            kind: kind.to_string(),
+            binding: binding.to_string(),
+            flags: vec![],
+            section: ".text".to_string(),
+        }
+    }
+
+    fn text() -> Section {
+        Section {
+            kind: "SHT_PROGBITS".to_string(),
+            flags: vec!["SHF_ALLOC".to_string(), "SHF_EXECINSTR".to_string()],
+            address: 0x1000,
+            offset: 0,
+            size: 0x1000,
+            alignment: 16,
+        }
+    }
+
+    #[test]
+    fn test_lookup() {
+        let symbols = [
+            ("outer", symbol(0x1000, 0x100, "Function", "Global")),
+            ("inner", symbol(0x1020, 0x10, "Function", "Local")),
+            ("alias", symbol(0x1000, 0x100, "Function", "Weak")),
+            ("label", symbol(0x1200, 0, "None", "Local")),
+            ("data", symbol(0x3000, 4, "Object", "Global")),
+            ("file.c", symbol(0, 0, "File", "Local")),
+        ];
+        let sections = [(".text", text())];
+        let index = SymbolIndex::new(
+            symbols.iter().map(|(name, symbol)| (*name, symbol)),
+            sections.iter().map(|(name, section)| (*name, section)),
+        );
+
+        assert_eq!(index.containing(0x1000), Some(("outer", 0x1000)));
+        assert_eq!(index.containing(0x1024), Some(("inner", 0x1020)));
+        // the end of a nested symbol doesn't hide the one around it
+        assert_eq!(index.containing(0x1030), Some(("outer", 0x1000)));
+        assert_eq!(index.containing(0x1100), None);
+
+        assert_eq!(index.symbolize(0x1000), "outer");
+        assert_eq!(index.symbolize(0x101C), "outer+0x1c");
+        // labels have no size, so they're only found as the closest symbol before an address
+        assert_eq!(index.symbolize(0x1204), "label+0x4");
+        assert_eq!(index.symbolize(0x3002), "data+0x2");
+        assert_eq!(index.symbolize(0x800), "0x800");
+        assert_eq!(index.section(0x1FFF), Some((".text", 0x1000)));
+        assert_eq!(index.section(0x2000), None);
+
+        // symbols without a size end at the next symbol, or at the end of their section
+        assert_eq!(index.implied_end(0x1020), Some(0x1200));
+        assert_eq!(index.implied_end(0x1200), Some(0x2000));
+        assert_eq!(index.implied_end(0x3000), None);
+    }
+}
@@ -1,23 +1,69 @@
-///!
-///! This module contains the `Binary` struct and its associated types and functions.
-///! The `Binary` struct is used to represent a binary file and its associated metadata.
-///! The `Section` struct is used to represent a section within a binary file.
-///! The `Symbol` struct is used to represent a symbol within a binary file.
-///! The `x86_32` function is used to create a `Binary` struct from a given file path.
+//!
+//! This module contains the `Binary` struct and its associated types and functions.
+//! The `Binary` struct is used to represent a binary file and its associated metadata.
+//! The `Section` struct is used to represent a section within a binary file.
+//! The `Symbol` struct is used to represent a symbol within a binary file.
+//! ELF files are parsed in-process by the `elf` module and PE images by the `pe` module;
+//! `llvm-readobj` is only used to cross-check ELF files, see `Binary::cross_check`.
 
 use std::collections::{LinkedList};
 use std::path::{Path};
-use anyhow::{Context};
+use anyhow::{bail, ensure, Context};
 use hashbrown::HashMap;
 use crate::util;
 
+mod arch;
+mod elf;
+mod index;
+mod pe;
 mod readobj;
 
+pub use arch::Architecture;
+pub use elf::{ElfClass, ElfHeader};
+pub use index::SymbolIndex;
+pub use pe::PeHeader;
+
 #[derive(Debug)]
 pub struct Binary {
     pub bytes: Vec<u8>,
+    /// the ELF or PE header
+    pub header: Header,
+    /// the program headers, or for PE images the headers and sections, in file order
+    pub segments: Vec<Segment>,
     pub sections: HashMap<String, Section>,
     pub symbols: HashMap<String, LinkedList<Symbol>>,
+    /// the symbols and sections by address
+    pub index: SymbolIndex,
+    /// the functions imported from other libraries, which only PE images list
+    pub imports: Vec<Import>,
+}
+
+/// The header of a binary, which says what format it's in
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Header {
+    Elf(ElfHeader),
+    Pe(PeHeader),
+}
+
+/// A program header, which describes how part of the file is loaded into memory. PE images have
+/// one for their headers and one for each section.
+#[derive(Debug)]
+pub struct Segment {
+    /// the segment type
+    pub kind: String,
+    /// the access permissions of the segment
+    pub flags: Vec<String>,
+    /// the offset from the start of the file
+    pub offset: u64,
+    /// the virtual address the segment is loaded at
+    pub address: u64,
+    pub physical_address: u64,
+    /// the number of bytes in the file
+    pub file_size: u64,
+    /// the number of bytes in memory, past the file size they're zero
+    pub memory_size: u64,
+    /// the alignment of the segment
+    pub alignment: u64,
 }
 
 #[derive(Debug)]
@@ -41,48 +87,246 @@ pub struct Symbol {
     pub address: u64,
     pub size: u64,
     pub kind: String,
+    /// whether the symbol is local, global or weak
+    pub binding: String,
     pub flags: Vec<String>,
     pub section: String,
 }
 
+/// A function imported from a DLL, whose address the loader writes into the import address table
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Import {
+    /// the name of the DLL, like `KERNEL32.dll`
+    pub library: String,
+    /// the name of the function, if it's imported by name
+    pub name: Option<String>,
+    /// the ordinal of the function, if it's imported by ordinal
+    pub ordinal: Option<u16>,
+    /// where the function probably is in the DLL's export name table
+    pub hint: u16,
+    /// the address of the slot in the import address table that holds the function's address
+    pub address: u64,
+}
+
+impl Segment {
+    /// Whether the segment is mapped into memory when the file is loaded
+    pub fn loaded(&self) -> bool {
+        matches!(self.kind.as_str(), "PT_LOAD" | "PE_HEADERS" | "PE_SECTION")
+    }
+}
+
+impl Section {
+    /// Whether the section takes up memory when the file is loaded
+    pub fn allocated(&self) -> bool {
+        self.has_flag("SHF_ALLOC") || self.has_flag("IMAGE_SCN_MEM_READ")
+    }
+
+    /// Whether the section holds code
+    pub fn executable(&self) -> bool {
+        self.has_flag("SHF_EXECINSTR") || self.has_flag("IMAGE_SCN_MEM_EXECUTE")
+    }
+
+    /// Whether the section can be written to once it's loaded
+    pub fn writable(&self) -> bool {
+        self.has_flag("SHF_WRITE") || self.has_flag("IMAGE_SCN_MEM_WRITE")
+    }
+
+    fn has_flag(&self, name: &str) -> bool {
+        self.flags.iter().any(|flag| flag == name)
+    }
+}
+
 impl Binary {
     pub fn new(path: impl AsRef<Path>) -> anyhow::Result<Self> {
         let path = path.as_ref();
         let bytes = util::read_file_as_bytes(path)?;
+        Self::parse(bytes).with_context(|| format!("unable to parse {}", path.display()))
+    }
 
-        let readobj = readobj::read(path)
-            .context("unable to run readobj on binary!")?;
+    /// Parses the bytes of an ELF file or PE image
+    pub fn parse(bytes: Vec<u8>) -> anyhow::Result<Self> {
+        let (header, segments, parsed_sections, parsed_symbols, imports) = if pe::is_pe(&bytes) {
+            let pe = pe::parse(&bytes)?;
+            (Header::Pe(pe.header), pe.segments, pe.sections, pe.symbols, pe.imports)
+        } else {
+            let elf = elf::parse(&bytes)?;
+            (Header::Elf(elf.header), elf.segments, elf.sections, elf.symbols, Vec::new())
+        };
 
         let mut sections = HashMap::new();
-        for readobj::SectionItem { section } in readobj.sections {
-            assert!(!sections.contains_key(&section.name.name));
-            sections.insert(section.name.name, Section {
-                kind: section.r#type.name,
-                flags: section.flags.flags.into_iter().map(|flag| flag.name).collect(),
-                address: section.address,
-                offset: section.offset,
-                size: section.size,
-                alignment: section.address_alignment,
-            });
+        for (name, section) in parsed_sections {
+            // object files can have several sections with the same name, the first one wins
+            sections.entry(name).or_insert(section);
         }
 
         let mut symbols = HashMap::<_, LinkedList<_>>::new();
-        for readobj::SymbolItem { symbol } in readobj.symbols {
-            symbols.entry(symbol.name.name)
+        for (name, symbol) in parsed_symbols {
+            symbols.entry(name)
                 .or_default()
-                .push_back(Symbol {
-                    address: symbol.value,
-                    size: symbol.size,
-                    kind: symbol.r#type.name,
-                    flags: symbol.other.flags.into_iter().map(|flag| flag.name).collect(),
-                    section: symbol.section.name,
-                });
+                .push_back(symbol);
         }
 
-        Ok(Self {
+        let mut binary = Self {
             bytes,
+            header,
+            segments,
             sections,
             symbols,
-        })
+            index: SymbolIndex::default(),
+            imports,
+        };
+        binary.reindex();
+        Ok(binary)
+    }
+
+    /// Rebuilds the index of symbols and sections by address
+    fn reindex(&mut self) {
+        self.index = SymbolIndex::new(
+            self.symbols.iter().flat_map(|(name, symbols)| symbols.iter().map(move |symbol| (name.as_str(), symbol))),
+            self.sections.iter().map(|(name, section)| (name.as_str(), section)),
+        );
     }
-}
\ No newline at end of file
+
+    /// The address execution starts at, if the binary has one
+    pub fn entry(&self) -> Option<u64> {
+        let entry = match &self.header {
+            Header::Elf(header) => header.entry,
+            Header::Pe(header) if header.entry == 0 => return None,
+            Header::Pe(header) => header.image_base + u64::from(header.entry),
+        };
+        Some(entry).filter(|entry| *entry != 0)
+    }
+
+    /// The offset in the file of the byte loaded at `address`, if it's loaded from the file
+    pub fn file_offset(&self, address: u64) -> Option<u64> {
+        file_offset(&self.segments, address)
+    }
+
+    /// Moves a PE image from its image base to `base`, applying its base relocations to the bytes
+    /// of the file and shifting every address
+    pub fn rebase(&mut self, base: u64) -> anyhow::Result<()> {
+        let Header::Pe(header) = &self.header else {
+            bail!("only PE images can be rebased");
+        };
+        let image_base = header.image_base;
+        let delta = base.wrapping_sub(image_base);
+        if delta == 0 {
+            return Ok(());
+        }
+        ensure!(pe::relocatable(header), "the image has no relocations, so it can only be loaded at {:#X}", image_base);
+
+        let segments = &self.segments;
+        let offset = |address: u32| file_offset(segments, image_base + u64::from(address));
+        let relocations = pe::relocations(&self.bytes, header, offset)?;
+        pe::relocate(&mut self.bytes, &relocations, delta, offset)?;
+
+        let shift = |address: &mut u64| *address = address.wrapping_add(delta);
+        if let Header::Pe(header) = &mut self.header {
+            header.image_base = base;
+        }
+        for segment in &mut self.segments {
+            shift(&mut segment.address);
+            shift(&mut segment.physical_address);
+        }
+        for section in self.sections.values_mut() {
+            shift(&mut section.address);
+        }
+        for symbol in self.symbols.values_mut().flatten() {
+            shift(&mut symbol.address);
+        }
+        for import in &mut self.imports {
+            shift(&mut import.address);
+        }
+        self.reindex();
+        Ok(())
+    }
+
+    /// Formats an address as `symbol+0x1c`, see `SymbolIndex::symbolize`
+    pub fn symbolize(&self, address: u64) -> String {
+        self.index.symbolize(address)
+    }
+
+    /// The architecture the binary was built for, from its header
+    pub fn architecture(&self) -> anyhow::Result<Architecture> {
+        match &self.header {
+            Header::Elf(header) => {
+                let attributes = self.sections.get(".ARM.attributes").and_then(|section| {
+                    let start = usize::try_from(section.offset).ok()?;
+                    self.bytes.get(start..start.checked_add(usize::try_from(section.size).ok()?)?)
+                });
+                Architecture::from_header(header, attributes)
+            }
+            Header::Pe(header) => Architecture::from_pe_header(header),
+        }
+    }
+
+    /// Compares the sections and symbols of an ELF file with what `llvm-readobj` finds in the file
+    /// at `path`, which needs a version of LLVM with JSON output (17 or later)
+    pub fn cross_check(&self, path: impl AsRef<Path>) -> anyhow::Result<()> {
+        ensure!(matches!(self.header, Header::Elf(_)), "only ELF files can be cross-checked with readobj");
+        let readobj = readobj::read(path)
+            .context("unable to run readobj on binary!")?;
+
+        let mut mismatches = Vec::new();
+        for readobj::SectionItem { section } in readobj.sections {
+            let name = section.name.name;
+            if name.is_empty() {
+                continue;
+            }
+            let Some(ours) = self.sections.get(&name) else {
+                mismatches.push(format!("missing section {}", name));
+                continue;
+            };
+            let flags = section.flags.flags.into_iter().map(|flag| flag.name).collect::<Vec<_>>();
+            let same = ours.kind == section.r#type.name
+                && ours.address == section.address
+                && ours.offset == section.offset
+                && ours.size == section.size
+                && ours.alignment == section.address_alignment
+                && same_flags(&ours.flags, &flags);
+            if !same {
+                mismatches.push(format!("section {} differs", name));
+            }
+        }
+
+        for readobj::SymbolItem { symbol } in readobj.symbols {
+            let name = symbol.name.name;
+            if name.is_empty() {
+                continue;
+            }
+            let found = self.symbols.get(&name).is_some_and(|symbols| {
+                symbols.iter().any(|ours| {
+                    ours.address == symbol.value
+                        && ours.size == symbol.size
+                        && ours.kind == symbol.r#type.name
+                        && ours.binding == symbol.binding.name
+                        && ours.section == symbol.section.name
+                })
+            });
+            if !found {
+                mismatches.push(format!("symbol {} at {:#X} differs or is missing", name, symbol.value));
+            }
+        }
+
+        ensure!(
+            mismatches.is_empty(),
+            "{} mismatches with readobj:\n{}",
+            mismatches.len(),
+            mismatches.iter().take(20).cloned().collect::<Vec<_>>().join("\n"),
+        );
+        Ok(())
+    }
+}
+
+/// The offset in the file of the byte loaded at `address` by one of `segments`
+fn file_offset(segments: &[Segment], address: u64) -> Option<u64> {
+    segments.iter()
+        .filter(|segment| segment.loaded())
+        .find(|segment| address >= segment.address && address - segment.address < segment.file_size)
+        .map(|segment| segment.offset + (address - segment.address))
+}
+
+/// Whether two lists of flags hold the same flags, in any order
+fn same_flags(a: &[String], b: &[String]) -> bool {
+    a.len() == b.len() && a.iter().all(|flag| b.contains(flag))
+}
@@ -0,0 +1,680 @@
+//!
+//! A parser for PE images, the executables and DLLs of Windows, covering the headers, section
+//! table, export table, import table and base relocations of 32 bit (PE32) and 64 bit (PE32+)
+//! files.
+//!
+//! Section characteristics are named the way `llvm-readobj` names them. Each section is also
+//! described by a segment, so images are loaded the same way as ELF files: the headers and every
+//! section are mapped at the image base plus their relative address.
+
+use anyhow::{bail, ensure, Context};
+use crate::binary::{Import, Section, Segment, Symbol};
+
+/// the magic bytes of the DOS header every PE file starts with
+const DOS_MAGIC: &[u8; 2] = b"MZ";
+/// the signature before the COFF header
+const PE_MAGIC: &[u8; 4] = b"PE\0\0";
+
+const PE32_MAGIC: u16 = 0x10B;
+const PE32_PLUS_MAGIC: u16 = 0x20B;
+
+/// `IMAGE_FILE_RELOCS_STRIPPED`, set when an image can only be loaded at its image base
+const RELOCS_STRIPPED: u16 = 0x0001;
+
+const DIRECTORY_EXPORT: usize = 0;
+const DIRECTORY_IMPORT: usize = 1;
+const DIRECTORY_BASERELOC: usize = 5;
+
+const SCN_CNT_CODE: u32 = 0x20;
+const SCN_CNT_INITIALIZED_DATA: u32 = 0x40;
+const SCN_CNT_UNINITIALIZED_DATA: u32 = 0x80;
+const SCN_MEM_EXECUTE: u32 = 0x2000_0000;
+const SCN_MEM_READ: u32 = 0x4000_0000;
+const SCN_MEM_WRITE: u32 = 0x8000_0000;
+
+const REL_BASED_ABSOLUTE: u16 = 0;
+const REL_BASED_HIGH: u16 = 1;
+const REL_BASED_LOW: u16 = 2;
+const REL_BASED_HIGHLOW: u16 = 3;
+const REL_BASED_DIR64: u16 = 10;
+
+/// The fields of the COFF and optional headers that describe the image
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PeHeader {
+    /// `Machine`, the architecture of the image
+    pub machine: u16,
+    /// whether the optional header is PE32+, with 64 bit addresses
+    pub wide: bool,
+    /// `Characteristics` from the COFF header, like whether the image is a DLL
+    pub characteristics: u16,
+    /// `TimeDateStamp`, when the linker made the image
+    pub timestamp: u32,
+    /// the address the image is loaded at, which its absolute addresses assume
+    pub image_base: u64,
+    /// `AddressOfEntryPoint`, relative to the image base, or 0 if there's no entry point
+    pub entry: u32,
+    pub section_alignment: u32,
+    pub file_alignment: u32,
+    /// the size of the image in memory, from the image base
+    pub size_of_image: u32,
+    /// the size of the headers in the file, which are mapped at the image base
+    pub size_of_headers: u32,
+    /// `Subsystem`, like a console or GUI program
+    pub subsystem: u16,
+    /// `DllCharacteristics`, like whether the image can be moved
+    pub dll_characteristics: u16,
+    /// the address and size of each data directory, relative to the image base
+    pub directories: Vec<(u32, u32)>,
+}
+
+impl PeHeader {
+    /// The data directory at `index`, if the image has one
+    fn directory(&self, index: usize) -> Option<(u32, u32)> {
+        self.directories.get(index).copied().filter(|(address, size)| *address != 0 && *size != 0)
+    }
+}
+
+/// The contents of a PE image, with addresses at its image base
+#[derive(Debug)]
+pub struct Pe {
+    pub header: PeHeader,
+    pub segments: Vec<Segment>,
+    pub sections: Vec<(String, Section)>,
+    pub symbols: Vec<(String, Symbol)>,
+    pub imports: Vec<Import>,
+}
+
+/// A relocation to apply when the image is moved away from its image base
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Relocation {
+    /// the address of the value to fix up, relative to the image base
+    pub address: u32,
+    /// `IMAGE_REL_BASED_*`, how much of the value to fix up
+    pub kind: u16,
+}
+
+/// Reads little endian fields one after another
+struct Cursor<'a> {
+    bytes: &'a [u8],
+    offset: usize,
+}
+
+impl<'a> Cursor<'a> {
+    fn new(bytes: &'a [u8], offset: u64) -> anyhow::Result<Self> {
+        let offset = usize::try_from(offset).ok()
+            .filter(|offset| *offset <= bytes.len())
+            .with_context(|| format!("offset {:#X} is past the end of the file", offset))?;
+        Ok(Self { bytes, offset })
+    }
+
+    fn take<const N: usize>(&mut self) -> anyhow::Result<[u8; N]> {
+        let bytes = self.bytes.get(self.offset..self.offset + N)
+            .context("unexpected end of file")?;
+        self.offset += N;
+        Ok(bytes.try_into().unwrap())
+    }
+
+    fn skip(&mut self, count: usize) {
+        self.offset += count;
+    }
+
+    fn u16(&mut self) -> anyhow::Result<u16> {
+        self.take().map(u16::from_le_bytes)
+    }
+
+    fn u32(&mut self) -> anyhow::Result<u32> {
+        self.take().map(u32::from_le_bytes)
+    }
+
+    fn u64(&mut self) -> anyhow::Result<u64> {
+        self.take().map(u64::from_le_bytes)
+    }
+}
+
+/// A section header, with the fields needed to find things in the file
+struct SectionHeader {
+    name: String,
+    virtual_size: u32,
+    virtual_address: u32,
+    raw_size: u32,
+    raw_offset: u32,
+    characteristics: u32,
+}
+
+impl SectionHeader {
+    /// The number of bytes the section takes up in memory. Some linkers leave the virtual size
+    /// empty, in which case it's the size in the file.
+    fn memory_size(&self) -> u32 {
+        if self.virtual_size == 0 { self.raw_size } else { self.virtual_size }
+    }
+
+    /// The number of bytes of the section that come from the file, past which it's zeroed
+    fn file_size(&self) -> u32 {
+        self.raw_size.min(self.memory_size())
+    }
+}
+
+/// Maps addresses relative to the image base to offsets in the file
+struct Image<'a> {
+    bytes: &'a [u8],
+    sections: &'a [SectionHeader],
+    size_of_headers: u32,
+}
+
+impl<'a> Image<'a> {
+    /// The file offset of `address`, if it's backed by the file
+    fn offset(&self, address: u32) -> Option<u64> {
+        if address < self.size_of_headers {
+            return Some(u64::from(address));
+        }
+        self.sections.iter()
+            .find(|section| {
+                address >= section.virtual_address && address - section.virtual_address < section.file_size()
+            })
+            .map(|section| u64::from(section.raw_offset) + u64::from(address - section.virtual_address))
+    }
+
+    fn cursor(&self, address: u32) -> anyhow::Result<Cursor<'a>> {
+        let offset = self.offset(address)
+            .with_context(|| format!("address {:#X} isn't backed by the file", address))?;
+        Cursor::new(self.bytes, offset)
+    }
+
+    /// The null terminated string at `address`
+    fn string(&self, address: u32) -> anyhow::Result<String> {
+        let cursor = self.cursor(address)?;
+        let bytes = &cursor.bytes[cursor.offset..];
+        let end = bytes.iter().position(|byte| *byte == 0).unwrap_or(bytes.len());
+        Ok(String::from_utf8_lossy(&bytes[..end]).into_owned())
+    }
+}
+
+/// Whether `bytes` start with the DOS header every PE file has
+pub fn is_pe(bytes: &[u8]) -> bool {
+    bytes.starts_with(DOS_MAGIC)
+}
+
+/// The offset of the PE signature, from `e_lfanew` in the DOS header
+fn pe_offset(bytes: &[u8]) -> anyhow::Result<u64> {
+    Cursor::new(bytes, 0x3C)?.u32().map(u64::from)
+}
+
+/// Parses the headers, sections, exports and imports of a PE image
+pub fn parse(bytes: &[u8]) -> anyhow::Result<Pe> {
+    let (header, sections) = read_headers(bytes)?;
+    let image = Image { bytes, sections: &sections, size_of_headers: header.size_of_headers };
+    let base = header.image_base;
+
+    let mut segments = vec![Segment {
+        kind: "PE_HEADERS".to_string(),
+        flags: vec!["IMAGE_SCN_MEM_READ".to_string()],
+        offset: 0,
+        address: base,
+        physical_address: base,
+        file_size: u64::from(header.size_of_headers).min(bytes.len() as u64),
+        memory_size: u64::from(header.size_of_headers),
+        alignment: u64::from(header.section_alignment),
+    }];
+    for section in &sections {
+        let address = base + u64::from(section.virtual_address);
+        let flags = section_flags(section.characteristics & (SCN_MEM_EXECUTE | SCN_MEM_READ | SCN_MEM_WRITE));
+        segments.push(Segment {
+            kind: "PE_SECTION".to_string(),
+            flags,
+            offset: u64::from(section.raw_offset),
+            address,
+            physical_address: address,
+            file_size: u64::from(section.file_size()),
+            memory_size: u64::from(section.memory_size()),
+            alignment: u64::from(header.section_alignment),
+        });
+    }
+
+    let symbols = read_exports(&image, &header)?;
+    let imports = read_imports(&image, &header)?;
+    let sections = sections.iter()
+        .map(|section| (section.name.clone(), Section {
+            kind: section_kind(section.characteristics),
+            flags: section_flags(section.characteristics),
+            address: base + u64::from(section.virtual_address),
+            offset: u64::from(section.raw_offset),
+            size: u64::from(section.memory_size()),
+            alignment: u64::from(header.section_alignment),
+        }))
+        .collect();
+
+    Ok(Pe { header, segments, sections, symbols, imports })
+}
+
+/// Parses the COFF header, the optional header and the section table
+fn read_headers(bytes: &[u8]) -> anyhow::Result<(PeHeader, Vec<SectionHeader>)> {
+    ensure!(bytes.starts_with(DOS_MAGIC), "not a PE file");
+    let mut cursor = Cursor::new(bytes, pe_offset(bytes)?)?;
+    ensure!(&cursor.take::<4>()? == PE_MAGIC, "not a PE file, only a DOS executable");
+
+    let machine = cursor.u16()?;
+    let section_count = cursor.u16()?;
+    let timestamp = cursor.u32()?;
+    let symbol_table = cursor.u32()?;
+    let symbol_count = cursor.u32()?;
+    let optional_size = cursor.u16()?;
+    let characteristics = cursor.u16()?;
+    let optional_offset = cursor.offset as u64;
+
+    let wide = match cursor.u16()? {
+        PE32_MAGIC => false,
+        PE32_PLUS_MAGIC => true,
+        magic => bail!("unknown optional header magic {:#X}", magic),
+    };
+    // linker version, section sizes
+    cursor.skip(2 + 4 * 3);
+    let entry = cursor.u32()?;
+    let _base_of_code = cursor.u32()?;
+    let image_base = if wide {
+        cursor.u64()?
+    } else {
+        let _base_of_data = cursor.u32()?;
+        u64::from(cursor.u32()?)
+    };
+    let section_alignment = cursor.u32()?;
+    let file_alignment = cursor.u32()?;
+    // operating system, image and subsystem versions, and Win32VersionValue
+    cursor.skip(2 * 6 + 4);
+    let size_of_image = cursor.u32()?;
+    let size_of_headers = cursor.u32()?;
+    let _checksum = cursor.u32()?;
+    let subsystem = cursor.u16()?;
+    let dll_characteristics = cursor.u16()?;
+    // stack and heap reserve and commit sizes, and LoaderFlags
+    cursor.skip(if wide { 8 * 4 } else { 4 * 4 } + 4);
+    let directory_count = cursor.u32()?.min(16);
+    let directories = (0..directory_count)
+        .map(|_| Ok((cursor.u32()?, cursor.u32()?)))
+        .collect::<anyhow::Result<Vec<_>>>()?;
+
+    let header = PeHeader {
+        machine,
+        wide,
+        characteristics,
+        timestamp,
+        image_base,
+        entry,
+        section_alignment,
+        file_alignment,
+        size_of_image,
+        size_of_headers,
+        subsystem,
+        dll_characteristics,
+        directories,
+    };
+
+    // long section names are kept in the COFF string table, after the symbols
+    let strings = u64::from(symbol_table) + u64::from(symbol_count) * 18;
+    let mut cursor = Cursor::new(bytes, optional_offset + u64::from(optional_size))?;
+    let mut sections = Vec::new();
+    for _ in 0..section_count {
+        let name = cursor.take::<8>()?;
+        let end = name.iter().position(|byte| *byte == 0).unwrap_or(name.len());
+        let mut name = String::from_utf8_lossy(&name[..end]).into_owned();
+        if let Some(offset) = name.strip_prefix('/').and_then(|offset| offset.parse::<u64>().ok()) {
+            if symbol_table != 0 {
+                name = string(bytes, strings + offset)?;
+            }
+        }
+
+        let virtual_size = cursor.u32()?;
+        let virtual_address = cursor.u32()?;
+        let raw_size = cursor.u32()?;
+        let raw_offset = cursor.u32()?;
+        // relocations and line numbers, which images don't have
+        cursor.skip(4 * 2 + 2 * 2);
+        let characteristics = cursor.u32()?;
+        sections.push(SectionHeader {
+            name,
+            virtual_size,
+            virtual_address,
+            // uninitialized sections have nothing in the file, whatever their raw size says
+            raw_size: if raw_offset == 0 { 0 } else { raw_size },
+            raw_offset,
+            characteristics,
+        });
+    }
+    Ok((header, sections))
+}
+
+/// The exported functions and data, as symbols. Forwarded exports live in another DLL, so they
+/// have no address here and are left out.
+fn read_exports(image: &Image, header: &PeHeader) -> anyhow::Result<Vec<(String, Symbol)>> {
+    let Some((start, size)) = header.directory(DIRECTORY_EXPORT) else {
+        return Ok(Vec::new());
+    };
+    let mut cursor = image.cursor(start)?;
+    // characteristics, timestamp, version and name
+    cursor.skip(4 * 2 + 2 * 2 + 4);
+    let ordinal_base = cursor.u32()?;
+    let function_count = cursor.u32()?;
+    let name_count = cursor.u32()?;
+    let functions = cursor.u32()?;
+    let names = cursor.u32()?;
+    let ordinals = cursor.u32()?;
+
+    // a function can be exported under several names, or only by ordinal
+    let mut function_names = vec![Vec::new(); function_count as usize];
+    for i in 0..name_count {
+        let name = image.string(image.cursor(names + 4 * i)?.u32()?)?;
+        let index = image.cursor(ordinals + 2 * i)?.u16()?;
+        function_names.get_mut(index as usize)
+            .with_context(|| format!("export {} refers to missing function {}", name, index))?
+            .push(name);
+    }
+
+    let mut symbols = Vec::new();
+    for (i, mut names) in function_names.into_iter().enumerate() {
+        let address = image.cursor(functions + 4 * i as u32)?.u32()?;
+        let forwarded = address >= start && address - start < size;
+        if address == 0 || forwarded {
+            continue;
+        }
+        if names.is_empty() {
+            names.push(format!("Ordinal_{}", ordinal_base + i as u32));
+        }
+        let section = image.sections.iter()
+            .find(|section| address >= section.virtual_address && address - section.virtual_address < section.memory_size());
+        let executable = section.is_some_and(|section| section.characteristics & SCN_MEM_EXECUTE != 0);
+        for name in names {
+            symbols.push((name, Symbol {
+                address: header.image_base + u64::from(address),
+                // exports don't say how big they are
+                size: 0,
+                kind: if executable { "Function" } else { "Object" }.to_string(),
+                binding: "Global".to_string(),
+                flags: vec![],
+                section: section.map_or_else(|| "Absolute".to_string(), |section| section.name.clone()),
+            }));
+        }
+    }
+    Ok(symbols)
+}
+
+/// The functions imported from other DLLs, in the order of the import directory
+fn read_imports(image: &Image, header: &PeHeader) -> anyhow::Result<Vec<Import>> {
+    let Some((start, _)) = header.directory(DIRECTORY_IMPORT) else {
+        return Ok(Vec::new());
+    };
+    let (thunk_size, ordinal_flag) = if header.wide { (8, 1 << 63) } else { (4, 1 << 31) };
+
+    let mut imports = Vec::new();
+    // the directory ends with an empty descriptor
+    for descriptor in (start..).step_by(20) {
+        let mut cursor = image.cursor(descriptor)?;
+        let lookup = cursor.u32()?;
+        cursor.skip(4 * 2);
+        let name = cursor.u32()?;
+        let table = cursor.u32()?;
+        if lookup == 0 && name == 0 && table == 0 {
+            break;
+        }
+        let library = image.string(name)?;
+
+        // the lookup table has the same entries as the address table, which bound images
+        // overwrite with addresses
+        let entries = if lookup == 0 { table } else { lookup };
+        for i in 0.. {
+            let mut cursor = image.cursor(entries + i * thunk_size)?;
+            let entry = if header.wide { cursor.u64()? } else { u64::from(cursor.u32()?) };
+            if entry == 0 {
+                break;
+            }
+            let (name, hint, ordinal) = if entry & ordinal_flag != 0 {
+                (None, 0, Some(entry as u16))
+            } else {
+                let mut cursor = image.cursor(entry as u32)?;
+                let hint = cursor.u16()?;
+                (Some(image.string(entry as u32 + 2)?), hint, None)
+            };
+            imports.push(Import {
+                library: library.clone(),
+                name,
+                ordinal,
+                hint,
+                address: header.image_base + u64::from(table + i * thunk_size),
+            });
+        }
+    }
+    Ok(imports)
+}
+
+/// The base relocations of an image, which are needed to load it anywhere but its image base.
+/// `offset` finds where in the file an address relative to the image base is.
+pub fn relocations(
+    bytes: &[u8],
+    header: &PeHeader,
+    offset: impl Fn(u32) -> Option<u64>,
+) -> anyhow::Result<Vec<Relocation>> {
+    let Some((start, size)) = header.directory(DIRECTORY_BASERELOC) else {
+        return Ok(Vec::new());
+    };
+
+    // the directory is a run of blocks, each covering a 4 KiB page
+    let mut relocations = Vec::new();
+    let mut block = start;
+    while block < start + size {
+        let block_offset = offset(block)
+            .with_context(|| format!("relocation block at {:#X} isn't backed by the file", block))?;
+        let mut cursor = Cursor::new(bytes, block_offset)?;
+        let page = cursor.u32()?;
+        let block_size = cursor.u32()?;
+        ensure!(block_size >= 8, "relocation block at {:#X} is too small", block);
+        for _ in 0..(block_size - 8) / 2 {
+            let entry = cursor.u16()?;
+            let kind = entry >> 12;
+            if kind != REL_BASED_ABSOLUTE {
+                relocations.push(Relocation { address: page + u32::from(entry & 0xFFF), kind });
+            }
+        }
+        block += block_size;
+    }
+    Ok(relocations)
+}
+
+/// Applies relocations to the bytes of an image, for moving it by `delta` bytes. `offset` finds
+/// where in the file an address relative to the image base is.
+pub fn relocate(
+    bytes: &mut [u8],
+    relocations: &[Relocation],
+    delta: u64,
+    offset: impl Fn(u32) -> Option<u64>,
+) -> anyhow::Result<()> {
+    for relocation in relocations {
+        let size = match relocation.kind {
+            REL_BASED_HIGH | REL_BASED_LOW => 2,
+            REL_BASED_HIGHLOW => 4,
+            REL_BASED_DIR64 => 8,
+            kind => bail!("unsupported relocation type {} at {:#X}", kind, relocation.address),
+        };
+        let target = offset(relocation.address)
+            .and_then(|start| usize::try_from(start).ok())
+            .and_then(|start| bytes.get_mut(start..start.checked_add(size)?))
+            .with_context(|| format!("relocation at {:#X} isn't backed by the file", relocation.address))?;
+
+        match relocation.kind {
+            REL_BASED_HIGH => {
+                let value = u16::from_le_bytes(target.try_into().unwrap());
+                let value = value.wrapping_add((delta >> 16) as u16);
+                target.copy_from_slice(&value.to_le_bytes());
+            }
+            REL_BASED_LOW => {
+                let value = u16::from_le_bytes(target.try_into().unwrap());
+                target.copy_from_slice(&value.wrapping_add(delta as u16).to_le_bytes());
+            }
+            REL_BASED_HIGHLOW => {
+                let value = u32::from_le_bytes(target.try_into().unwrap());
+                target.copy_from_slice(&value.wrapping_add(delta as u32).to_le_bytes());
+            }
+            _ => {
+                let value = u64::from_le_bytes(target.try_into().unwrap());
+                target.copy_from_slice(&value.wrapping_add(delta).to_le_bytes());
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Whether the image can be moved away from its image base
+pub fn relocatable(header: &PeHeader) -> bool {
+    header.characteristics & RELOCS_STRIPPED == 0
+}
+
+/// The null terminated string at `offset` in the file
+fn string(bytes: &[u8], offset: u64) -> anyhow::Result<String> {
+    let cursor = Cursor::new(bytes, offset)?;
+    let bytes = &cursor.bytes[cursor.offset..];
+    let end = bytes.iter().position(|byte| *byte == 0).unwrap_or(bytes.len());
+    Ok(String::from_utf8_lossy(&bytes[..end]).into_owned())
+}
+
+/// What a section holds, from its content flags
+fn section_kind(characteristics: u32) -> String {
+    let name = if characteristics & SCN_CNT_CODE != 0 {
+        "IMAGE_SCN_CNT_CODE"
+    } else if characteristics & SCN_CNT_INITIALIZED_DATA != 0 {
+        "IMAGE_SCN_CNT_INITIALIZED_DATA"
+    } else if characteristics & SCN_CNT_UNINITIALIZED_DATA != 0 {
+        "IMAGE_SCN_CNT_UNINITIALIZED_DATA"
+    } else {
+        return format!("{:#X}", characteristics & 0xFF);
+    };
+    name.to_string()
+}
+
+fn section_flags(characteristics: u32) -> Vec<String> {
+    const NAMES: [(u32, &str); 16] = [
+        (0x8, "IMAGE_SCN_TYPE_NO_PAD"),
+        (SCN_CNT_CODE, "IMAGE_SCN_CNT_CODE"),
+        (SCN_CNT_INITIALIZED_DATA, "IMAGE_SCN_CNT_INITIALIZED_DATA"),
+        (SCN_CNT_UNINITIALIZED_DATA, "IMAGE_SCN_CNT_UNINITIALIZED_DATA"),
+        (0x200, "IMAGE_SCN_LNK_INFO"),
+        (0x800, "IMAGE_SCN_LNK_REMOVE"),
+        (0x1000, "IMAGE_SCN_LNK_COMDAT"),
+        (0x8000, "IMAGE_SCN_GPREL"),
+        (0x0100_0000, "IMAGE_SCN_LNK_NRELOC_OVFL"),
+        (0x0200_0000, "IMAGE_SCN_MEM_DISCARDABLE"),
+        (0x0400_0000, "IMAGE_SCN_MEM_NOT_CACHED"),
+        (0x0800_0000, "IMAGE_SCN_MEM_NOT_PAGED"),
+        (0x1000_0000, "IMAGE_SCN_MEM_SHARED"),
+        (SCN_MEM_EXECUTE, "IMAGE_SCN_MEM_EXECUTE"),
+        (SCN_MEM_READ, "IMAGE_SCN_MEM_READ"),
+        (SCN_MEM_WRITE, "IMAGE_SCN_MEM_WRITE"),
+    ];
+    NAMES.iter()
+        .filter(|(flag, _)| characteristics & flag != 0)
+        .map(|(_, name)| name.to_string())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::binary::Binary;
+
+    fn fixture(name: &str) -> Vec<u8> {
+        let path = format!("{}/tests/pe/{}", env!("CARGO_MANIFEST_DIR"), name);
+        crate::util::read_file_as_bytes(path).unwrap()
+    }
+
+    #[test]
+    fn test_parse_pe32() {
+        let bytes = fixture("x86.exe");
+        let pe = parse(&bytes).unwrap();
+        assert_eq!((pe.header.machine, pe.header.wide, pe.header.image_base), (0x14C, false, 0x400000));
+        assert_eq!((pe.header.entry, pe.header.size_of_image, pe.header.size_of_headers), (0x1000, 0x6000, 0x400));
+
+        let names = pe.sections.iter().map(|(name, _)| name.as_str()).collect::<Vec<_>>();
+        assert_eq!(names, [".text", ".rdata", ".data", ".bss", ".reloc"]);
+        let (_, text) = &pe.sections[0];
+        assert_eq!((text.kind.as_str(), text.address, text.offset, text.size), ("IMAGE_SCN_CNT_CODE", 0x401000, 0x400, 9));
+        assert_eq!(text.flags, ["IMAGE_SCN_CNT_CODE", "IMAGE_SCN_MEM_EXECUTE", "IMAGE_SCN_MEM_READ"]);
+
+        // the headers, then a segment for each section
+        assert_eq!(pe.segments.len(), 6);
+        let bss = &pe.segments[4];
+        assert_eq!((bss.address, bss.file_size, bss.memory_size), (0x404000, 0, 0x100));
+        assert_eq!(bss.flags, ["IMAGE_SCN_MEM_READ", "IMAGE_SCN_MEM_WRITE"]);
+
+        let [(name, symbol)] = pe.symbols.as_slice() else { panic!("expected one export") };
+        assert_eq!((name.as_str(), symbol.address, symbol.kind.as_str()), ("next_value", 0x401000, "Function"));
+        assert_eq!(symbol.section, ".text");
+
+        let [exit, ordinal] = pe.imports.as_slice() else { panic!("expected two imports") };
+        assert_eq!(exit.library, "KERNEL32.dll");
+        assert_eq!((exit.name.as_deref(), exit.ordinal, exit.address), (Some("ExitProcess"), None, 0x402020));
+        assert_eq!((ordinal.name.as_deref(), ordinal.ordinal, ordinal.address), (None, Some(17), 0x402024));
+
+        let binary = Binary::parse(bytes.clone()).unwrap();
+        let offset = |address: u32| binary.file_offset(0x400000 + u64::from(address));
+        let relocations = relocations(&bytes, &pe.header, offset).unwrap();
+        assert_eq!(relocations, [
+            Relocation { address: 0x1001, kind: REL_BASED_HIGHLOW },
+            Relocation { address: 0x3004, kind: REL_BASED_HIGHLOW },
+        ]);
+    }
+
+    #[test]
+    fn test_parse_pe32_plus() {
+        let pe = parse(&fixture("x64.dll")).unwrap();
+        assert_eq!((pe.header.machine, pe.header.wide, pe.header.image_base), (0x8664, true, 0x180000000));
+        assert_eq!(pe.header.characteristics & 0x2000, 0x2000);
+
+        // the forwarder to msvcrt has no address in this DLL
+        let mut exports = pe.symbols.iter()
+            .map(|(name, symbol)| (name.as_str(), symbol.address))
+            .collect::<Vec<_>>();
+        exports.sort();
+        assert_eq!(exports, [("add", 0x180001000), ("sub", 0x180001004)]);
+
+        let imports = pe.imports.iter()
+            .map(|import| (import.library.as_str(), import.name.as_deref(), import.ordinal, import.address))
+            .collect::<Vec<_>>();
+        assert_eq!(imports, [
+            ("msvcrt.dll", Some("puts"), None, 0x180002020),
+            ("msvcrt.dll", None, Some(7), 0x180002028),
+        ]);
+
+        // .data has more room in memory than its contents in the file
+        let (_, data) = pe.sections.iter().find(|(name, _)| name == ".data").unwrap();
+        assert_eq!((data.address, data.size), (0x180003000, 0x20));
+        assert!(data.flags.contains(&"IMAGE_SCN_MEM_WRITE".to_string()));
+    }
+
+    #[test]
+    fn test_rebase() {
+        let mut binary = Binary::parse(fixture("x64.dll")).unwrap();
+        let pointer = |binary: &Binary| {
+            let offset = binary.file_offset(binary.sections[".data"].address).unwrap() as usize;
+            u64::from_le_bytes(binary.bytes[offset..][..8].try_into().unwrap())
+        };
+        assert_eq!(pointer(&binary), 0x180001000);
+
+        binary.rebase(0x7FF0_0000_0000).unwrap();
+        assert_eq!(pointer(&binary), 0x7FF0_0000_1000);
+        assert_eq!(binary.symbols["add"].front().unwrap().address, 0x7FF0_0000_1000);
+        assert_eq!(binary.sections[".text"].address, 0x7FF0_0000_1000);
+        assert_eq!(binary.imports[0].address, 0x7FF0_0000_2020);
+        assert_eq!(binary.symbolize(0x7FF0_0000_1005), "sub+0x1");
+
+        binary.rebase(0x180000000).unwrap();
+        assert_eq!(binary.bytes, fixture("x64.dll"));
+        assert!(Binary::parse(crate::util::read_file_as_bytes(concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fib/bin")).unwrap())
+            .unwrap()
+            .rebase(0)
+            .is_err());
+    }
+
+    #[test]
+    fn test_not_pe() {
+        assert!(parse(b"MZ\x90\x00").is_err());
+        let mut bytes = fixture("x86.exe");
+        bytes[0x80..0x84].copy_from_slice(b"NE\0\0");
+        assert_eq!(parse(&bytes).unwrap_err().to_string(), "not a PE file, only a DOS executable");
+    }
+}
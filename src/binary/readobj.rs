@@ -5,6 +5,7 @@ use crate::util::{exec, ExecUtil};
 
 #[derive(Deserialize, Debug)]
 #[serde(rename_all = "PascalCase")]
+#[allow(dead_code)]
 pub struct FileSummary {
     pub file: String,
     pub format: String,
@@ -33,6 +34,7 @@ pub struct Flags {
 
 #[derive(Deserialize, Debug)]
 #[serde(rename_all = "PascalCase")]
+#[allow(dead_code)]
 pub struct Symbol {
     pub name: NameValue,
     pub value: u64,
@@ -57,6 +59,7 @@ pub struct SymbolItem {
 
 #[derive(Deserialize, Debug)]
 #[serde(rename_all = "PascalCase")]
+#[allow(dead_code)]
 pub struct NameValue {
     pub name: String,
     pub value: u64,
@@ -64,6 +67,7 @@ pub struct NameValue {
 
 #[derive(Deserialize, Debug)]
 #[serde(rename_all = "PascalCase")]
+#[allow(dead_code)]
 pub struct Readobj {
     pub file_summary: FileSummary,
     pub sections: Vec<SectionItem>,
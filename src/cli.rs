@@ -1,7 +1,8 @@
 use std::path::PathBuf;
-use clap::{Args, Parser, Subcommand, ValueEnum};
+use clap::{Parser, Subcommand};
 
 #[derive(Parser, Clone)]
+#[allow(clippy::upper_case_acronyms)]
 pub struct CLI {
     #[command(subcommand)]
     pub command: Command,
@@ -13,5 +14,14 @@ pub enum Command {
     Emulate {
         /// the path to the binary
         binary: PathBuf,
+        /// fault on accesses to unmapped memory instead of reading zeros
+        #[arg(long)]
+        fault_unmapped: bool,
+        /// compare the parsed sections and symbols with llvm-readobj (needs LLVM 17 or later)
+        #[arg(long)]
+        cross_check: bool,
+        /// print every pcode op, varnode access and branch as it's emulated
+        #[arg(long)]
+        trace: bool,
     },
 }
@@ -1,23 +1,60 @@
-use std::cell::Ref;
+use std::cmp::Ordering;
 use std::collections::btree_map;
 use std::hash::Hash;
-use std::ops::Deref;
-use anyhow::{bail, Context};
-use hashbrown::Equivalent;
+use hashbrown::{Equivalent, HashMap};
 use itertools::Itertools;
-use num::{BigInt, BigUint, Zero};
+use num::{BigInt, BigUint, One, Signed, ToPrimitive, Zero};
 use sleigh::{AddrSpace, Opcode, PCode, SpaceType, VarnodeData};
-use crate::emulator::{Machine, Space, space};
+use crate::emulator::{Access, Change, EmulationError, ErrorKind, Machine, Permissions, Space, SpaceSnapshot, UnmappedPolicy, space};
+use crate::emulator::space::Fault;
+use crate::emulator::float::FloatFormat;
+use crate::emulator::userop::{self, UserOpHandler};
+use crate::emulator::hook::Hooks;
+use crate::emulator::native::{self, NATIVE_SIZE};
+
+/// Prints a line of the trace, if `Emulator::set_trace` turned it on
+macro_rules! trace {
+    ($emulator:expr, $($arg:tt)*) => {
+        if $emulator.trace {
+            println!($($arg)*);
+        }
+    };
+}
 
 
 /// A control structure for the emulator
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum PCodeControl {
     /// branch to the given address
     Branch(u64),
+    /// call the function at `target`, which should eventually return to `return_address`
+    Call {
+        target: u64,
+        return_address: u64,
+    },
+    /// return to the given address
+    Return(u64),
+    /// branch by the given number of pcode ops within the current instruction
+    RelativeBranch(i64),
     /// continue to the next pcode instruction
     Continue,
 }
 
+/// The state of an `Emulator` at some point, see `Emulator::snapshot`
+#[derive(Debug, Clone)]
+pub struct Snapshot {
+    address: u64,
+    pcode_index: usize,
+    spaces: Vec<Option<SpaceSnapshot>>,
+}
+
+impl Snapshot {
+    /// The address of the instruction that was being emulated
+    pub fn address(&self) -> u64 {
+        self.address
+    }
+}
+
 pub struct Emulator<'a, 'b> {
     /// the emulator
     pub emulator: &'a Machine<'b>,
@@ -26,472 +63,1162 @@ pub struct Emulator<'a, 'b> {
     /// the exit address of the emulator code
     pub end_address: u64,
 
-    pub unique_space: Space,
-    pub register_space: Space,
-    pub ram_space: Space,
+    /// the spaces values are stored in, indexed by space index
+    spaces: Vec<Option<Space>>,
+    /// the index of the space code is in, which hooks and memory permissions apply to
+    pub default_space: usize,
+
+    /// handlers for user-defined ops, keyed by name
+    user_ops: HashMap<String, Box<UserOpHandler>>,
+    /// callbacks on accesses to ram
+    pub(crate) hooks: Hooks,
+    /// whether ops, accesses and branches are printed as they're emulated
+    trace: bool,
+    /// whether integer ops that fit are emulated on native integers instead of `BigUint`s
+    native: bool,
 
     pcode_group_iter: btree_map::Range<'a, u64, Vec<PCode>>,
-    pcode_iter: std::iter::Enumerate<std::slice::Iter<'a, PCode>>,
+    /// the pcode ops of the current instruction
+    pcode_group: &'a [PCode],
+    /// the index of the next pcode op to emulate in `pcode_group`
+    pcode_index: usize,
 }
 
 impl<'a, 'b> Emulator<'a, 'b> {
-    pub fn new(machine: &'a Machine<'b>, address: u64, end_address: u64) -> Self {
+    pub fn new(machine: &'a Machine<'b>, address: u64, end_address: u64) -> Result<Self, EmulationError> {
         let mut pcode_group_iter = machine.pcodes.range(address..);
-        let (new_addr, new_vec) = pcode_group_iter.next().expect("no more pcodes!");
-        let pcode_iter = new_vec.iter().enumerate();
-        Self {
+        let (new_addr, new_vec) = pcode_group_iter.next()
+            .ok_or_else(|| EmulationError::new(address, None, ErrorKind::EndOfCode)
+                .with_symbol(machine.binary.index.describe(address)))?;
+        Ok(Self {
             emulator: machine,
             address: *new_addr,
             end_address,
-            unique_space: Space::new(false),
-            register_space: Space::new(false),
-            ram_space: Space::new(false),
+            spaces: machine.new_spaces(),
+            default_space: machine.default_space,
+            user_ops: userop::defaults().into_iter()
+                .map(|(name, handler)| (name.to_string(), handler))
+                .collect(),
+            hooks: Hooks::default(),
+            trace: false,
+            native: true,
             pcode_group_iter,
-            pcode_iter,
-        }
+            pcode_group: new_vec,
+            pcode_index: 0,
+        })
     }
 
     #[inline]
-    pub fn set_address(&mut self, address: u64) {
+    pub fn set_address(&mut self, address: u64) -> Result<(), EmulationError> {
         if self.address == self.end_address {
-            return;
+            return Ok(());
         }
         self.pcode_group_iter = self.emulator.pcodes.range(address..);
-        let (new_addr, new_vec) = self.pcode_group_iter.next().expect("no more pcodes!");
+        let (new_addr, new_vec) = self.pcode_group_iter.next()
+            .ok_or_else(|| self.error(address, None, ErrorKind::EndOfCode))?;
         self.address = *new_addr;
-        self.pcode_iter = new_vec.iter().enumerate();
+        self.pcode_group = new_vec;
+        self.pcode_index = 0;
+        self.clear_unique();
+        Ok(())
     }
 
+    /// Zeroes the unique space, whose values only live until the end of an instruction
     #[inline]
-    pub fn get_register<Q: ?Sized>(&self, k: &Q) -> Option<&VarnodeData>
+    fn clear_unique(&self) {
+        if let Some(Some(unique)) = self.emulator.unique_space.map(|index| &self.spaces[index]) {
+            unique.clear();
+        }
+    }
+
+    /// Captures the spaces and the position in the pcode. Memory is copied on write, so taking a
+    /// snapshot is cheap and holding one costs only the pages written since. Hooks and user op
+    /// handlers aren't part of the snapshot.
+    pub fn snapshot(&self) -> Snapshot {
+        Snapshot {
+            address: self.address,
+            pcode_index: self.pcode_index,
+            spaces: self.spaces.iter()
+                .map(|space| space.as_ref().map(Space::snapshot))
+                .collect(),
+        }
+    }
+
+    /// The bytes of the default space that have changed since `snapshot` was taken
+    pub fn memory_changes(&self, snapshot: &Snapshot) -> Vec<Change> {
+        match &snapshot.spaces[self.default_space] {
+            Some(checkpoint) => self.ram().diff(checkpoint),
+            None => Vec::new(),
+        }
+    }
+
+    /// Puts the emulator back the way it was when `snapshot` was taken
+    pub fn restore(&mut self, snapshot: &Snapshot) {
+        for (space, snapshot) in self.spaces.iter_mut().zip(&snapshot.spaces) {
+            if let (Some(space), Some(snapshot)) = (space, snapshot) {
+                space.restore(snapshot);
+            }
+        }
+
+        self.pcode_group_iter = self.emulator.pcodes.range(snapshot.address..);
+        self.pcode_group = self.pcode_group_iter.next()
+            .map_or(&[], |(_, pcodes)| pcodes.as_slice());
+        self.address = snapshot.address;
+        self.pcode_index = snapshot.pcode_index;
+    }
+
+    /// Moves to the pcode op `offset` ops away from the last one emulated, within the current
+    /// instruction. Landing just past the last op falls through to the next instruction.
+    pub fn branch_relative(&mut self, offset: i64) -> Result<(), EmulationError> {
+        let current = self.pcode_index.checked_sub(1);
+        let index = current
+            .and_then(|current| current.checked_add_signed(offset as isize))
+            .filter(|index| *index <= self.pcode_group.len())
+            .ok_or_else(|| self.error(
+                self.address,
+                current,
+                decode_error(format!("relative branch by {} leaves the instruction", offset)),
+            ))?;
+        self.pcode_index = index;
+        Ok(())
+    }
+
+    /// Moves to the next pcode op according to a control returned by `emulate_one`
+    pub fn apply(&mut self, control: PCodeControl) -> Result<(), EmulationError> {
+        match control {
+            PCodeControl::Branch(target)
+            | PCodeControl::Call { target, .. }
+            | PCodeControl::Return(target) => self.set_address(target),
+            PCodeControl::RelativeBranch(offset) => self.branch_relative(offset),
+            PCodeControl::Continue => Ok(()),
+        }
+    }
+
+    /// Registers a handler for the user-defined op called `name`, replacing any existing handler
+    pub fn register_user_op<F>(&mut self, name: impl Into<String>, handler: F)
         where
-            Q: Hash + Equivalent<String>,
+            F: Fn(&Emulator<'_, '_>, &[VarnodeData], Option<&VarnodeData>) -> anyhow::Result<PCodeControl> + 'static,
+    {
+        self.user_ops.insert(name.into(), Box::new(handler));
+    }
+
+    /// Maps `size` bytes of ram at `start` with the given permissions
+    pub fn map_memory(&mut self, start: u64, size: u64, permissions: Permissions) {
+        self.ram_mut().map(start, size, permissions);
+    }
+
+    /// Sets what happens when unmapped ram is accessed
+    pub fn set_unmapped_policy(&mut self, policy: UnmappedPolicy) {
+        self.ram_mut().set_unmapped_policy(policy);
+    }
+
+    /// Turns printing every op, varnode access and branch on or off. It's off by default, since
+    /// formatting an access costs far more than emulating it.
+    pub fn set_trace(&mut self, trace: bool) {
+        self.trace = trace;
+    }
+
+    /// Turns emulating integer ops of up to 16 bytes on native integers on or off. It's on by
+    /// default; turning it off emulates every integer op with `BigUint`s, which is only useful
+    /// for comparing the two.
+    pub fn set_native(&mut self, native: bool) {
+        self.native = native;
+    }
+
+    #[inline]
+    pub fn get_register<Q>(&self, k: &Q) -> Option<&VarnodeData>
+        where
+            Q: ?Sized + Hash + Equivalent<String>,
     {
         self.emulator.named_registers.get(k)
     }
 }
 
 impl<'a, 'b> Iterator for Emulator<'a, 'b> {
-    type Item = (usize, &'a PCode);
+    type Item = Result<(usize, &'a PCode), EmulationError>;
 
     #[inline]
     fn next(&mut self) -> Option<Self::Item> {
-        let Some((i, pcode)) = self.pcode_iter.next() else {
+        let i = self.pcode_index;
+        let Some(pcode) = self.pcode_group.get(i) else {
             if self.address == self.end_address {
                 return None;
             }
-            let (new_addr, new_vec) = self.pcode_group_iter.next().expect("no more pcodes!");
+            let Some((new_addr, new_vec)) = self.pcode_group_iter.next() else {
+                return Some(Err(self.error(self.address, None, ErrorKind::EndOfCode)));
+            };
             self.address = *new_addr;
-            self.pcode_iter = new_vec.iter().enumerate();
+            self.pcode_group = new_vec;
+            self.pcode_index = 0;
+            self.clear_unique();
             return self.next();
         };
-        Some((i, pcode))
+        if i == 0 {
+            if let Err(kind) = self.check_access(self.ram(), self.address, 1, Access::Execute) {
+                return Some(Err(self.error(self.address, None, kind)));
+            }
+        }
+        self.pcode_index += 1;
+        Some(Ok((i, pcode)))
     }
 }
 
 impl<'a, 'b> Emulator<'a, 'b> {
     #[inline]
-    pub fn get_bytes(&self, node: &VarnodeData) -> Ref<[u8]> {
-        let bytes = self.get_varnode_space(node)
-            .expect("sleigh dropped the ball, node doesn't have a space")
-            .get_bytes(node.offset, node.size.into());
-        println!("  read {:X?} from {}", bytes, self.nameof(node));
-        bytes
+    pub fn get_bytes(&self, node: &VarnodeData) -> Result<Vec<u8>, ErrorKind> {
+        let mut bytes = vec![0; node.size as usize];
+        self.read_into(node, &mut bytes)?;
+        Ok(bytes)
+    }
+
+    /// Reads a varnode into `buffer`, which must be the size of the varnode
+    #[inline]
+    pub fn read_into(&self, node: &VarnodeData, buffer: &mut [u8]) -> Result<(), ErrorKind> {
+        expect_size(node, buffer.len() as u32)?;
+        let space = self.get_varnode_space(node)?;
+        self.check_access(space, node.offset, node.size.into(), Access::Read)?;
+        let hooked = !self.hooks.is_empty() && node.space.index == self.default_space;
+        if hooked {
+            self.before_read(node.offset, node.size.into())?;
+        }
+        space.read_into(node.offset, buffer);
+        if hooked {
+            self.read_mmio(node.offset, buffer)?;
+        }
+        trace!(self, "  read {:X?} from {}", buffer, self.nameof(node));
+        Ok(())
     }
 
-    pub fn set_bytes(&self, node: &VarnodeData, bytes: &[u8]) {
+    pub fn set_bytes(&self, node: &VarnodeData, bytes: &[u8]) -> Result<(), ErrorKind> {
         if matches!(node.space.type_, SpaceType::Constant) {
-            panic!("cannot write to constant space");
+            return Err(ErrorKind::InvalidSpace(node.space.name.clone()));
+        }
+        trace!(self, "  wrote {:X?} to {}", bytes, self.nameof(node));
+        let space = self.get_varnode_space(node)?;
+        self.check_access(space, node.offset, bytes.len() as u64, Access::Write)?;
+        if !self.hooks.is_empty() && node.space.index == self.default_space {
+            self.write_unclaimed(space, node.offset, bytes);
+            self.after_write(node.offset, bytes)?;
+        } else {
+            space.write_from(node.offset, bytes);
         }
-        println!("  wrote {:X?} to {}", bytes, self.nameof(node));
-        self.get_varnode_space(node)
-            .unwrap()
-            .set_bytes(node.offset, bytes);
+        Ok(())
     }
 
-    pub fn read<T: space::Read>(&self, node: &VarnodeData) -> T {
+    /// Whether a varnode's value is stored big endian, according to its space
+    #[inline]
+    pub fn is_big_endian(&self, node: &VarnodeData) -> Result<bool, ErrorKind> {
         if matches!(node.space.type_, SpaceType::Constant) {
-            let bytes = if node.space.is_big_endian {
-                node.offset.to_be_bytes()
-            } else {
-                node.offset.to_le_bytes()
-            };
-            T::read(node.space.is_big_endian, &bytes)
+            Ok(node.space.is_big_endian)
         } else {
-            T::read(node.space.is_big_endian, self.get_bytes(node).deref())
+            Ok(self.get_varnode_space(node)?.is_big_endian())
         }
     }
 
     #[inline]
-    pub fn write<T: space::Write>(&self, node: &VarnodeData, value: T) {
-        let mut bytes = vec![0; node.size as usize];
-        value.write(node.space.is_big_endian, &mut bytes);
-        self.set_bytes(node, &bytes);
+    fn check_access(&self, space: &Space, offset: u64, len: u64, access: Access) -> Result<(), ErrorKind> {
+        space.check(offset, len, access).map_err(|fault| match fault {
+            Fault::Unmapped(offset) => ErrorKind::UnmappedMemory { space: space.name().to_string(), offset, access },
+            Fault::Denied(offset) => ErrorKind::AccessViolation { space: space.name().to_string(), offset, access },
+        })
     }
 
-    pub fn get_space_from_const(&self, node: &VarnodeData) -> anyhow::Result<AddrSpace> {
+    pub fn read<T: space::Read>(&self, node: &VarnodeData) -> Result<T, ErrorKind> {
+        let value = if matches!(node.space.type_, SpaceType::Constant) {
+            // the constant is the offset, truncated to the size of the varnode like any other value
+            let mut bytes = vec![0; node.size as usize];
+            space::Write::write(node.offset, node.space.is_big_endian, &mut bytes);
+            T::read(node.space.is_big_endian, &bytes)
+        } else {
+            T::read(self.is_big_endian(node)?, &self.get_bytes(node)?)
+        };
+        value.ok_or_else(|| decode_error(format!(
+            "{} doesn't fit in a {}", self.nameof(node), std::any::type_name::<T>(),
+        )))
+    }
+
+    #[inline]
+    pub fn write<T: space::Write>(&self, node: &VarnodeData, value: T) -> Result<(), ErrorKind> {
+        // avoid allocating for anything that fits in a register
+        let mut buffer = [0u8; NATIVE_SIZE as usize];
+        let mut vec;
+        let bytes = if node.size <= NATIVE_SIZE {
+            &mut buffer[..node.size as usize]
+        } else {
+            vec = vec![0; node.size as usize];
+            vec.as_mut_slice()
+        };
+        value.write(self.is_big_endian(node)?, bytes);
+        self.set_bytes(node, bytes)
+    }
+
+    /// The space accessed by a LOAD or STORE, from the space index constant that is its input0
+    pub fn get_space_from_const(&self, node: &VarnodeData) -> Result<AddrSpace, ErrorKind> {
         if node.space.type_ != SpaceType::Constant {
-            bail!("expected constant space");
+            return Err(decode_error("expected constant space"));
         }
 
-        use sleigh_sys::ffi;
-        let space: *mut ffi::AddrSpace = node.offset as *mut ffi::AddrSpace;
-        let space: &ffi::AddrSpace = unsafe { // uh oh, now I've done it
-            space.as_ref()
-                .context("unable to get space")?
-        };
-        let out = sleigh::AddrSpace::from(space);
-        println!("  resolved {} to the {:?} space", self.nameof(node), out.name);
+        let out = self.emulator.spaces.iter()
+            .find(|space| space.index as u64 == node.offset)
+            .cloned()
+            .ok_or_else(|| ErrorKind::InvalidSpace(format!("#{}", node.offset)))?;
+        trace!(self, "  resolved {} to the {:?} space", self.nameof(node), out.name);
         Ok(out)
     }
 
     #[inline]
-    pub fn get_varnode_space(&self, node: &VarnodeData) -> anyhow::Result<&Space> {
-        self.get_space(&node.space.name)
+    pub fn get_varnode_space(&self, node: &VarnodeData) -> Result<&Space, ErrorKind> {
+        self.spaces.get(node.space.index)
+            .and_then(Option::as_ref)
+            .ok_or_else(|| ErrorKind::InvalidSpace(node.space.name.clone()))
     }
 
-    #[inline]
-    pub fn get_space(&self, name: &str) -> anyhow::Result<&Space> {
-        match name {
-            "unique" => Ok(&self.unique_space),
-            "register" => Ok(&self.register_space),
-            "ram" => Ok(&self.ram_space),
-            _ => bail!("unsupported space type: {:?}", name),
+    /// The space called `name`
+    pub fn get_space(&self, name: &str) -> Result<&Space, ErrorKind> {
+        self.spaces.iter()
+            .flatten()
+            .find(|space| space.name() == name)
+            .ok_or_else(|| ErrorKind::InvalidSpace(name.to_string()))
+    }
+
+    /// The space with the given index
+    pub fn get_space_by_index(&self, index: usize) -> Option<&Space> {
+        self.spaces.get(index)?.as_ref()
+    }
+
+    /// The default space, which code is loaded into
+    pub fn ram(&self) -> &Space {
+        self.spaces[self.default_space].as_ref()
+            .expect("the default space always exists")
+    }
+
+    pub fn ram_mut(&mut self) -> &mut Space {
+        self.spaces[self.default_space].as_mut()
+            .expect("the default space always exists")
+    }
+
+    /// The control for a direct branch to `target`. Targets in the constant space are relative to
+    /// the current pcode op rather than machine addresses.
+    fn branch_to(&self, target: &VarnodeData) -> PCodeControl {
+        if target.space.type_ == SpaceType::Constant {
+            let offset = native::sign_extend(u128::from(target.offset), target.size) as i64;
+            trace!(self, "  branch by {} pcode ops", offset);
+            PCodeControl::RelativeBranch(offset)
+        } else {
+            trace!(self, "  branch to {:X}", target.offset);
+            PCodeControl::Branch(target.offset)
         }
     }
 
+    /// Reads a varnode as a float, decoded according to its size. Extended precision values are
+    /// rounded to the nearest double.
+    pub fn read_float(&self, node: &VarnodeData) -> Result<f64, ErrorKind> {
+        let format = float_format(node)?;
+        let bits: u128 = self.read(node)?;
+        Ok(format.decode(bits))
+    }
+
+    /// Writes a float to a varnode, encoded according to its size
+    pub fn write_float(&self, node: &VarnodeData, value: f64) -> Result<(), ErrorKind> {
+        let format = float_format(node)?;
+        self.write(node, format.encode(value))?;
+        Ok(())
+    }
+
+    /// The address of the instruction following the one at `address`
+    pub fn fallthrough(&self, address: u64) -> Result<u64, ErrorKind> {
+        self.emulator.instructions.range(address + 1..)
+            .next()
+            .map(|(address, _)| *address)
+            .ok_or(ErrorKind::EndOfCode)
+    }
+
     pub fn nameof(&self, node: &VarnodeData) -> String {
-        self.emulator.register_names.get(node)
-            .cloned()
-            .unwrap_or_else(|| format!("{}:{:X}+{}", node.space.name, node.offset, node.size))
+        if let Some(name) = self.emulator.register_names.get(node) {
+            return name.clone();
+        }
+        // part of a register that sleigh has no name for
+        if self.emulator.register_extent(node.space.index).is_some() {
+            if let Some((name, register)) = self.containing_register(node) {
+                return format!("{}[{}:{}]", name, node.offset - register.offset, node.size);
+            }
+        }
+        format!("{}:{:X}+{}", node.space.name, node.offset, node.size)
     }
 
     pub fn emulate_one(
         &self,
         pcode: &PCode,
-    ) -> anyhow::Result<PCodeControl> {
-        println!("  {:?} : {} -> {}", pcode.opcode,
-                 pcode.vars.iter().map(|node| self.nameof(node)).join(", "),
-                 pcode.outvar.as_ref().map(|node| self.nameof(node)).unwrap_or("!".to_string()));
+    ) -> Result<PCodeControl, EmulationError> {
+        self.emulate_op(pcode).map_err(|kind| {
+            // the op is usually the last one handed out by the iterator
+            let index = self.pcode_group.iter().position(|op| std::ptr::eq(op, pcode));
+            self.error(pcode.address, index, kind)
+        })
+    }
+
+    /// An error at an instruction, naming the symbol it's in
+    fn error(&self, address: u64, index: Option<usize>, kind: ErrorKind) -> EmulationError {
+        EmulationError::new(address, index, kind).with_symbol(self.emulator.binary.index.describe(address))
+    }
+
+    /// Formats an address as `symbol+0x1c`, or as a bare address if the binary has no symbol
+    /// there
+    pub fn symbolize(&self, address: u64) -> String {
+        self.emulator.binary.symbolize(address)
+    }
+
+    fn emulate_op(&self, pcode: &PCode) -> Result<PCodeControl, ErrorKind> {
+        trace!(self, "  {:?} : {} -> {}", pcode.opcode,
+                     pcode.vars.iter().map(|node| self.nameof(node)).join(", "),
+                     pcode.outvar.as_ref().map(|node| self.nameof(node)).unwrap_or("!".to_string()));
         let control = match pcode.opcode {
-            Opcode::Copy => {
+            Opcode::Branch => {
+                let [addr] = pcode.vars.as_slice() else {
+                    return Err(decode_error("expected 1 input"));
+                };
+                self.branch_to(addr)
+            }
+            Opcode::Call => {
+                let [input0, _args @ ..] = pcode.vars.as_slice() else {
+                    return Err(decode_error("expected at least 1 input"));
+                };
+                let return_address = self.fallthrough(pcode.address)?;
+                trace!(self, "  call {:X}, returning to {:X}", input0.offset, return_address);
+                PCodeControl::Call { target: input0.offset, return_address }
+            }
+            Opcode::CallInd => {
+                let [input0, _args @ ..] = pcode.vars.as_slice() else {
+                    return Err(decode_error("expected at least 1 input"));
+                };
+                let target: u64 = self.read(input0)?;
+                let return_address = self.fallthrough(pcode.address)?;
+                trace!(self, "  call {:X}, returning to {:X}", target, return_address);
+                PCodeControl::Call { target, return_address }
+            }
+            Opcode::BranchInd => {
+                let [input0] = pcode.vars.as_slice() else {
+                    return Err(decode_error("expected 1 input"));
+                };
+                let target: u64 = self.read(input0)?;
+                trace!(self, "  branch to {:X}", target);
+                PCodeControl::Branch(target)
+            }
+            Opcode::CBranch => {
+                let [input0, input1] = pcode.vars.as_slice() else {
+                    return Err(decode_error("expected 2 inputs"));
+                };
+
+                let condition: BigUint = self.read(input1)?;
+                if condition != BigUint::zero() {
+                    self.branch_to(input0)
+                } else {
+                    trace!(self, "  fall through");
+                    PCodeControl::Continue
+                }
+            }
+            Opcode::Return => {
+                let [input0, _values @ ..] = pcode.vars.as_slice() else {
+                    return Err(decode_error("expected at least 1 input"));
+                };
+                let off: u64 = self.read(input0)?;
+                trace!(self, "  return to {:X}", off);
+                PCodeControl::Return(off)
+            }
+            Opcode::FloatAdd | Opcode::FloatSub | Opcode::FloatMult | Opcode::FloatDiv => {
+                let [input0, input1] = pcode.vars.as_slice() else {
+                    return Err(decode_error("expected 2 inputs"));
+                };
+                let output = output_of(pcode)?;
+
+                expect_size(input1, input0.size)?;
+                expect_size(output, input0.size)?;
+                let format = float_format(input0)?;
+                let left: u128 = self.read(input0)?;
+                let right: u128 = self.read(input1)?;
+                let result = match pcode.opcode {
+                    Opcode::FloatAdd => format.add(left, right),
+                    Opcode::FloatSub => format.sub(left, right),
+                    Opcode::FloatMult => format.mul(left, right),
+                    _ => format.div(left, right),
+                };
+                self.write(output, result)?;
+                PCodeControl::Continue
+            }
+            Opcode::FloatNeg | Opcode::FloatAbs => {
+                let [input0] = pcode.vars.as_slice() else {
+                    return Err(decode_error("expected 1 input"));
+                };
+                let output = output_of(pcode)?;
+
+                expect_size(output, input0.size)?;
+                // only the sign bit changes, so nan payloads are kept intact
+                let format = float_format(input0)?;
+                let bits: u128 = self.read(input0)?;
+                let sign = 1u128 << format.sign_bit();
+                let result = match pcode.opcode {
+                    Opcode::FloatNeg => bits ^ sign,
+                    _ => bits & !sign,
+                };
+                self.write(output, result)?;
+                PCodeControl::Continue
+            }
+            Opcode::FloatSqrt => {
+                let [input0] = pcode.vars.as_slice() else {
+                    return Err(decode_error("expected 1 input"));
+                };
+                let output = output_of(pcode)?;
+
+                expect_size(output, input0.size)?;
+                let format = float_format(input0)?;
+                let bits: u128 = self.read(input0)?;
+                self.write(output, format.sqrt(bits))?;
+                PCodeControl::Continue
+            }
+            Opcode::FloatNan => {
+                let [input0] = pcode.vars.as_slice() else {
+                    return Err(decode_error("expected 1 input"));
+                };
+                let output = output_of(pcode)?;
+
+                let format = float_format(input0)?;
+                let bits: u128 = self.read(input0)?;
+                self.write(output, format.is_nan(bits))?;
+                PCodeControl::Continue
+            }
+            Opcode::FloatEqual | Opcode::FloatNotEqual | Opcode::FloatLess | Opcode::FloatLessEqual => {
+                let [input0, input1] = pcode.vars.as_slice() else {
+                    return Err(decode_error("expected 2 inputs"));
+                };
+                let output = output_of(pcode)?;
+
+                expect_size(input1, input0.size)?;
+                expect_size(output, 1)?;
+                // comparisons with nan are false, except for not-equal
+                let format = float_format(input0)?;
+                let left: u128 = self.read(input0)?;
+                let right: u128 = self.read(input1)?;
+                let order = format.compare(left, right);
+                let result = match pcode.opcode {
+                    Opcode::FloatEqual => order == Some(Ordering::Equal),
+                    Opcode::FloatNotEqual => order != Some(Ordering::Equal),
+                    Opcode::FloatLess => order == Some(Ordering::Less),
+                    _ => matches!(order, Some(Ordering::Less | Ordering::Equal)),
+                };
+                self.write(output, result)?;
+                PCodeControl::Continue
+            }
+            Opcode::FloatInt2Float => {
+                let [input0] = pcode.vars.as_slice() else {
+                    return Err(decode_error("expected 1 input"));
+                };
+                let output = output_of(pcode)?;
+
+                // the input is a signed integer, rounded straight to the output's format
+                let format = float_format(output)?;
+                let value: i128 = self.read(input0)?;
+                self.write(output, format.from_int(value))?;
+                PCodeControl::Continue
+            }
+            Opcode::FloatFloat2Float => {
+                let [input0] = pcode.vars.as_slice() else {
+                    return Err(decode_error("expected 1 input"));
+                };
+                let output = output_of(pcode)?;
+
+                let from = float_format(input0)?;
+                let to = float_format(output)?;
+                let bits: u128 = self.read(input0)?;
+                self.write(output, from.convert(bits, to))?;
+                PCodeControl::Continue
+            }
+            Opcode::FloatTrunc => {
+                let [input0] = pcode.vars.as_slice() else {
+                    return Err(decode_error("expected 1 input"));
+                };
+                let output = output_of(pcode)?;
+
+                // like x86's CVTTSD2SI, nans and values that don't fit in the output become the
+                // "integer indefinite" value, the most negative integer of the output's size
+                let format = float_format(input0)?;
+                let bits: u128 = self.read(input0)?;
+                let indefinite = i128::MIN >> (128 - output.size.clamp(1, NATIVE_SIZE) * 8);
+                let result = format.trunc(bits)
+                    .filter(|value| native::sign_extend(*value as u128, output.size) == *value)
+                    .unwrap_or(indefinite);
+                self.write(output, result)?;
+                PCodeControl::Continue
+            }
+            Opcode::FloatCeil | Opcode::FloatFloor | Opcode::FloatRound => {
                 let [input0] = pcode.vars.as_slice() else {
-                    bail!("expected 1 input");
+                    return Err(decode_error("expected 1 input"));
                 };
-                let output = pcode.outvar.as_ref()
-                    .context("expected output")?;
+                let output = output_of(pcode)?;
 
-                assert_eq!(input0.size, output.size, "input and output must have the same size");
-                let value: BigUint = self.read(input0);
-                self.write(output, value);
+                expect_size(output, input0.size)?;
+                let format = float_format(input0)?;
+                let bits: u128 = self.read(input0)?;
+                let result = match pcode.opcode {
+                    Opcode::FloatCeil => format.ceil(bits),
+                    Opcode::FloatFloor => format.floor(bits),
+                    _ => format.round(bits),
+                };
+                self.write(output, result)?;
+                PCodeControl::Continue
+            }
+            Opcode::CallOther => {
+                let [input0, inputs @ ..] = pcode.vars.as_slice() else {
+                    return Err(decode_error("expected at least 1 input"));
+                };
 
+                let name = self.emulator.user_ops.get(input0.offset as usize)
+                    .ok_or_else(|| decode_error(format!("unknown user-defined op index: {}", input0.offset)))?;
+                let Some(handler) = self.user_ops.get(name) else {
+                    return Err(ErrorKind::UnhandledUserOp(name.clone()));
+                };
+                trace!(self, "  calling user-defined op {}", name);
+                handler(self, inputs, pcode.outvar.as_ref())
+                    .map_err(|error| ErrorKind::UserOp { name: name.clone(), error })?
+            }
+            _ => {
+                if self.native && native::fits(pcode) {
+                    self.emulate_native(pcode)?;
+                } else {
+                    self.emulate_wide(pcode)?;
+                }
                 PCodeControl::Continue
             }
+        };
+
+        Ok(control)
+    }
+
+    /// Emulates an integer op with `BigUint`s, for varnodes too wide to emulate natively
+    pub(crate) fn emulate_wide(&self, pcode: &PCode) -> Result<(), ErrorKind> {
+        match pcode.opcode {
+            Opcode::Copy => {
+                let [input0] = pcode.vars.as_slice() else {
+                    return Err(decode_error("expected 1 input"));
+                };
+                let output = output_of(pcode)?;
+
+                expect_size(output, input0.size)?;
+                let value: BigUint = self.read(input0)?;
+                self.write(output, value)?;
+            }
             Opcode::IntSub => {
                 let [input0, input1] = pcode.vars.as_slice() else {
-                    bail!("expected 2 inputs");
+                    return Err(decode_error("expected 2 inputs"));
                 };
-                let output = pcode.outvar.as_ref()
-                    .context("expected output")?;
+                let output = output_of(pcode)?;
 
-                assert_eq!(input0.size, input1.size, "inputs must have be the same size");
-                let left: BigInt = self.read(input0);
-                let right: BigInt = self.read(input1);
+                expect_size(input1, input0.size)?;
+                let left: BigInt = self.read(input0)?;
+                let right: BigInt = self.read(input1)?;
                 let result = left - right;
-                self.write(output, result);
-                PCodeControl::Continue
+                self.write(output, result)?;
             }
             Opcode::Store => {
                 let [input0, input1, input2] = pcode.vars.as_slice() else {
-                    bail!("expected 3 inputs");
+                    return Err(decode_error("expected 3 inputs"));
                 };
 
                 let space = self.get_space_from_const(input0)?;
-                let offset: u64 = self.read(input1);
-                let offset = offset * u64::from(space.wordsize); // offset to bytes
-                let value: BigUint = self.read(input2);
+                let offset = pointer_offset(&space, self.read(input1)?);
+                let value: BigUint = self.read(input2)?;
 
                 let varnode = VarnodeData { space, offset, size: input2.size };
-                self.write(&varnode, value);
-
-                PCodeControl::Continue
+                self.write(&varnode, value)?;
             }
             Opcode::IntSBorrow => {
                 let [input0, input1] = pcode.vars.as_slice() else {
-                    bail!("expected 2 inputs");
+                    return Err(decode_error("expected 2 inputs"));
                 };
-                let output = pcode.outvar.as_ref()
-                    .context("expected output")?;
+                let output = output_of(pcode)?;
 
-                assert_eq!(input0.size, input1.size, "inputs must have be the same size");
-                assert_eq!(output.size, 1, "output must be 1 byte");
+                expect_size(input1, input0.size)?;
+                expect_size(output, 1)?;
 
-                let left: BigInt = self.read(input0);
-                let right: BigInt = self.read(input1);
+                // the signed difference doesn't fit in the inputs' size
+                let left: BigInt = self.read(input0)?;
+                let right: BigInt = self.read(input1)?;
                 let result = left - right;
-                let overflow = result.bits() > u64::from(input0.size);
-                self.write(output, overflow);
-                PCodeControl::Continue
+                let overflow = !fits_signed(&result, input0.size);
+                self.write(output, overflow)?;
             }
             Opcode::IntLess => {
                 let [input0, input1] = pcode.vars.as_slice() else {
-                    bail!("expected 2 inputs");
+                    return Err(decode_error("expected 2 inputs"));
                 };
-                let output = pcode.outvar.as_ref()
-                    .context("expected output")?;
+                let output = output_of(pcode)?;
 
-                assert_eq!(input0.size, input1.size, "inputs must have be the same size");
+                expect_size(input1, input0.size)?;
+                expect_size(output, 1)?;
 
-                let left: BigUint = self.read(input0);
-                let right: BigUint = self.read(input1);
-                self.write(output, left < right);
-                PCodeControl::Continue
+                let left: BigUint = self.read(input0)?;
+                let right: BigUint = self.read(input1)?;
+                self.write(output, left < right)?;
             }
             Opcode::IntSLess => {
                 let [input0, input1] = pcode.vars.as_slice() else {
-                    bail!("expected 2 inputs");
+                    return Err(decode_error("expected 2 inputs"));
                 };
-                let output = pcode.outvar.as_ref()
-                    .context("expected output")?;
+                let output = output_of(pcode)?;
 
-                assert_eq!(input0.size, input1.size, "inputs must have be the same size");
-                let left: BigInt = self.read(input0);
-                let right: BigInt = self.read(input1);
-                self.write(output, left < right);
-                PCodeControl::Continue
+                expect_size(input1, input0.size)?;
+                expect_size(output, 1)?;
+                let left: BigInt = self.read(input0)?;
+                let right: BigInt = self.read(input1)?;
+                self.write(output, left < right)?;
             }
             Opcode::IntEqual => {
                 let [input0, input1] = pcode.vars.as_slice() else {
-                    bail!("expected 2 inputs");
+                    return Err(decode_error("expected 2 inputs"));
                 };
-                let output = pcode.outvar.as_ref()
-                    .context("expected output")?;
+                let output = output_of(pcode)?;
 
-                assert_eq!(input0.size, input1.size, "inputs must have be the same size");
-                assert_eq!(output.size, 1, "output must be 1 byte");
+                expect_size(input1, input0.size)?;
+                expect_size(output, 1)?;
 
-                let left: BigUint = self.read(input0);
-                let right: BigUint = self.read(input1);
+                let left: BigUint = self.read(input0)?;
+                let right: BigUint = self.read(input1)?;
                 let result = left == right;
-                self.write(output, result);
-                PCodeControl::Continue
+                self.write(output, result)?;
+            }
+            Opcode::IntNotEqual => {
+                let [input0, input1] = pcode.vars.as_slice() else {
+                    return Err(decode_error("expected 2 inputs"));
+                };
+                let output = output_of(pcode)?;
+
+                expect_size(input1, input0.size)?;
+                expect_size(output, 1)?;
+
+                let left: BigUint = self.read(input0)?;
+                let right: BigUint = self.read(input1)?;
+                self.write(output, left != right)?;
+            }
+            Opcode::IntLessEqual => {
+                let [input0, input1] = pcode.vars.as_slice() else {
+                    return Err(decode_error("expected 2 inputs"));
+                };
+                let output = output_of(pcode)?;
+
+                expect_size(input1, input0.size)?;
+                expect_size(output, 1)?;
+                let left: BigUint = self.read(input0)?;
+                let right: BigUint = self.read(input1)?;
+                self.write(output, left <= right)?;
+            }
+            Opcode::IntSLessEqual => {
+                let [input0, input1] = pcode.vars.as_slice() else {
+                    return Err(decode_error("expected 2 inputs"));
+                };
+                let output = output_of(pcode)?;
+
+                expect_size(input1, input0.size)?;
+                expect_size(output, 1)?;
+                let left: BigInt = self.read(input0)?;
+                let right: BigInt = self.read(input1)?;
+                self.write(output, left <= right)?;
             }
             Opcode::IntAnd => {
                 let [input0, input1] = pcode.vars.as_slice() else {
-                    bail!("expected 2 inputs");
+                    return Err(decode_error("expected 2 inputs"));
                 };
-                let output = pcode.outvar.as_ref()
-                    .context("expected output")?;
+                let output = output_of(pcode)?;
 
-                assert_eq!(input0.size, input1.size, "inputs must have be the same size");
-                let left: BigUint = self.read(input0);
-                let right: BigUint = self.read(input1);
+                expect_size(input1, input0.size)?;
+                let left: BigUint = self.read(input0)?;
+                let right: BigUint = self.read(input1)?;
                 let result = &left & &right;
-                self.write(output, result);
-                PCodeControl::Continue
+                self.write(output, result)?;
             }
             Opcode::PopCount => {
                 let [input0] = pcode.vars.as_slice() else {
-                    bail!("expected 1 input");
+                    return Err(decode_error("expected 1 input"));
                 };
-                let output = pcode.outvar.as_ref()
-                    .context("expected output")?;
+                let output = output_of(pcode)?;
 
-                let value: BigUint = self.read(input0);
+                let value: BigUint = self.read(input0)?;
                 let result = value.count_ones();
-                self.write(output, result);
-                PCodeControl::Continue
+                self.write(output, result)?;
             }
-            Opcode::Branch => {
-                let [addr] = pcode.vars.as_slice() else {
-                    bail!("expected 1 input");
+            Opcode::LzCount => {
+                let [input0] = pcode.vars.as_slice() else {
+                    return Err(decode_error("expected 1 input"));
                 };
-                println!("  branch to {:X}", addr.offset);
-                PCodeControl::Branch(addr.offset)
+                let output = output_of(pcode)?;
+
+                let value: BigUint = self.read(input0)?;
+                let result = u64::from(input0.size) * 8 - value.bits();
+                self.write(output, result)?;
             }
             Opcode::IntAdd => {
                 let [input0, input1] = pcode.vars.as_slice() else {
-                    bail!("expected 2 inputs");
+                    return Err(decode_error("expected 2 inputs"));
                 };
-                let output = pcode.outvar.as_ref()
-                    .context("expected output")?;
+                let output = output_of(pcode)?;
 
-                let left: BigInt = self.read(input0);
-                let right: BigInt = self.read(input1);
+                expect_size(input1, input0.size)?;
+                let left: BigInt = self.read(input0)?;
+                let right: BigInt = self.read(input1)?;
                 let result = &left + &right;
-                self.write(output, result);
-                PCodeControl::Continue
+                self.write(output, result)?;
             }
-            Opcode::Load => {
+            Opcode::IntMult => {
                 let [input0, input1] = pcode.vars.as_slice() else {
-                    bail!("expected 2 inputs");
+                    return Err(decode_error("expected 2 inputs"));
                 };
-                let output = pcode.outvar.as_ref()
-                    .context("expected output")?;
+                let output = output_of(pcode)?;
 
-                let space = self.get_space_from_const(input0)?;
-                let offset: u64 = self.read(input1);
-                let offset = offset * u64::from(space.wordsize); // offset to bytes
-                let varnode = VarnodeData { space, offset, size: output.size };
+                expect_size(input1, input0.size)?;
+                // the low bits of the product are the same for signed and unsigned inputs
+                let left: BigUint = self.read(input0)?;
+                let right: BigUint = self.read(input1)?;
+                let result = &left * &right;
+                self.write(output, result)?;
+            }
+            Opcode::IntDiv => {
+                let [input0, input1] = pcode.vars.as_slice() else {
+                    return Err(decode_error("expected 2 inputs"));
+                };
+                let output = output_of(pcode)?;
 
-                let bytes: BigUint = self.read(&varnode);
-                self.write(output, bytes);
+                expect_size(input1, input0.size)?;
+                let left: BigUint = self.read(input0)?;
+                let right: BigUint = self.read(input1)?;
+                if right.is_zero() {
+                    return Err(ErrorKind::DivisionByZero);
+                }
+                let result = &left / &right;
+                self.write(output, result)?;
+            }
+            Opcode::IntSDiv => {
+                let [input0, input1] = pcode.vars.as_slice() else {
+                    return Err(decode_error("expected 2 inputs"));
+                };
+                let output = output_of(pcode)?;
 
-                PCodeControl::Continue
+                expect_size(input1, input0.size)?;
+                let left: BigInt = self.read(input0)?;
+                let right: BigInt = self.read(input1)?;
+                if right.is_zero() {
+                    return Err(ErrorKind::DivisionByZero);
+                }
+                // BigInt division truncates toward zero, MIN / -1 wraps when written back
+                let result = &left / &right;
+                self.write(output, result)?;
             }
-            Opcode::Call => {
-                let [input0, _args @ ..] = pcode.vars.as_slice() else {
-                    bail!("expected at least 1 input");
+            Opcode::IntRem => {
+                let [input0, input1] = pcode.vars.as_slice() else {
+                    return Err(decode_error("expected 2 inputs"));
                 };
-                PCodeControl::Branch(input0.offset)
+                let output = output_of(pcode)?;
+
+                expect_size(input1, input0.size)?;
+                let left: BigUint = self.read(input0)?;
+                let right: BigUint = self.read(input1)?;
+                if right.is_zero() {
+                    return Err(ErrorKind::DivisionByZero);
+                }
+                let result = &left % &right;
+                self.write(output, result)?;
             }
-            Opcode::IntCarry => {
+            Opcode::IntSRem => {
                 let [input0, input1] = pcode.vars.as_slice() else {
-                    bail!("expected 2 inputs");
+                    return Err(decode_error("expected 2 inputs"));
                 };
-                let output = pcode.outvar.as_ref()
-                    .context("expected output")?;
+                let output = output_of(pcode)?;
 
-                assert_eq!(input0.size, input1.size, "inputs must have be the same size");
+                expect_size(input1, input0.size)?;
+                let left: BigInt = self.read(input0)?;
+                let right: BigInt = self.read(input1)?;
+                if right.is_zero() {
+                    return Err(ErrorKind::DivisionByZero);
+                }
+                // the remainder takes the sign of the dividend
+                let result = &left % &right;
+                self.write(output, result)?;
+            }
+            Opcode::IntLeft => {
+                let [input0, input1] = pcode.vars.as_slice() else {
+                    return Err(decode_error("expected 2 inputs"));
+                };
+                let output = output_of(pcode)?;
 
-                let left: BigUint = self.read(input0);
-                let right: BigUint = self.read(input1);
-                let result = &left + &right;
-                let carry = result.bits() > input0.size as u64;
-                self.write(output, carry);
-                PCodeControl::Continue
+                expect_size(output, input0.size)?;
+                // the shift amount is unsigned and may be any size
+                let value: BigUint = self.read(input0)?;
+                let amount: BigUint = self.read(input1)?;
+                let result = match shift_amount(&amount, input0.size) {
+                    Some(amount) => value << amount,
+                    None => BigUint::zero(),
+                };
+                self.write(output, result)?;
             }
-            Opcode::IntSCarry => {
+            Opcode::IntRight => {
                 let [input0, input1] = pcode.vars.as_slice() else {
-                    bail!("expected 2 inputs");
+                    return Err(decode_error("expected 2 inputs"));
                 };
-                let output = pcode.outvar.as_ref()
-                    .context("expected output")?;
+                let output = output_of(pcode)?;
 
-                assert_eq!(input0.size, input1.size, "inputs must have be the same size");
+                expect_size(output, input0.size)?;
+                let value: BigUint = self.read(input0)?;
+                let amount: BigUint = self.read(input1)?;
+                let result = match shift_amount(&amount, input0.size) {
+                    Some(amount) => value >> amount,
+                    None => BigUint::zero(),
+                };
+                self.write(output, result)?;
+            }
+            Opcode::IntSRight => {
+                let [input0, input1] = pcode.vars.as_slice() else {
+                    return Err(decode_error("expected 2 inputs"));
+                };
+                let output = output_of(pcode)?;
 
-                let left: BigInt = self.read(input0);
-                let right: BigInt = self.read(input1);
-                let result = &left + &right;
-                let carry = result.bits() > input0.size as u64;
-                self.write(output, carry);
-                PCodeControl::Continue
+                expect_size(output, input0.size)?;
+                let value: BigInt = self.read(input0)?;
+                let amount: BigUint = self.read(input1)?;
+                // shifting a BigInt right rounds toward negative infinity, filling with the sign bit
+                let result = match shift_amount(&amount, input0.size) {
+                    Some(amount) => value >> amount,
+                    None if value.is_negative() => BigInt::from(-1),
+                    None => BigInt::zero(),
+                };
+                self.write(output, result)?;
             }
-            Opcode::CBranch => {
+            Opcode::Load => {
                 let [input0, input1] = pcode.vars.as_slice() else {
-                    bail!("expected 2 inputs");
+                    return Err(decode_error("expected 2 inputs"));
                 };
+                let output = output_of(pcode)?;
 
-                let condition: BigUint = self.read(input1);
-                if condition != BigUint::zero() {
-                    println!("  branch to {:X}", input0.offset);
-                    PCodeControl::Branch(input0.offset)
-                } else {
-                    println!("  fall through");
-                    PCodeControl::Continue
-                }
+                let space = self.get_space_from_const(input0)?;
+                let offset = pointer_offset(&space, self.read(input1)?);
+                let varnode = VarnodeData { space, offset, size: output.size };
+
+                let bytes: BigUint = self.read(&varnode)?;
+                self.write(output, bytes)?;
             }
-            Opcode::Return => {
-                let [input0, _values @ ..] = pcode.vars.as_slice() else {
-                    bail!("expected at least 1 input");
+            Opcode::IntCarry => {
+                let [input0, input1] = pcode.vars.as_slice() else {
+                    return Err(decode_error("expected 2 inputs"));
                 };
-                let off: u64 = self.read(input0);
-                println!("  return to {:X}", off);
-                PCodeControl::Branch(off)
+                let output = output_of(pcode)?;
+
+                expect_size(input1, input0.size)?;
+                expect_size(output, 1)?;
+
+                // the unsigned sum doesn't fit in the inputs' size
+                let left: BigUint = self.read(input0)?;
+                let right: BigUint = self.read(input1)?;
+                let result = &left + &right;
+                let carry = result.bits() > u64::from(input0.size) * 8;
+                self.write(output, carry)?;
+            }
+            Opcode::IntSCarry => {
+                let [input0, input1] = pcode.vars.as_slice() else {
+                    return Err(decode_error("expected 2 inputs"));
+                };
+                let output = output_of(pcode)?;
+
+                expect_size(input1, input0.size)?;
+                expect_size(output, 1)?;
+
+                // the signed sum doesn't fit in the inputs' size
+                let left: BigInt = self.read(input0)?;
+                let right: BigInt = self.read(input1)?;
+                let result = &left + &right;
+                let overflow = !fits_signed(&result, input0.size);
+                self.write(output, overflow)?;
             }
             Opcode::IntXor => {
                 let [input0, input1] = pcode.vars.as_slice() else {
-                    bail!("expected 2 inputs");
+                    return Err(decode_error("expected 2 inputs"));
                 };
-                let output = pcode.outvar.as_ref()
-                    .context("expected output")?;
+                let output = output_of(pcode)?;
 
-                assert_eq!(input0.size, input1.size, "inputs must have be the same size");
-                let left: BigUint = self.read(input0);
-                let right: BigUint = self.read(input1);
+                expect_size(input1, input0.size)?;
+                let left: BigUint = self.read(input0)?;
+                let right: BigUint = self.read(input1)?;
                 let result = &left ^ &right;
-                self.write(output, result);
-                PCodeControl::Continue
+                self.write(output, result)?;
             }
             Opcode::IntOr => {
                 let [input0, input1] = pcode.vars.as_slice() else {
-                    bail!("expected 2 inputs");
+                    return Err(decode_error("expected 2 inputs"));
                 };
-                let output = pcode.outvar.as_ref()
-                    .context("expected output")?;
+                let output = output_of(pcode)?;
 
-                assert_eq!(input0.size, input1.size, "inputs must have be the same size");
-                let left: BigUint = self.read(input0);
-                let right: BigUint = self.read(input1);
+                expect_size(input1, input0.size)?;
+                let left: BigUint = self.read(input0)?;
+                let right: BigUint = self.read(input1)?;
                 let result = &left | &right;
-                self.write(output, result);
-                PCodeControl::Continue
+                self.write(output, result)?;
             }
             Opcode::IntZExt => {
                 let [input0] = pcode.vars.as_slice() else {
-                    bail!("expected 1 input");
+                    return Err(decode_error("expected 1 input"));
                 };
-                let output = pcode.outvar.as_ref()
-                    .context("expected output")?;
+                let output = output_of(pcode)?;
 
-                let value: BigUint = self.read(input0);
-                self.write(output, value);
-                PCodeControl::Continue
+                let value: BigUint = self.read(input0)?;
+                self.write(output, value)?;
+            }
+            Opcode::IntSExt => {
+                let [input0] = pcode.vars.as_slice() else {
+                    return Err(decode_error("expected 1 input"));
+                };
+                let output = output_of(pcode)?;
+
+                // writing a BigInt fills the upper bytes with the sign
+                let value: BigInt = self.read(input0)?;
+                self.write(output, value)?;
+            }
+            Opcode::Piece => {
+                let [input0, input1] = pcode.vars.as_slice() else {
+                    return Err(decode_error("expected 2 inputs"));
+                };
+                let output = output_of(pcode)?;
+
+                expect_size(output, input0.size + input1.size)?;
+                // input0 makes up the most significant part of the output
+                let high: BigUint = self.read(input0)?;
+                let low: BigUint = self.read(input1)?;
+                let result = (high << (input1.size as usize * 8)) | low;
+                self.write(output, result)?;
+            }
+            Opcode::SubPiece => {
+                let [input0, input1] = pcode.vars.as_slice() else {
+                    return Err(decode_error("expected 2 inputs"));
+                };
+                let output = output_of(pcode)?;
+
+                if input1.space.type_ != SpaceType::Constant {
+                    return Err(decode_error("expected input1 to be a constant"));
+                }
+                // input1 is the number of least significant bytes to throw away, the value is
+                // truncated to the output size when written
+                let value: BigUint = self.read(input0)?;
+                let result = value >> (input1.offset as usize * 8);
+                self.write(output, result)?;
+            }
+            Opcode::Int2Comp => {
+                let [input0] = pcode.vars.as_slice() else {
+                    return Err(decode_error("expected 1 input"));
+                };
+                let output = output_of(pcode)?;
+
+                expect_size(output, input0.size)?;
+                let value: BigInt = self.read(input0)?;
+                let result = -value;
+                self.write(output, result)?;
+            }
+            Opcode::IntNegate => {
+                let [input0] = pcode.vars.as_slice() else {
+                    return Err(decode_error("expected 1 input"));
+                };
+                let output = output_of(pcode)?;
+
+                expect_size(output, input0.size)?;
+                let value: BigUint = self.read(input0)?;
+                let mask = (BigUint::one() << (input0.size as usize * 8)) - 1u32;
+                let result = value ^ mask;
+                self.write(output, result)?;
             }
             Opcode::BoolOr => {
                 let [input0, input1] = pcode.vars.as_slice() else {
-                    bail!("expected 2 inputs");
+                    return Err(decode_error("expected 2 inputs"));
                 };
-                let output = pcode.outvar.as_ref()
-                    .context("expected output")?;
+                let output = output_of(pcode)?;
 
-                let left: bool = self.read(input0);
-                let right: bool = self.read(input1);
+                let left: bool = self.read(input0)?;
+                let right: bool = self.read(input1)?;
                 let result = left | right;
-                self.write(output, result);
-                PCodeControl::Continue
+                self.write(output, result)?;
             }
             Opcode::BoolXor => {
                 let [input0, input1] = pcode.vars.as_slice() else {
-                    bail!("expected 2 inputs");
+                    return Err(decode_error("expected 2 inputs"));
                 };
-                let output = pcode.outvar.as_ref()
-                    .context("expected output")?;
+                let output = output_of(pcode)?;
 
-                let left: bool = self.read(input0);
-                let right: bool = self.read(input1);
+                let left: bool = self.read(input0)?;
+                let right: bool = self.read(input1)?;
                 let result = left ^ right;
-                self.write(output, result);
-                PCodeControl::Continue
+                self.write(output, result)?;
             }
             Opcode::BoolNegate => {
                 let [input0] = pcode.vars.as_slice() else {
-                    bail!("expected 1 input");
+                    return Err(decode_error("expected 1 input"));
                 };
-                let output = pcode.outvar.as_ref()
-                    .context("expected output")?;
+                let output = output_of(pcode)?;
 
-                let value: bool = self.read(input0);
+                let value: bool = self.read(input0)?;
                 let result = !&value;
-                self.write(output, result);
-                PCodeControl::Continue
+                self.write(output, result)?;
             }
             Opcode::BoolAnd => {
                 let [input0, input1] = pcode.vars.as_slice() else {
-                    bail!("expected 2 inputs");
+                    return Err(decode_error("expected 2 inputs"));
                 };
-                let output = pcode.outvar.as_ref()
-                    .context("expected output")?;
+                let output = output_of(pcode)?;
 
-                let left: bool = self.read(input0);
-                let right: bool = self.read(input1);
+                let left: bool = self.read(input0)?;
+                let right: bool = self.read(input1)?;
                 let result = left & right;
-                self.write(output, result);
-                PCodeControl::Continue
+                self.write(output, result)?;
             }
-            _ => bail!("unimplemented opcode: {:?}", pcode.opcode),
-        };
+            _ => return Err(ErrorKind::UnimplementedOpcode(pcode.opcode)),
+        }
 
-        Ok(control)
+        Ok(())
+    }
+}
+
+/// Converts a pcode shift amount to a bit count, returning `None` if it shifts out every bit of a
+/// varnode with `size` bytes.
+fn shift_amount(amount: &BigUint, size: u32) -> Option<usize> {
+    amount.to_usize()
+        .filter(|amount| *amount < size as usize * 8)
+}
+
+
+/// Whether `value` is representable as a signed integer of `size` bytes
+fn fits_signed(value: &BigInt, size: u32) -> bool {
+    let limit = BigInt::one() << (size as usize * 8 - 1);
+    -&limit <= *value && *value < limit
+}
+
+/// The float encoding of a varnode, which sleigh selects by size
+fn float_format(node: &VarnodeData) -> Result<FloatFormat, ErrorKind> {
+    FloatFormat::from_size(node.size)
+        .ok_or_else(|| decode_error(format!("unsupported float size: {}", node.size)))
+}
+
+/// The byte offset a LOAD or STORE pointer refers to in a space that counts in words, wrapped
+/// around the space like any other access
+pub(crate) fn pointer_offset(space: &AddrSpace, pointer: u64) -> u64 {
+    space::wrap(pointer.wrapping_mul(u64::from(space.wordsize)), 0, space.highest)
+}
+
+pub(crate) fn decode_error(message: impl Into<String>) -> ErrorKind {
+    ErrorKind::Decode(message.into())
+}
+
+/// The output of an op, which must have one
+pub(crate) fn output_of(pcode: &PCode) -> Result<&VarnodeData, ErrorKind> {
+    pcode.outvar.as_ref()
+        .ok_or_else(|| decode_error("expected output"))
+}
+
+/// Checks that a varnode is `size` bytes
+pub(crate) fn expect_size(node: &VarnodeData, size: u32) -> Result<(), ErrorKind> {
+    if node.size == size {
+        Ok(())
+    } else {
+        Err(ErrorKind::SizeMismatch { expected: size, found: node.size })
     }
 }
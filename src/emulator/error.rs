@@ -0,0 +1,118 @@
+use std::fmt::{Display, Formatter};
+use sleigh::Opcode;
+use crate::emulator::space::Access;
+
+/// An error raised while emulating pcode, at the instruction (and pcode op) that caused it
+#[derive(Debug)]
+pub struct EmulationError {
+    /// the address of the instruction being emulated
+    pub address: u64,
+    /// the index of the pcode op within the instruction, if one was being emulated
+    pub index: Option<usize>,
+    /// the address relative to the symbol it's in, like `main+0x1c`, if the binary knows one
+    pub symbol: Option<String>,
+    /// what went wrong
+    pub kind: ErrorKind,
+}
+
+#[derive(Debug)]
+pub enum ErrorKind {
+    /// an access to an address that isn't mapped
+    UnmappedMemory {
+        /// the name of the space accessed
+        space: String,
+        /// the offset into the space
+        offset: u64,
+        access: Access,
+    },
+    /// an access to mapped memory that its permissions don't allow
+    AccessViolation {
+        space: String,
+        offset: u64,
+        access: Access,
+    },
+    /// an opcode the emulator doesn't implement
+    UnimplementedOpcode(Opcode),
+    /// a varnode that isn't the size the op requires
+    SizeMismatch {
+        expected: u32,
+        found: u32,
+    },
+    /// a varnode in a space the emulator doesn't have, or can't write to
+    InvalidSpace(String),
+    /// malformed pcode, or a value that can't be decoded as what the op requires
+    Decode(String),
+    /// an integer division or remainder by zero
+    DivisionByZero,
+    /// emulation ran past the last pcode op that was lifted
+    EndOfCode,
+    /// a register name the language doesn't have
+    UnknownRegister(String),
+    /// a user-defined op that has no handler registered
+    UnhandledUserOp(String),
+    /// the handler for a user-defined op failed
+    UserOp {
+        name: String,
+        error: anyhow::Error,
+    },
+    /// a memory hook or MMIO handler failed
+    Hook {
+        /// the address of the access
+        address: u64,
+        error: anyhow::Error,
+    },
+}
+
+impl EmulationError {
+    pub fn new(address: u64, index: Option<usize>, kind: ErrorKind) -> Self {
+        Self { address, index, symbol: None, kind }
+    }
+
+    /// Names the symbol the error happened in
+    pub fn with_symbol(self, symbol: Option<String>) -> Self {
+        Self { symbol, ..self }
+    }
+}
+
+impl Display for EmulationError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} at {:0>8X}", self.kind, self.address)?;
+        if let Some(index) = self.index {
+            write!(f, ".{:0>2X}", index)?;
+        }
+        if let Some(symbol) = &self.symbol {
+            write!(f, " ({})", symbol)?;
+        }
+        Ok(())
+    }
+}
+
+impl Display for ErrorKind {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ErrorKind::UnmappedMemory { space, offset, access } => write!(f, "{} of unmapped memory at {}:{:X}", access, space, offset),
+            ErrorKind::AccessViolation { space, offset, access } => write!(f, "{} access violation at {}:{:X}", access, space, offset),
+            ErrorKind::UnimplementedOpcode(opcode) => write!(f, "unimplemented opcode: {:?}", opcode),
+            ErrorKind::SizeMismatch { expected, found } => write!(f, "expected a {} byte varnode, found {} bytes", expected, found),
+            ErrorKind::InvalidSpace(name) => write!(f, "invalid space: {:?}", name),
+            ErrorKind::Decode(message) => write!(f, "unable to decode pcode: {}", message),
+            ErrorKind::DivisionByZero => write!(f, "division by zero"),
+            ErrorKind::EndOfCode => write!(f, "ran off the end of the code"),
+            ErrorKind::UnknownRegister(name) => write!(f, "unknown register: {}", name),
+            ErrorKind::UnhandledUserOp(name) => write!(f, "unhandled user-defined op: {}", name),
+            ErrorKind::UserOp { name, error } => write!(f, "user-defined op {} failed: {:#}", name, error),
+            ErrorKind::Hook { address, error } => write!(f, "memory hook at {:X} failed: {:#}", address, error),
+        }
+    }
+}
+
+impl std::error::Error for EmulationError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match &self.kind {
+            ErrorKind::UserOp { error, .. } | ErrorKind::Hook { error, .. } => Some(error.as_ref()),
+            _ => None,
+        }
+    }
+}
+
+impl std::error::Error for ErrorKind {}
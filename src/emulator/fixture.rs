@@ -0,0 +1,72 @@
+//!
+//! Fixtures shared by the emulator's tests: a 32 bit x86 machine with no code, and varnodes in
+//! its spaces.
+
+use hashbrown::HashMap;
+use sleigh::{AddrSpace, Decompiler, VarnodeData, X86Mode};
+use crate::binary::{Binary, ElfClass, ElfHeader, Header, SymbolIndex};
+use crate::emulator::Machine;
+
+pub(crate) fn empty_binary() -> Binary {
+    Binary {
+        bytes: vec![],
+        // a 32 bit x86 executable
+        header: Header::Elf(ElfHeader {
+            class: ElfClass::Elf32,
+            big_endian: false,
+            os_abi: 0,
+            abi_version: 0,
+            kind: 2,
+            machine: 3,
+            version: 1,
+            entry: 0,
+            flags: 0,
+        }),
+        segments: vec![],
+        sections: HashMap::new(),
+        symbols: HashMap::new(),
+        index: SymbolIndex::default(),
+        imports: vec![],
+    }
+}
+
+pub(crate) fn machine(binary: &Binary) -> Machine<'_> {
+    let mut machine = Machine::new(binary).expect("unable to create machine");
+    // the emulator starts at the first group of pcodes, so give it an empty one
+    machine.pcodes.insert(0, vec![]);
+    machine
+}
+
+thread_local! {
+    static SPACES: Vec<AddrSpace> = Decompiler::builder().x86(X86Mode::Mode32).build().get_spaces();
+}
+
+/// The x86 space called `name`
+pub(crate) fn space(name: &str) -> AddrSpace {
+    SPACES.with(|spaces| spaces.iter().find(|space| space.name == name).cloned())
+        .expect("no such space")
+}
+
+pub(crate) fn unique(offset: u64, size: u32) -> VarnodeData {
+    VarnodeData { space: space("unique"), offset, size }
+}
+
+pub(crate) fn constant(value: u64, size: u32) -> VarnodeData {
+    VarnodeData { space: space("const"), offset: value, size }
+}
+
+/// Declares `$name` as an emulator for a machine with no code. The binary and machine it borrows
+/// are declared alongside it, so they live until the end of the enclosing block.
+macro_rules! emulator {
+    (mut $name:ident) => {
+        let binary = $crate::emulator::fixture::empty_binary();
+        let machine = $crate::emulator::fixture::machine(&binary);
+        let mut $name = $crate::emulator::Emulator::new(&machine, 0, 0).unwrap();
+    };
+    ($name:ident) => {
+        let binary = $crate::emulator::fixture::empty_binary();
+        let machine = $crate::emulator::fixture::machine(&binary);
+        let $name = $crate::emulator::Emulator::new(&machine, 0, 0).unwrap();
+    };
+}
+pub(crate) use emulator;
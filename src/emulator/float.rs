@@ -0,0 +1,636 @@
+//!
+//! Encoding and decoding of the floating point formats sleigh uses for float varnodes.
+//!
+//! Values are split into a sign, exponent and integer significand, and conversions and arithmetic
+//! are done on those with integer arithmetic, rounding once to nearest even. Unlike Ghidra's
+//! `FloatFormat`, which computes on host doubles, this is bit-exact for extended (x87) values,
+//! whose 64-bit significands don't fit in a double. Invalid operations give the x86 default nan.
+//!
+//! The x87 register stack needs no special handling here: sleigh models ST0-ST7 as 10-byte
+//! registers in the register space and performs the stack rotation itself in pcode.
+
+use std::cmp::Ordering;
+use num::{BigUint, ToPrimitive};
+
+/// A floating point encoding, selected by the size of a varnode
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FloatFormat {
+    /// IEEE-754 binary32
+    Single,
+    /// IEEE-754 binary64
+    Double,
+    /// x87 80-bit extended precision, with an explicit integer bit
+    Extended,
+}
+
+/// An encoded value split into its parts
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Parts {
+    /// `significand * 2^exponent`, including zero
+    Finite {
+        negative: bool,
+        exponent: i32,
+        significand: u128,
+    },
+    Infinity {
+        negative: bool,
+    },
+    /// the fraction bits, aligned to the top of a `u64`
+    Nan {
+        negative: bool,
+        payload: u64,
+    },
+}
+
+impl FloatFormat {
+    pub fn from_size(size: u32) -> Option<Self> {
+        match size {
+            4 => Some(Self::Single),
+            8 => Some(Self::Double),
+            10 => Some(Self::Extended),
+            _ => None,
+        }
+    }
+
+    /// the size of the encoding in bytes
+    pub fn size(self) -> u32 {
+        match self {
+            Self::Single => 4,
+            Self::Double => 8,
+            Self::Extended => 10,
+        }
+    }
+
+    /// the position of the sign bit in the encoding
+    pub fn sign_bit(self) -> u32 {
+        self.size() * 8 - 1
+    }
+
+    /// the number of fraction bits, not counting the integer bit
+    fn fraction_bits(self) -> u32 {
+        match self {
+            Self::Single => 23,
+            Self::Double => 52,
+            Self::Extended => 63,
+        }
+    }
+
+    /// the position of the lowest bit of the exponent
+    fn exponent_shift(self) -> u32 {
+        match self {
+            Self::Extended => 64,
+            _ => self.fraction_bits(),
+        }
+    }
+
+    /// the largest biased exponent, used by infinities and nans
+    fn max_exponent(self) -> u128 {
+        (1 << (self.sign_bit() - self.exponent_shift())) - 1
+    }
+
+    fn bias(self) -> i32 {
+        (self.max_exponent() >> 1) as i32
+    }
+
+    /// the integer bit, which only extended precision stores
+    fn integer_bit(self) -> u128 {
+        match self {
+            Self::Extended => 1 << 63,
+            _ => 0,
+        }
+    }
+
+    fn fraction_mask(self) -> u128 {
+        (1 << self.fraction_bits()) - 1
+    }
+
+    fn unpack(self, bits: u128) -> Parts {
+        let negative = (bits >> self.sign_bit()) & 1 != 0;
+        let biased = (bits >> self.exponent_shift()) & self.max_exponent();
+        let fraction = bits & self.fraction_mask();
+        if biased == self.max_exponent() {
+            // the integer bit is ignored for infinities and nans
+            return if fraction == 0 {
+                Parts::Infinity { negative }
+            } else {
+                Parts::Nan { negative, payload: (fraction << (64 - self.fraction_bits())) as u64 }
+            };
+        }
+
+        let significand = match self {
+            Self::Extended => bits & u128::from(u64::MAX),
+            _ if biased == 0 => fraction,
+            _ => fraction | (1 << self.fraction_bits()),
+        };
+        // subnormals have the exponent of the smallest normal
+        let exponent = (biased as i32).max(1) - self.bias() - self.fraction_bits() as i32;
+        Parts::Finite { negative, exponent, significand }
+    }
+
+    fn pack(self, parts: Parts) -> u128 {
+        let infinity = (self.max_exponent() << self.exponent_shift()) | self.integer_bit();
+        let (negative, magnitude) = match parts {
+            Parts::Infinity { negative } => (negative, infinity),
+            // converting a nan keeps the top of its payload and makes it quiet
+            Parts::Nan { negative, payload } => {
+                let quiet = 1 << (self.fraction_bits() - 1);
+                (negative, infinity | quiet | (u128::from(payload) >> (64 - self.fraction_bits())))
+            }
+            Parts::Finite { negative, significand: 0, .. } => (negative, 0),
+            Parts::Finite { negative, exponent, significand } => (negative, self.encode_magnitude(exponent, significand)),
+        };
+        (u128::from(negative) << self.sign_bit()) | magnitude
+    }
+
+    /// Encodes the magnitude `significand * 2^exponent`, rounding to nearest even
+    fn encode_magnitude(self, exponent: i32, significand: u128) -> u128 {
+        let fraction_bits = self.fraction_bits() as i32;
+        let top = 127 - significand.leading_zeros() as i32;
+        let mut biased = (exponent + top + self.bias()).max(0);
+
+        // the encoding holds `mantissa * 2^(biased - bias - fraction_bits)`, with subnormals
+        // using the exponent of the smallest normal
+        let shift = biased.max(1) - self.bias() - fraction_bits - exponent;
+        let mut mantissa = if shift > 0 {
+            shift_right_even(significand, shift as u32)
+        } else {
+            significand << -shift
+        };
+        if mantissa >> (fraction_bits + 1) != 0 {
+            // rounding up carried into a new top bit
+            mantissa >>= 1;
+            biased += 1;
+        } else if biased == 0 && mantissa >> fraction_bits != 0 {
+            // a subnormal rounded up to the smallest normal
+            biased = 1;
+        }
+
+        if biased as u128 >= self.max_exponent() {
+            return (self.max_exponent() << self.exponent_shift()) | self.integer_bit();
+        }
+        let mantissa = match self {
+            Self::Extended => mantissa,
+            _ => mantissa & self.fraction_mask(),
+        };
+        ((biased as u128) << self.exponent_shift()) | mantissa
+    }
+
+    /// Converts an encoded value to another format, rounding to nearest even. Nans stay nans,
+    /// keeping as much of their payload as fits.
+    pub fn convert(self, bits: u128, to: FloatFormat) -> u128 {
+        to.pack(self.unpack(bits))
+    }
+
+    /// Encodes a signed integer, rounding to nearest even
+    pub fn from_int(self, value: i128) -> u128 {
+        self.pack(Parts::Finite { negative: value < 0, exponent: 0, significand: value.unsigned_abs() })
+    }
+
+    /// Truncates an encoded value toward zero, or returns `None` if it's a nan, infinite, or too
+    /// big for an `i128`
+    pub fn trunc(self, bits: u128) -> Option<i128> {
+        let Parts::Finite { negative, exponent, significand } = self.unpack(bits) else {
+            return None;
+        };
+        let magnitude = if exponent >= 0 {
+            // every bit has to survive the shift
+            let shifted = significand.checked_shl(exponent as u32)?;
+            (shifted >> exponent == significand).then_some(shifted)?
+        } else {
+            significand.checked_shr(exponent.unsigned_abs()).unwrap_or(0)
+        };
+        if negative {
+            (magnitude <= 1 << 127).then(|| magnitude.wrapping_neg() as i128)
+        } else {
+            i128::try_from(magnitude).ok()
+        }
+    }
+
+    /// Decodes an encoded value into a host double, rounding to nearest if the format is wider
+    pub fn decode(self, bits: u128) -> f64 {
+        f64::from_bits(self.convert(bits, Self::Double) as u64)
+    }
+
+    /// Encodes a host double, rounding to nearest if the format is narrower
+    pub fn encode(self, value: f64) -> u128 {
+        Self::Double.convert(u128::from(value.to_bits()), self)
+    }
+
+    /// Whether an encoded value is not a number
+    pub fn is_nan(self, bits: u128) -> bool {
+        matches!(self.unpack(bits), Parts::Nan { .. })
+    }
+
+    pub fn add(self, left: u128, right: u128) -> u128 {
+        self.pack(self.unpack(left).add(self.unpack(right)))
+    }
+
+    pub fn sub(self, left: u128, right: u128) -> u128 {
+        self.pack(self.unpack(left).add(self.unpack(right).negate()))
+    }
+
+    pub fn mul(self, left: u128, right: u128) -> u128 {
+        self.pack(self.unpack(left).mul(self.unpack(right)))
+    }
+
+    pub fn div(self, left: u128, right: u128) -> u128 {
+        self.pack(self.unpack(left).div(self.unpack(right)))
+    }
+
+    pub fn sqrt(self, bits: u128) -> u128 {
+        self.pack(self.unpack(bits).sqrt())
+    }
+
+    /// Orders two encoded values, or returns `None` if either is a nan. Zeros are equal whatever
+    /// their sign.
+    pub fn compare(self, left: u128, right: u128) -> Option<Ordering> {
+        self.unpack(left).compare(self.unpack(right))
+    }
+
+    /// Rounds an encoded value up to an integer
+    pub fn ceil(self, bits: u128) -> u128 {
+        self.pack(self.unpack(bits).integral(|negative, _| !negative))
+    }
+
+    /// Rounds an encoded value down to an integer
+    pub fn floor(self, bits: u128) -> u128 {
+        self.pack(self.unpack(bits).integral(|negative, _| negative))
+    }
+
+    /// Rounds an encoded value to the nearest integer, with halfway values going away from zero
+    /// like sleigh's `FloatFormat::opRound`
+    pub fn round(self, bits: u128) -> u128 {
+        self.pack(self.unpack(bits).integral(|_, fraction| fraction != Ordering::Less))
+    }
+}
+
+impl Parts {
+    /// the x86 default nan, the result of invalid operations like `0 / 0`
+    const INDEFINITE: Parts = Parts::Nan { negative: true, payload: 0 };
+
+    fn zero(negative: bool) -> Parts {
+        Parts::Finite { negative, exponent: 0, significand: 0 }
+    }
+
+    fn is_zero(self) -> bool {
+        matches!(self, Parts::Finite { significand: 0, .. })
+    }
+
+    fn is_negative(self) -> bool {
+        match self {
+            Parts::Finite { negative, .. } | Parts::Infinity { negative } | Parts::Nan { negative, .. } => negative,
+        }
+    }
+
+    /// Flips the sign, except of nans, which pass through subtraction unchanged
+    fn negate(self) -> Parts {
+        match self {
+            Parts::Finite { negative, exponent, significand } => Parts::Finite { negative: !negative, exponent, significand },
+            Parts::Infinity { negative } => Parts::Infinity { negative: !negative },
+            nan => nan,
+        }
+    }
+
+    /// The first nan of two operands, which is what the result of an op on them is
+    fn nan(self, other: Parts) -> Option<Parts> {
+        [self, other].into_iter().find(|parts| matches!(parts, Parts::Nan { .. }))
+    }
+
+    /// The exact sum, unless the exponents are too far apart for the smaller operand to be held
+    /// exactly, in which case its lost bits are kept as a sticky bit far below the rounding point
+    fn add(self, other: Parts) -> Parts {
+        if let Some(nan) = self.nan(other) {
+            return nan;
+        }
+        let (
+            Parts::Finite { negative, exponent, significand },
+            Parts::Finite { negative: other_negative, exponent: other_exponent, significand: other_significand },
+        ) = (self, other) else {
+            return match (self, other) {
+                (Parts::Infinity { negative }, Parts::Infinity { negative: other }) if negative != other => Parts::INDEFINITE,
+                (Parts::Infinity { .. }, _) => self,
+                _ => other,
+            };
+        };
+        if significand == 0 && other_significand == 0 {
+            // only two negative zeros sum to a negative zero
+            return Parts::zero(negative && other_negative);
+        } else if significand == 0 {
+            return other;
+        } else if other_significand == 0 {
+            return self;
+        }
+
+        let (big, small) = if exponent >= other_exponent {
+            ((negative, exponent, significand), (other_negative, other_exponent, other_significand))
+        } else {
+            ((other_negative, other_exponent, other_significand), (negative, exponent, significand))
+        };
+        let gap = big.1.abs_diff(small.1);
+        // leave room above the larger operand for a carry
+        let up = gap.min(big.2.leading_zeros().saturating_sub(3));
+        let big_significand = big.2 << up;
+        let small_significand = shift_right_sticky(small.2, gap - up);
+        let exponent = big.1 - up as i32;
+
+        if big.0 == small.0 {
+            return Parts::Finite { negative: big.0, exponent, significand: big_significand + small_significand };
+        }
+        match big_significand.cmp(&small_significand) {
+            Ordering::Greater => Parts::Finite { negative: big.0, exponent, significand: big_significand - small_significand },
+            Ordering::Less => Parts::Finite { negative: small.0, exponent, significand: small_significand - big_significand },
+            Ordering::Equal => Parts::zero(false),
+        }
+    }
+
+    /// The exact product, which fits since significands have at most 64 bits
+    fn mul(self, other: Parts) -> Parts {
+        if let Some(nan) = self.nan(other) {
+            return nan;
+        }
+        let negative = self.is_negative() != other.is_negative();
+        match (self, other) {
+            (Parts::Infinity { .. }, zero) | (zero, Parts::Infinity { .. }) if zero.is_zero() => Parts::INDEFINITE,
+            (Parts::Finite { exponent, significand, .. }, Parts::Finite { exponent: other_exponent, significand: other_significand, .. }) => {
+                Parts::Finite { negative, exponent: exponent + other_exponent, significand: significand * other_significand }
+            }
+            _ => Parts::Infinity { negative },
+        }
+    }
+
+    /// A quotient of at least 94 bits, with an inexact remainder kept as a sticky bit
+    fn div(self, other: Parts) -> Parts {
+        if let Some(nan) = self.nan(other) {
+            return nan;
+        }
+        let negative = self.is_negative() != other.is_negative();
+        match (self, other) {
+            (Parts::Infinity { .. }, Parts::Infinity { .. }) => Parts::INDEFINITE,
+            (Parts::Infinity { .. }, _) => Parts::Infinity { negative },
+            (_, Parts::Infinity { .. }) => Parts::zero(negative),
+            (dividend, divisor) if divisor.is_zero() => match dividend.is_zero() {
+                true => Parts::INDEFINITE,
+                false => Parts::Infinity { negative },
+            },
+            (dividend, _) if dividend.is_zero() => Parts::zero(negative),
+            (Parts::Finite { exponent, significand, .. }, Parts::Finite { exponent: other_exponent, significand: other_significand, .. }) => {
+                // a 127 bit dividend over a 64 bit divisor gives the top 63 or 64 bits, and the
+                // remainder gives 32 more
+                let dividend_shift = significand.leading_zeros() - 1;
+                let divisor_shift = other_significand.leading_zeros() - 64;
+                let dividend = significand << dividend_shift;
+                let divisor = other_significand << divisor_shift;
+                let high = dividend / divisor;
+                let rest = (dividend % divisor) << 32;
+                let low = rest / divisor;
+                let inexact = rest % divisor != 0;
+                Parts::Finite {
+                    negative,
+                    exponent: exponent - dividend_shift as i32 - (other_exponent - divisor_shift as i32) - 32,
+                    significand: (high << 32) | low | u128::from(inexact),
+                }
+            }
+            _ => unreachable!("nans are handled above"),
+        }
+    }
+
+    /// A root of about 70 bits, with an inexact remainder kept as a sticky bit. A square root is
+    /// never exactly halfway between two values, so that's enough to round correctly.
+    fn sqrt(self) -> Parts {
+        match self {
+            Parts::Nan { .. } => self,
+            zero if zero.is_zero() => zero,
+            Parts::Finite { negative: true, .. } | Parts::Infinity { negative: true } => Parts::INDEFINITE,
+            Parts::Infinity { .. } => self,
+            Parts::Finite { exponent, significand, .. } => {
+                let mut shift = 140 - (128 - significand.leading_zeros() as i32);
+                if (exponent - shift) % 2 != 0 {
+                    shift += 1;
+                }
+                let radicand = BigUint::from(significand) << shift as usize;
+                let root = radicand.sqrt();
+                let inexact = &root * &root != radicand;
+                let root = root.to_u128().expect("the root has about 70 bits");
+                Parts::Finite { negative: false, exponent: (exponent - shift) / 2, significand: root | u128::from(inexact) }
+            }
+        }
+    }
+
+    fn compare(self, other: Parts) -> Option<Ordering> {
+        if self.nan(other).is_some() {
+            return None;
+        }
+        if self.is_zero() && other.is_zero() {
+            return Some(Ordering::Equal);
+        }
+        // zeros are ordered as positive, which is only wrong when both are zero
+        let negative = self.is_negative() && !self.is_zero();
+        let other_negative = other.is_negative() && !other.is_zero();
+        if negative != other_negative {
+            return Some(other_negative.cmp(&negative));
+        }
+        let magnitude = self.compare_magnitude(other);
+        Some(if negative { magnitude.reverse() } else { magnitude })
+    }
+
+    fn compare_magnitude(self, other: Parts) -> Ordering {
+        match (self, other) {
+            (Parts::Infinity { .. }, Parts::Infinity { .. }) => Ordering::Equal,
+            (Parts::Infinity { .. }, _) => Ordering::Greater,
+            (_, Parts::Infinity { .. }) => Ordering::Less,
+            (Parts::Finite { significand: 0, .. }, _) => Ordering::Less,
+            (_, Parts::Finite { significand: 0, .. }) => Ordering::Greater,
+            (Parts::Finite { exponent, significand, .. }, Parts::Finite { exponent: other_exponent, significand: other_significand, .. }) => {
+                // compare the position of the top bits, then the significands lined up at the top
+                let (length, other_length) = (128 - significand.leading_zeros(), 128 - other_significand.leading_zeros());
+                (exponent + length as i32).cmp(&(other_exponent + other_length as i32)).then_with(|| {
+                    let (aligned, other_aligned) = (significand << (128 - length), other_significand << (128 - other_length));
+                    aligned.cmp(&other_aligned)
+                })
+            }
+            _ => unreachable!("nans are handled by compare"),
+        }
+    }
+
+    /// Rounds to an integer, going up in magnitude when the value has a fraction and `away`
+    /// says so given the sign and how the fraction compares to a half
+    fn integral(self, away: impl Fn(bool, Ordering) -> bool) -> Parts {
+        let Parts::Finite { negative, exponent, significand } = self else {
+            return self;
+        };
+        if exponent >= 0 || significand == 0 {
+            return self;
+        }
+        let shift = exponent.unsigned_abs();
+        let (kept, fraction) = if shift >= 128 {
+            // less than a half, since significands have at most 64 bits
+            (0, Ordering::Less)
+        } else {
+            let lost = significand & ((1 << shift) - 1);
+            if lost == 0 {
+                return self;
+            }
+            (significand >> shift, lost.cmp(&(1 << (shift - 1))))
+        };
+        let significand = kept + u128::from(away(negative, fraction));
+        Parts::Finite { negative, exponent: 0, significand }
+    }
+}
+
+/// Shifts `value` right, rounding to nearest with ties to even
+fn shift_right_even(value: u128, shift: u32) -> u128 {
+    if shift > 128 {
+        return 0;
+    }
+    let kept = value.checked_shr(shift).unwrap_or(0);
+    let lost = value & u128::MAX.checked_shr(128 - shift).unwrap_or(0);
+    let half = 1 << (shift - 1);
+    if lost > half || (lost == half && kept & 1 == 1) {
+        kept + 1
+    } else {
+        kept
+    }
+}
+
+/// Shifts `value` right, setting the lowest bit if any set bits were shifted out
+fn shift_right_sticky(value: u128, shift: u32) -> u128 {
+    match value.checked_shr(shift) {
+        Some(kept) => kept | u128::from(kept << shift != value),
+        None => u128::from(value != 0),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_extended_round_trip() {
+        let values = [1.0, -2.5, 0.1, f64::MAX, f64::MIN_POSITIVE, 5e-324, -0.0, f64::INFINITY];
+        for value in values {
+            let bits = FloatFormat::Extended.encode(value);
+            let decoded = FloatFormat::Extended.decode(bits);
+            assert_eq!(decoded.to_bits(), value.to_bits(), "{} round-tripped to {}", value, decoded);
+        }
+        assert!(FloatFormat::Extended.is_nan(FloatFormat::Extended.encode(f64::NAN)));
+    }
+
+    #[test]
+    fn test_extended_encoding() {
+        // 1.0 has a biased exponent of 0x3FFF and only the integer bit set
+        assert_eq!(FloatFormat::Extended.encode(1.0), 0x3FFF_8000_0000_0000_0000);
+        assert_eq!(FloatFormat::Extended.encode(-2.0), 0xC000_8000_0000_0000_0000);
+        // pi as stored by FLDPI, which rounds to the nearest double
+        assert_eq!(FloatFormat::Extended.decode(0x4000_C90F_DAA2_2168_C235), std::f64::consts::PI);
+        assert_eq!(FloatFormat::Extended.encode(f64::INFINITY), 0x7FFF_8000_0000_0000_0000);
+    }
+
+    #[test]
+    fn test_convert() {
+        let single = |value: f32| u128::from(value.to_bits());
+        let double = |value: f64| u128::from(value.to_bits());
+        let cases = [
+            0.1, -1.0 / 3.0, f64::from(f32::MAX) * 1.5, f64::MAX, 1e-40, 1e-46, -5e-324,
+            // halfway between two singles, ties go to the even one
+            1.0 + f64::powi(2.0, -24), 1.0 + 3.0 * f64::powi(2.0, -24),
+        ];
+        for value in cases {
+            let bits = FloatFormat::Double.convert(double(value), FloatFormat::Single);
+            assert_eq!(bits, single(value as f32), "{}", value);
+            assert_eq!(FloatFormat::Single.convert(bits, FloatFormat::Double), double(f64::from(value as f32)));
+        }
+
+        // rounding a value with more bits than a double, and ties to even in extended precision
+        let extended = 0x3FFF_8000_0000_0000_0400;
+        assert_eq!(FloatFormat::Extended.convert(extended, FloatFormat::Double), double(1.0));
+        assert_eq!(FloatFormat::Extended.convert(extended + 1, FloatFormat::Double), double(1.0 + f64::EPSILON));
+        // the largest extended values overflow a double
+        assert_eq!(FloatFormat::Extended.convert(0x7FFE_FFFF_FFFF_FFFF_FFFF, FloatFormat::Double), double(f64::INFINITY));
+        // the smallest extended subnormal underflows to zero
+        assert_eq!(FloatFormat::Extended.convert(0x8000_0000_0000_0000_0001, FloatFormat::Single), single(-0.0));
+
+        // nans stay quiet nans with the top of their payload
+        let nan = FloatFormat::Double.convert(0x7FF4_0000_0000_0001, FloatFormat::Single);
+        assert_eq!(nan, 0x7FE0_0000);
+        assert_eq!(FloatFormat::Single.convert(nan, FloatFormat::Extended), 0x7FFF_E000_0000_0000_0000);
+    }
+
+    #[test]
+    fn test_from_int() {
+        // 2^60 + 2^36 + 1 rounds up to a single, but rounding to a double first makes it a tie
+        let value = (1i128 << 60) + (1 << 36) + 1;
+        assert_eq!(FloatFormat::Single.from_int(value), u128::from((((1u64 << 60) + (1 << 37)) as f32).to_bits()));
+        assert_ne!(FloatFormat::Single.from_int(value), u128::from((value as f64 as f32).to_bits()));
+
+        assert_eq!(FloatFormat::Double.from_int(-3), u128::from((-3f64).to_bits()));
+        assert_eq!(FloatFormat::Double.from_int(0), 0);
+        assert_eq!(FloatFormat::Double.from_int((1 << 53) + 1), u128::from(2f64.powi(53).to_bits()));
+        // extended precision holds any i64 exactly
+        assert_eq!(FloatFormat::Extended.from_int(i64::MAX.into()), 0x403D_FFFF_FFFF_FFFF_FFFE);
+        assert_eq!(FloatFormat::Extended.from_int(i64::MIN.into()), 0xC03E_8000_0000_0000_0000);
+    }
+
+    #[test]
+    fn test_trunc() {
+        let double = |value: f64| u128::from(value.to_bits());
+        assert_eq!(FloatFormat::Double.trunc(double(-2.75)), Some(-2));
+        assert_eq!(FloatFormat::Double.trunc(double(0.5)), Some(0));
+        assert_eq!(FloatFormat::Double.trunc(double(1e30)), Some(1_000_000_000_000_000_019_884_624_838_656));
+        assert_eq!(FloatFormat::Double.trunc(double(-2f64.powi(127))), Some(i128::MIN));
+        assert_eq!(FloatFormat::Double.trunc(double(2f64.powi(127))), None);
+        assert_eq!(FloatFormat::Double.trunc(double(f64::NAN)), None);
+        assert_eq!(FloatFormat::Double.trunc(double(f64::NEG_INFINITY)), None);
+        assert_eq!(FloatFormat::Extended.trunc(0x403D_FFFF_FFFF_FFFF_FFFE), Some(i64::MAX.into()));
+    }
+
+    #[test]
+    fn test_arithmetic() {
+        type Op = fn(FloatFormat, u128, u128) -> u128;
+        // host arithmetic is correctly rounded, so single and double results must match it
+        let mut values = vec![
+            0.0, -0.0, 1.0, -1.5, 0.1, 3.0, 1e308, -1e-310, 5e-324, f64::MAX, f64::MIN_POSITIVE,
+            1.0 + f64::EPSILON, f64::INFINITY, f64::NEG_INFINITY, f64::NAN,
+        ];
+        let mut state = 0x2545_F491_4F6C_DD1Du64;
+        for _ in 0..40 {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            // random bits, with exponents kept near each other half the time
+            let bits = if state & 1 == 0 { state } else { state & 0x803F_FFFF_FFFF_FFFF | 0x3F00_0000_0000_0000 };
+            values.push(f64::from_bits(bits));
+        }
+
+        let double = |value: f64| u128::from(value.to_bits());
+        let single = |value: f32| u128::from(value.to_bits());
+        let same = |result: u128, expected: u128, format: FloatFormat| {
+            result == expected || (format.is_nan(result) && format.is_nan(expected))
+        };
+        for &a in &values {
+            assert!(same(FloatFormat::Double.sqrt(double(a)), double(a.sqrt()), FloatFormat::Double), "sqrt {}", a);
+            let a32 = a as f32;
+            assert!(same(FloatFormat::Single.sqrt(single(a32)), single(a32.sqrt()), FloatFormat::Single), "sqrt {}", a32);
+            for &b in &values {
+                let b32 = b as f32;
+                let ops: [(Op, f64, f32); 4] = [
+                    (FloatFormat::add, a + b, a32 + b32),
+                    (FloatFormat::sub, a - b, a32 - b32),
+                    (FloatFormat::mul, a * b, a32 * b32),
+                    (FloatFormat::div, a / b, a32 / b32),
+                ];
+                for (op, expected, expected32) in ops {
+                    let result = op(FloatFormat::Double, double(a), double(b));
+                    assert!(same(result, double(expected), FloatFormat::Double), "{} {} gave {:X}, expected {}", a, b, result, expected);
+                    let result = op(FloatFormat::Single, single(a32), single(b32));
+                    assert!(same(result, single(expected32), FloatFormat::Single), "{} {} gave {:X}, expected {}", a32, b32, result, expected32);
+                }
+                assert_eq!(FloatFormat::Double.compare(double(a), double(b)), a.partial_cmp(&b), "{} {}", a, b);
+            }
+        }
+
+        // the extended root of 2 as computed by FSQRT, and an invalid operation
+        assert_eq!(FloatFormat::Extended.sqrt(0x4000_8000_0000_0000_0000), 0x3FFF_B504_F333_F9DE_6484);
+        assert_eq!(FloatFormat::Extended.sub(0x7FFF_8000_0000_0000_0000, 0x7FFF_8000_0000_0000_0000), 0xFFFF_C000_0000_0000_0000);
+    }
+
+}
@@ -0,0 +1,197 @@
+//!
+//! Callbacks on accesses to address ranges in the ram space.
+//!
+//! Read hooks run before a read and write hooks after a write, which is enough for watchpoints
+//! and tracing. MMIO handlers go further: they supply the bytes of any read that overlaps their
+//! range and writes to it don't reach ram, so device registers and magic addresses can be
+//! modelled.
+
+use std::ops::Range;
+use crate::emulator::{Emulator, ErrorKind, Space};
+
+/// Called before a read of ram overlapping the hooked range, with the address and size of the read
+pub type ReadHook = dyn Fn(&Emulator<'_, '_>, u64, u64) -> anyhow::Result<()>;
+
+/// Called after a write to ram overlapping the hooked range, with the address and bytes written
+pub type WriteHook = dyn Fn(&Emulator<'_, '_>, u64, &[u8]) -> anyhow::Result<()>;
+
+/// Supplies the bytes of a read from its range, given the address of the first byte it supplies
+pub type MmioHandler = dyn Fn(&Emulator<'_, '_>, u64, &mut [u8]) -> anyhow::Result<()>;
+
+/// Identifies a hook so that it can be removed
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct HookId(usize);
+
+pub(crate) enum Hook {
+    BeforeRead(Box<ReadHook>),
+    AfterWrite(Box<WriteHook>),
+    Mmio(Box<MmioHandler>),
+}
+
+pub(crate) struct MemoryHook {
+    id: HookId,
+    range: Range<u64>,
+    hook: Hook,
+}
+
+/// The hooks installed on an emulator, in the order they were added
+#[derive(Default)]
+pub(crate) struct Hooks {
+    hooks: Vec<MemoryHook>,
+    next_id: usize,
+}
+
+impl Hooks {
+    fn add(&mut self, range: Range<u64>, hook: Hook) -> HookId {
+        let id = HookId(self.next_id);
+        self.next_id += 1;
+        self.hooks.push(MemoryHook { id, range, hook });
+        id
+    }
+
+    #[inline]
+    pub(crate) fn is_empty(&self) -> bool {
+        self.hooks.is_empty()
+    }
+
+    /// The hooks whose range overlaps `len` bytes starting at `addr`
+    fn overlapping(&self, addr: u64, len: u64) -> impl Iterator<Item = &MemoryHook> {
+        let end = addr.saturating_add(len);
+        self.hooks.iter()
+            .filter(move |hook| hook.range.start < end && addr < hook.range.end)
+    }
+}
+
+impl<'a, 'b> Emulator<'a, 'b> {
+    /// Calls `hook` before every read of ram that overlaps `range`
+    pub fn hook_read<F>(&mut self, range: Range<u64>, hook: F) -> HookId
+        where
+            F: Fn(&Emulator<'_, '_>, u64, u64) -> anyhow::Result<()> + 'static,
+    {
+        self.hooks.add(range, Hook::BeforeRead(Box::new(hook)))
+    }
+
+    /// Calls `hook` after every write to ram that overlaps `range`
+    pub fn hook_write<F>(&mut self, range: Range<u64>, hook: F) -> HookId
+        where
+            F: Fn(&Emulator<'_, '_>, u64, &[u8]) -> anyhow::Result<()> + 'static,
+    {
+        self.hooks.add(range, Hook::AfterWrite(Box::new(hook)))
+    }
+
+    /// Lets `handler` supply the value of every byte of ram read from `range`. Writes to the range
+    /// don't land in ram, but can be observed with `hook_write`.
+    pub fn map_mmio<F>(&mut self, range: Range<u64>, handler: F) -> HookId
+        where
+            F: Fn(&Emulator<'_, '_>, u64, &mut [u8]) -> anyhow::Result<()> + 'static,
+    {
+        self.hooks.add(range, Hook::Mmio(Box::new(handler)))
+    }
+
+    /// Removes a hook or MMIO handler, returning whether it was installed
+    pub fn remove_hook(&mut self, id: HookId) -> bool {
+        let len = self.hooks.hooks.len();
+        self.hooks.hooks.retain(|hook| hook.id != id);
+        self.hooks.hooks.len() != len
+    }
+
+    pub(crate) fn before_read(&self, addr: u64, len: u64) -> Result<(), ErrorKind> {
+        for hook in self.hooks.overlapping(addr, len) {
+            if let Hook::BeforeRead(f) = &hook.hook {
+                f(self, addr, len).map_err(|error| ErrorKind::Hook { address: addr, error })?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Overwrites the bytes of a read with whatever the MMIO handlers overlapping it supply
+    pub(crate) fn read_mmio(&self, addr: u64, buffer: &mut [u8]) -> Result<(), ErrorKind> {
+        for hook in self.hooks.overlapping(addr, buffer.len() as u64) {
+            if let Hook::Mmio(f) = &hook.hook {
+                let start = addr.max(hook.range.start);
+                let end = addr.saturating_add(buffer.len() as u64).min(hook.range.end);
+                let chunk = &mut buffer[(start - addr) as usize..(end - addr) as usize];
+                f(self, start, chunk).map_err(|error| ErrorKind::Hook { address: start, error })?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Writes `bytes` to ram at `addr`, leaving out the bytes that MMIO handlers claim
+    pub(crate) fn write_unclaimed(&self, ram: &Space, addr: u64, bytes: &[u8]) {
+        let end = addr.saturating_add(bytes.len() as u64);
+        let mut claimed = self.hooks.overlapping(addr, bytes.len() as u64)
+            .filter(|hook| matches!(hook.hook, Hook::Mmio(_)))
+            .map(|hook| hook.range.start.max(addr)..hook.range.end.min(end))
+            .collect::<Vec<_>>();
+        claimed.sort_unstable_by_key(|range| range.start);
+
+        let mut next = addr;
+        for range in claimed.into_iter().chain(std::iter::once(end..end)) {
+            if range.start > next {
+                ram.write_from(next, &bytes[(next - addr) as usize..(range.start - addr) as usize]);
+            }
+            next = next.max(range.end);
+        }
+    }
+
+    pub(crate) fn after_write(&self, addr: u64, bytes: &[u8]) -> Result<(), ErrorKind> {
+        for hook in self.hooks.overlapping(addr, bytes.len() as u64) {
+            if let Hook::AfterWrite(f) = &hook.hook {
+                f(self, addr, bytes).map_err(|error| ErrorKind::Hook { address: addr, error })?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::{Cell, RefCell};
+    use std::rc::Rc;
+    use sleigh::{Opcode, PCode, VarnodeData};
+    use crate::emulator::fixture::{constant, emulator, space, unique};
+
+    #[test]
+    fn test_memory_hooks() {
+        emulator!(mut emulator);
+        let ram = |offset| VarnodeData { space: space("ram"), offset, size: 4 };
+        let copy = |from, to| PCode { address: 0, opcode: Opcode::Copy, vars: vec![from], outvar: Some(to) };
+
+        // a device register at 0x100 whose second byte counts reads
+        let reads = Rc::new(Cell::new(0u8));
+        let counter = reads.clone();
+        emulator.map_mmio(0x101..0x102, move |_, address, bytes| {
+            assert_eq!((address, bytes.len()), (0x101, 1));
+            counter.set(counter.get() + 1);
+            bytes[0] = counter.get();
+            Ok(())
+        });
+        let watched = Rc::new(RefCell::new(Vec::new()));
+        let log = watched.clone();
+        let watchpoint = emulator.hook_write(0x100..0x104, move |_, address, bytes| {
+            log.borrow_mut().push((address, bytes.to_vec()));
+            Ok(())
+        });
+        emulator.hook_read(0x200..0x201, |_, address, _| anyhow::bail!("read of {:X} is not allowed", address));
+
+        emulator.emulate_one(&copy(constant(0x4433_2211, 4), ram(0x100))).unwrap();
+        emulator.emulate_one(&copy(ram(0x100), unique(0, 4))).unwrap();
+        emulator.emulate_one(&copy(ram(0x100), unique(0, 4))).unwrap();
+        assert_eq!(emulator.read_native(&unique(0, 4)).unwrap(), 0x4433_0211);
+        assert_eq!(reads.get(), 2);
+        // the device register's byte never reaches ram, but the write hook sees all of it
+        assert_eq!(emulator.ram().get_bytes(0x100, 4), [0x11, 0, 0x33, 0x44]);
+        assert_eq!(*watched.borrow(), [(0x100, vec![0x11, 0x22, 0x33, 0x44])]);
+
+        // writes outside the watched range, and after removing the watchpoint, aren't seen
+        emulator.emulate_one(&copy(constant(0, 4), ram(0x104))).unwrap();
+        assert!(emulator.remove_hook(watchpoint));
+        emulator.emulate_one(&copy(constant(0, 4), ram(0x100))).unwrap();
+        assert_eq!(watched.borrow().len(), 1);
+
+        let error = emulator.emulate_one(&copy(ram(0x1FE), unique(0, 4))).unwrap_err();
+        assert!(matches!(error.kind, ErrorKind::Hook { address: 0x1FE, .. }), "{}", error);
+    }
+}
@@ -0,0 +1,188 @@
+//!
+//! Loading a binary's memory image into ram.
+//!
+//! Executables and shared objects are loaded the way the kernel would: every `PT_LOAD` segment
+//! is mapped with the permissions in its flags, filled from the file and zeroed past the end of
+//! the file bytes (which is where `.bss` lives). PE images are loaded the same way, from the
+//! segments describing their headers and sections. Files without program headers, like object
+//! files, fall back to their allocated sections.
+
+use anyhow::{ensure, Context};
+use crate::binary::{Binary, Section, Segment};
+use crate::emulator::{Emulator, Permissions, Space};
+use crate::emulator::space::PAGE_SIZE;
+
+impl<'a, 'b> Emulator<'a, 'b> {
+    /// Maps the loadable parts of `binary` into ram and copies their contents in
+    pub fn load(&mut self, binary: &Binary) -> anyhow::Result<()> {
+        let segments = binary.segments.iter()
+            .filter(|segment| segment.loaded())
+            .collect::<Vec<_>>();
+        if segments.is_empty() {
+            for section in binary.sections.values().filter(|section| section.allocated()) {
+                self.load_section(binary, section)?;
+            }
+            return Ok(());
+        }
+
+        for segment in segments {
+            self.load_segment(binary, segment)?;
+        }
+        Ok(())
+    }
+
+    fn load_segment(&mut self, binary: &Binary, segment: &Segment) -> anyhow::Result<()> {
+        ensure!(
+            segment.file_size <= segment.memory_size,
+            "segment at {:08X} has more bytes in the file than in memory", segment.address,
+        );
+        let bytes = file_bytes(binary, segment.offset, segment.file_size)
+            .with_context(|| format!("segment at {:08X} is past the end of the file", segment.address))?;
+
+        self.map_memory(segment.address, segment.memory_size, segment_permissions(segment));
+        let ram = self.ram();
+        ram.write_from(segment.address, bytes);
+        zero_partial_page(ram, segment.address + segment.file_size, segment.address + segment.memory_size);
+        Ok(())
+    }
+
+    fn load_section(&mut self, binary: &Binary, section: &Section) -> anyhow::Result<()> {
+        self.map_memory(section.address, section.size, section_permissions(section));
+        // sections like .bss take up no space in the file
+        if section.kind == "SHT_NOBITS" {
+            zero_partial_page(self.ram(), section.address, section.address + section.size);
+            return Ok(());
+        }
+        let bytes = file_bytes(binary, section.offset, section.size)
+            .with_context(|| format!("section at {:08X} is past the end of the file", section.address))?;
+        self.ram().write_from(section.address, bytes);
+        Ok(())
+    }
+}
+
+/// Zeroes the part of `start..end` in the page `start` is in, unless `start` begins a page. That
+/// page can hold bytes loaded before it, but the pages after it have never been written, so they
+/// already read as zero.
+fn zero_partial_page(ram: &Space, start: u64, end: u64) {
+    const ZEROS: [u8; PAGE_SIZE] = [0; PAGE_SIZE];
+    let offset = (start % PAGE_SIZE as u64) as usize;
+    if offset == 0 || start >= end {
+        return;
+    }
+    let size = (end - start).min((PAGE_SIZE - offset) as u64) as usize;
+    ram.write_from(start, &ZEROS[..size]);
+}
+
+/// `size` bytes of the file starting at `offset`
+fn file_bytes(binary: &Binary, offset: u64, size: u64) -> Option<&[u8]> {
+    let start = usize::try_from(offset).ok()?;
+    let end = start.checked_add(usize::try_from(size).ok()?)?;
+    binary.bytes.get(start..end)
+}
+
+fn has(flags: &[String], name: &str) -> bool {
+    flags.iter().any(|flag| flag == name)
+}
+
+/// The permissions of a loadable segment, from its ELF flags or PE section characteristics
+fn segment_permissions(segment: &Segment) -> Permissions {
+    Permissions {
+        read: has(&segment.flags, "PF_R") || has(&segment.flags, "IMAGE_SCN_MEM_READ"),
+        write: has(&segment.flags, "PF_W") || has(&segment.flags, "IMAGE_SCN_MEM_WRITE"),
+        execute: has(&segment.flags, "PF_X") || has(&segment.flags, "IMAGE_SCN_MEM_EXECUTE"),
+    }
+}
+
+/// The permissions of an allocated section, from its flags
+fn section_permissions(section: &Section) -> Permissions {
+    Permissions {
+        read: true,
+        write: section.writable(),
+        execute: section.executable(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::emulator::{Emulator, Machine};
+    use crate::emulator::fixture::{emulator, empty_binary};
+
+    #[test]
+    fn test_load_segments() {
+        emulator!(mut emulator);
+        let fib = Binary::new(concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fib/bin")).unwrap();
+        emulator.load(&fib).unwrap();
+
+        let ram = emulator.ram();
+        for name in [".rodata", ".data", ".text"] {
+            let section = &fib.sections[name];
+            let file = &fib.bytes[section.offset as usize..][..section.size as usize];
+            assert_eq!(ram.get_bytes(section.address, section.size), file, "{}", name);
+        }
+        let bss = &fib.sections[".bss"];
+        assert!(ram.get_bytes(bss.address, bss.size).iter().all(|byte| *byte == 0));
+
+        assert_eq!(ram.permissions(fib.sections[".text"].address), Some(Permissions::READ_EXECUTE));
+        assert_eq!(ram.permissions(fib.sections[".rodata"].address), Some(Permissions::READ));
+        assert_eq!(ram.permissions(bss.address + bss.size - 1), Some(Permissions::READ_WRITE));
+        let data = fib.segments.iter().rfind(|segment| segment.kind == "PT_LOAD").unwrap();
+        assert_eq!(ram.permissions(data.address + data.memory_size), None);
+    }
+
+    #[test]
+    fn test_zero_fill_is_lazy() {
+        let mut binary = empty_binary();
+        binary.bytes = vec![0xAA; 0x20];
+        binary.segments.push(Segment {
+            kind: "PT_LOAD".to_string(),
+            flags: vec!["PF_R".to_string(), "PF_W".to_string()],
+            offset: 0,
+            address: 0x1000_0010,
+            physical_address: 0x1000_0010,
+            file_size: 0x20,
+            memory_size: 0x1000_0000,
+            alignment: 0x1000,
+        });
+        emulator!(mut emulator);
+        // stale bytes in the page the file bytes end in are cleared
+        emulator.ram().write_from(0x1000_0030, &[0xFF; 4]);
+        emulator.load(&binary).unwrap();
+
+        let ram = emulator.ram();
+        assert_eq!(ram.get_bytes(0x1000_0010, 0x20), [0xAA; 0x20]);
+        assert_eq!(ram.get_bytes(0x1000_0030, 4), [0; 4]);
+        assert_eq!(ram.get_bytes(0x1FFF_FFFC, 4), [0; 4]);
+        assert_eq!(ram.permissions(0x2000_000F), Some(Permissions::READ_WRITE));
+        // the rest of the 256MB tail isn't allocated
+        assert_eq!(ram.page_count(), 1);
+    }
+
+    #[test]
+    fn test_load_pe() {
+        let mut binary = Binary::new(concat!(env!("CARGO_MANIFEST_DIR"), "/tests/pe/x86.exe")).unwrap();
+        binary.rebase(0x1000_0000).unwrap();
+        let machine = Machine::new(&binary).unwrap();
+
+        // next_value reads a global through a relocated address and adds one to it
+        let start = binary.symbols["next_value"].front().unwrap().address;
+        assert_eq!(binary.entry(), Some(start));
+        let end = *machine.instructions.keys().next_back().unwrap();
+        let mut emulator = Emulator::new(&machine, start, end).unwrap();
+        emulator.load(&binary).unwrap();
+        while let Some(next) = emulator.next() {
+            let (_, pcode) = next.unwrap();
+            let control = emulator.emulate_one(pcode).unwrap();
+            emulator.apply(control).unwrap();
+        }
+        assert_eq!(emulator.reg::<u32>("EAX").unwrap(), 42);
+
+        let ram = emulator.ram();
+        assert_eq!(ram.permissions(0x1000_0000), Some(Permissions::READ));
+        assert_eq!(ram.permissions(0x1000_1000), Some(Permissions::READ_EXECUTE));
+        assert_eq!(ram.permissions(0x1000_4000), Some(Permissions::READ_WRITE));
+        assert!(ram.get_bytes(0x1000_4000, 0x100).iter().all(|byte| *byte == 0));
+        // the pointer in .data was relocated too
+        assert_eq!(ram.get_bytes(0x1000_3004, 4), 0x1000_3000u32.to_le_bytes());
+    }
+}
@@ -1,13 +1,31 @@
 use std::collections::BTreeMap;
-use std::ops::Index;
+use std::ops::Range;
 use anyhow::{bail, Context};
+use itertools::Itertools;
 use hashbrown::{HashMap, HashSet};
-use sleigh::{Decompiler, Instruction, PCode, VarnodeData, X86Mode};
-use crate::binary::{Binary, Section};
-use crate::emulator::{Emulator};
+use sleigh::{AddrSpace, Decompiler, Instruction, PCode, SpaceType, VarnodeData};
+use crate::binary::{Architecture, Binary, Section};
+use crate::emulator::{Emulator, Permissions, Space};
+
+/// the size of the region mapped for the stack
+const STACK_SIZE: u64 = 0x10_0000;
+/// how far below the end of the stack region the stack pointer starts
+const STACK_OFFSET: u64 = 0x3448;
+/// where the stack region ends in a 64 bit address space, the top of user space on x86-64 Linux
+const STACK_END_64: u64 = 0x7FFF_FFFF_F000;
+
+/// The region mapped for the stack and the initial stack pointer, at the top of a 32 bit (or
+/// smaller) ram space, or where Linux puts it in a 64 bit one
+fn stack(ram: &AddrSpace) -> (Range<u64>, u64) {
+    let end = if ram.addrsize >= 8 { STACK_END_64 } else { ram.highest.saturating_add(1) };
+    let size = STACK_SIZE.min(end / 2);
+    (end - size..end, end - STACK_OFFSET.min(size / 2))
+}
 
 pub struct Machine<'a> {
     pub binary: &'a Binary,
+    /// the processor the binary runs on
+    pub architecture: Architecture,
     pub decompiler: Decompiler,
 
     pub sections: HashSet<String>,
@@ -15,16 +33,34 @@ pub struct Machine<'a> {
     pub instructions: BTreeMap<u64, Instruction>,
     pub register_names: HashMap<VarnodeData, String>,
     pub named_registers: HashMap<String, VarnodeData>,
+    /// the registers keyed by lowercase name, for looking them up case-insensitively
+    pub folded_registers: HashMap<String, VarnodeData>,
+    /// the number of bytes needed to hold every register in a space, for the spaces that have any
+    register_extents: HashMap<usize, usize>,
+    /// the names of the user-defined ops, by index
+    pub user_ops: Vec<String>,
+    /// the address spaces of the language, in index order
+    pub spaces: Vec<AddrSpace>,
+    /// the index of the space code is loaded into
+    pub default_space: usize,
+    /// the index of the space holding temporaries, which only live for one instruction
+    pub unique_space: Option<usize>,
+    /// the registers shown in a register dump, in order
+    pub general_registers: Vec<String>,
+    /// the status flag registers shown in a register dump
+    pub flag_registers: Vec<String>,
 }
 
 impl<'a> Machine<'a> {
     pub fn load_function(&mut self, name: &str) -> anyhow::Result<(u64, u64)> {
-        let symbol = self.binary.symbols
+        let symbols = self.binary.symbols
             .get(name)
-            .context("unable to find symbol")?;
-        println!("loading function: {:X}", symbol.front().unwrap().address);
-        assert_eq!(symbol.len(), 1);
-        let symbol = symbol.front().unwrap();
+            .with_context(|| format!("unable to find symbol {}", name))?;
+        let symbol = match symbols.len() {
+            1 => symbols.front().context("unable to find symbol")?,
+            count => bail!("{} symbols are called {}", count, name),
+        };
+        println!("loading function: {:X}", symbol.address);
         self.load_section(&symbol.section)?;
 
         println!("loaded function: {} at {:0>8X} with {} bytes", name, symbol.address, symbol.size);
@@ -41,8 +77,9 @@ impl<'a> Machine<'a> {
             return Ok(section);
         }
 
-        let start: usize = section.offset.try_into().unwrap();
-        let bytes = self.binary.bytes.index(start..);
+        let bytes = usize::try_from(section.offset).ok()
+            .and_then(|start| self.binary.bytes.get(start..))
+            .with_context(|| format!("section {} starts past the end of the file", name))?;
 
         let (_, pcodes) = self.decompiler.translate(bytes, section.address, section.size);
         let (_, instructions) = self.decompiler.disassemble(bytes, section.address, section.size);
@@ -61,18 +98,28 @@ impl<'a> Machine<'a> {
     }
 
     pub fn new(binary: &'a Binary) -> anyhow::Result<Self> {
+        let architecture = binary.architecture()?;
         let mut emulator = Machine {
             binary,
-            decompiler: Decompiler::builder().x86(X86Mode::Mode32).build(),
+            architecture,
+            decompiler: architecture.decompiler()?,
             sections: HashSet::new(),
             pcodes: BTreeMap::default(),
             instructions: BTreeMap::default(),
             register_names: HashMap::default(),
             named_registers: HashMap::default(),
+            folded_registers: HashMap::default(),
+            register_extents: HashMap::default(),
+            user_ops: Vec::new(),
+            spaces: Vec::new(),
+            default_space: 0,
+            unique_space: None,
+            general_registers: Vec::new(),
+            flag_registers: Vec::new(),
         };
 
         for (name, section) in binary.sections.iter() {
-            if section.flags.iter().any(|flag| flag == "SHF_EXECINSTR") {
+            if section.executable() {
                 println!("loading section: {}", name);
                 emulator.load_section(name.as_str())?;
             }
@@ -83,31 +130,130 @@ impl<'a> Machine<'a> {
         emulator.named_registers = emulator.register_names.iter()
             .map(|(node, name)| (name.clone(), node.clone()))
             .collect();
+        // when names differ only in case, the one that sorts first wins
+        for (name, node) in emulator.named_registers.iter().sorted_by(|a, b| a.0.cmp(b.0)) {
+            emulator.folded_registers.entry(name.to_ascii_lowercase()).or_insert_with(|| node.clone());
+        }
+        for node in emulator.register_names.keys() {
+            let extent = emulator.register_extents.entry(node.space.index).or_default();
+            *extent = (*extent).max(node.offset as usize + node.size as usize);
+        }
+        // not every variant of a language has every register
+        let known = |name: &String| emulator.named_registers.contains_key(name);
+        emulator.general_registers = architecture.general_registers().into_iter().filter(known).collect();
+        emulator.flag_registers = architecture.flag_registers().into_iter().filter(known).collect();
+        emulator.user_ops = emulator.decompiler.get_user_ops();
+        emulator.spaces = emulator.decompiler.get_spaces();
+        emulator.default_space = emulator.decompiler.get_default_space();
+        emulator.unique_space = emulator.spaces.iter()
+            .find(|space| space.type_ == SpaceType::Internal)
+            .map(|space| space.index);
 
         Ok(emulator)
     }
 
+    /// Empty emulator spaces for every space of the language that can hold values, indexed by
+    /// space index. Spaces holding registers are a flat buffer sized to fit every register, and
+    /// unique is a flat buffer that grows to fit whatever an instruction needs.
+    pub fn new_spaces(&self) -> Vec<Option<Space>> {
+        let mut spaces = Vec::new();
+        for space in &self.spaces {
+            if spaces.len() <= space.index {
+                spaces.resize_with(space.index + 1, || None);
+            }
+            spaces[space.index] = match space.type_ {
+                SpaceType::Constant => None,
+                SpaceType::Internal => Some(Space::flat(space, 0)),
+                _ => match self.register_extent(space.index) {
+                    Some(size) => Some(Space::flat(space, size)),
+                    None => Some(Space::from(space)),
+                },
+            };
+        }
+        spaces
+    }
+
+    /// The number of bytes needed to hold every register in a space, if it has any
+    pub fn register_extent(&self, space: usize) -> Option<usize> {
+        self.register_extents.get(&space).copied()
+    }
+
     pub fn emulate(&mut self, symbol: &str) -> anyhow::Result<Emulator<'_, 'a>> {
         let (address, size) = self.load_function(symbol)?;
+        // symbols like PE exports don't say how big they are, so they run up to whatever follows
+        let end = match size {
+            0 => self.binary.index.implied_end(address)
+                .with_context(|| format!("unable to tell where {} ends", symbol))?,
+            size => address + size,
+        };
         // I don't know the size of instructions so we're going to find the last one
-        let end_address = *self.instructions.range(..address + size).next_back().unwrap().0;
+        let (&end_address, _) = self.instructions.range(address..end)
+            .next_back()
+            .with_context(|| format!("no instructions in {}", symbol))?;
 
-        let emulator = Emulator::new(self, address, end_address);
+        let mut emulator = Emulator::new(self, address, end_address)?;
         println!("emulating {} at {:0>8X} with {} bytes", symbol, address, size);
 
-        // todo: support more architectures
-        let ebp = emulator.get_register("EBP")
-            .expect("unable to find EBP register");
-        emulator.write(ebp, 0u32);
-
-        let esp = emulator.get_register("ESP")
-            .expect("unable to find ESP register");
-        emulator.write(esp, 0xFFFF_CBB8_u32);
+        emulator.load(self.binary)?;
+        let (stack, stack_pointer) = stack(&self.spaces[self.default_space]);
+        emulator.map_memory(stack.start, stack.end - stack.start, Permissions::READ_WRITE);
 
-        let eip = emulator.get_register("EIP")
-            .expect("unable to find EIP register");
-        emulator.write(eip, u32::try_from(emulator.address).unwrap());
+        emulator.set_reg(self.architecture.stack_pointer(), stack_pointer)?;
+        emulator.set_reg(self.architecture.program_counter(), emulator.address)?;
 
         Ok(emulator)
     }
 }
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_emulate_unknown_symbol() {
+        let binary = Binary::new(concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fib/bin")).unwrap();
+        let mut machine = Machine::new(&binary).unwrap();
+        let error = machine.emulate("no_such_function").err().expect("emulating a missing symbol should fail");
+        assert_eq!(error.to_string(), "unable to find symbol no_such_function");
+    }
+
+    #[test]
+    fn test_emulate_export() {
+        // PE exports have no size, so add runs up to where sub starts
+        let binary = Binary::new(concat!(env!("CARGO_MANIFEST_DIR"), "/tests/pe/x64.dll")).unwrap();
+        let mut machine = Machine::new(&binary).unwrap();
+        let mut emulator = machine.emulate("add").unwrap();
+        assert_eq!((emulator.address, emulator.end_address), (0x1_8000_1000, 0x1_8000_1003));
+
+        emulator.set_reg("RCX", 40u64).unwrap();
+        emulator.set_reg("RDX", 2u64).unwrap();
+        while let Some(next) = emulator.next() {
+            let (_, pcode) = next.unwrap();
+            let control = emulator.emulate_one(pcode).unwrap();
+            emulator.apply(control).unwrap();
+        }
+        assert_eq!(emulator.reg::<u64>("RAX").unwrap(), 42);
+    }
+
+    #[test]
+    fn test_stack_placement() {
+        let binary = Binary::new(concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fib/bin")).unwrap();
+        let mut machine = Machine::new(&binary).unwrap();
+        let emulator = machine.emulate("main").unwrap();
+        assert_eq!(emulator.reg::<u32>("ESP").unwrap(), 0xFFFF_CBB8);
+        assert_eq!(emulator.ram().permissions(0xFFFF_FFFF), Some(Permissions::READ_WRITE));
+
+        // a 64 bit stack goes where Linux puts it, with the stack pointer aligned the same way
+        let binary = Binary::new(concat!(env!("CARGO_MANIFEST_DIR"), "/tests/pe/x64.dll")).unwrap();
+        let machine = Machine::new(&binary).unwrap();
+        let ram = &machine.spaces[machine.default_space];
+        assert_eq!(stack(ram), (STACK_END_64 - STACK_SIZE..STACK_END_64, 0x7FFF_FFFF_BBB8));
+
+        // a 16 bit space still fits its stack
+        let ram = AddrSpace { addrsize: 2, highest: 0xFFFF, ..ram.clone() };
+        let (region, stack_pointer) = stack(&ram);
+        assert_eq!(region, 0x8000..0x1_0000);
+        assert!(region.contains(&stack_pointer));
+    }
+}
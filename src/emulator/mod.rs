@@ -1,8 +1,25 @@
 
 mod space;
+#[allow(clippy::module_inception)]
 mod emulator;
 mod machine;
+mod error;
+mod float;
+mod userop;
+mod native;
+mod hook;
+mod register;
+mod loader;
+#[cfg(test)]
+mod fixture;
+#[cfg(test)]
+mod tests;
 
-pub use space::Space;
-pub use emulator::{Emulator, PCodeControl};
-pub use machine::Machine;
\ No newline at end of file
+pub use space::{Access, Change, Fault, Permissions, Space, SpaceSnapshot, UnmappedPolicy};
+pub use emulator::{Emulator, PCodeControl, Snapshot};
+pub use machine::Machine;
+pub use error::{EmulationError, ErrorKind};
+pub use float::FloatFormat;
+pub use userop::UserOpHandler;
+pub use native::NATIVE_SIZE;
+pub use hook::{HookId, MmioHandler, ReadHook, WriteHook};
\ No newline at end of file
@@ -0,0 +1,299 @@
+//!
+//! The native integer implementation of the emulator.
+//!
+//! Integer ops whose varnodes are all at most 16 bytes are operated on as `u128`s, with results
+//! masked to the size of the output when written. Only ops with a wider varnode fall back to the
+//! `BigUint` implementation in `Emulator::emulate_wide`.
+
+use sleigh::{Opcode, PCode, SpaceType, VarnodeData};
+use crate::emulator::{Emulator, ErrorKind};
+use crate::emulator::emulator::{decode_error, expect_size, output_of, pointer_offset};
+
+/// the widest varnode, in bytes, that is emulated natively
+pub const NATIVE_SIZE: u32 = 16;
+
+/// Whether every varnode of `pcode` is narrow enough to be emulated natively
+#[inline]
+pub(crate) fn fits(pcode: &PCode) -> bool {
+    pcode.vars.iter()
+        .chain(pcode.outvar.as_ref())
+        .all(|node| node.size <= NATIVE_SIZE)
+}
+
+/// A mask of the low `size` bytes
+#[inline]
+pub(crate) fn mask(size: u32) -> u128 {
+    if size >= NATIVE_SIZE {
+        u128::MAX
+    } else {
+        (1 << (size * 8)) - 1
+    }
+}
+
+/// Sign-extends the low `size` bytes of `value`
+#[inline]
+pub(crate) fn sign_extend(value: u128, size: u32) -> i128 {
+    if size == 0 || size >= NATIVE_SIZE {
+        return value as i128;
+    }
+    let unused = 128 - size * 8;
+    ((value << unused) as i128) >> unused
+}
+
+impl<'a, 'b> Emulator<'a, 'b> {
+    /// Reads a varnode of up to 16 bytes as an unsigned integer
+    pub fn read_native(&self, node: &VarnodeData) -> Result<u128, ErrorKind> {
+        if node.size > NATIVE_SIZE {
+            return Err(ErrorKind::SizeMismatch { expected: NATIVE_SIZE, found: node.size });
+        }
+        if matches!(node.space.type_, SpaceType::Constant) {
+            return Ok(u128::from(node.offset) & mask(node.size));
+        }
+
+        let size = node.size as usize;
+        let mut buffer = [0u8; NATIVE_SIZE as usize];
+        if self.is_big_endian(node)? {
+            self.read_into(node, &mut buffer[NATIVE_SIZE as usize - size..])?;
+            Ok(u128::from_be_bytes(buffer))
+        } else {
+            self.read_into(node, &mut buffer[..size])?;
+            Ok(u128::from_le_bytes(buffer))
+        }
+    }
+
+    /// Writes the low bytes of `value` to a varnode of up to 16 bytes
+    pub fn write_native(&self, node: &VarnodeData, value: u128) -> Result<(), ErrorKind> {
+        if node.size > NATIVE_SIZE {
+            return Err(ErrorKind::SizeMismatch { expected: NATIVE_SIZE, found: node.size });
+        }
+        let size = node.size as usize;
+        if self.is_big_endian(node)? {
+            let bytes = value.to_be_bytes();
+            self.set_bytes(node, &bytes[bytes.len() - size..])
+        } else {
+            let bytes = value.to_le_bytes();
+            self.set_bytes(node, &bytes[..size])
+        }
+    }
+
+    /// Emulates an integer op with native integers. Every varnode must fit, see `fits`.
+    pub(crate) fn emulate_native(&self, pcode: &PCode) -> Result<(), ErrorKind> {
+        match pcode.opcode {
+            Opcode::Copy | Opcode::IntZExt | Opcode::IntSExt | Opcode::Int2Comp | Opcode::IntNegate
+            | Opcode::PopCount | Opcode::LzCount | Opcode::BoolNegate => {
+                let [input0] = pcode.vars.as_slice() else {
+                    return Err(decode_error("expected 1 input"));
+                };
+                let output = output_of(pcode)?;
+
+                if matches!(pcode.opcode, Opcode::Copy | Opcode::Int2Comp | Opcode::IntNegate) {
+                    expect_size(output, input0.size)?;
+                }
+                let value = self.read_native(input0)?;
+                let result = match pcode.opcode {
+                    Opcode::IntSExt => sign_extend(value, input0.size) as u128,
+                    Opcode::Int2Comp => value.wrapping_neg(),
+                    Opcode::IntNegate => !value,
+                    Opcode::PopCount => u128::from(value.count_ones()),
+                    // the value is zero-extended, so don't count the bits above the input
+                    Opcode::LzCount => u128::from(value.leading_zeros() - (128 - input0.size * 8)),
+                    Opcode::BoolNegate => u128::from(value == 0),
+                    _ => value,
+                };
+                self.write_native(output, result)?;
+            }
+            Opcode::IntAdd | Opcode::IntSub | Opcode::IntMult | Opcode::IntAnd | Opcode::IntOr
+            | Opcode::IntXor | Opcode::BoolAnd | Opcode::BoolOr | Opcode::BoolXor => {
+                let [input0, input1] = pcode.vars.as_slice() else {
+                    return Err(decode_error("expected 2 inputs"));
+                };
+                let output = output_of(pcode)?;
+
+                if !matches!(pcode.opcode, Opcode::BoolAnd | Opcode::BoolOr | Opcode::BoolXor) {
+                    expect_size(input1, input0.size)?;
+                }
+                let left = self.read_native(input0)?;
+                let right = self.read_native(input1)?;
+                let result = match pcode.opcode {
+                    Opcode::IntAdd => left.wrapping_add(right),
+                    Opcode::IntSub => left.wrapping_sub(right),
+                    Opcode::IntMult => left.wrapping_mul(right),
+                    Opcode::IntAnd => left & right,
+                    Opcode::IntOr => left | right,
+                    Opcode::IntXor => left ^ right,
+                    Opcode::BoolAnd => u128::from(left != 0 && right != 0),
+                    Opcode::BoolOr => u128::from(left != 0 || right != 0),
+                    _ => u128::from((left != 0) ^ (right != 0)),
+                };
+                self.write_native(output, result)?;
+            }
+            Opcode::IntDiv | Opcode::IntRem | Opcode::IntSDiv | Opcode::IntSRem => {
+                let [input0, input1] = pcode.vars.as_slice() else {
+                    return Err(decode_error("expected 2 inputs"));
+                };
+                let output = output_of(pcode)?;
+
+                expect_size(input1, input0.size)?;
+                let left = self.read_native(input0)?;
+                let right = self.read_native(input1)?;
+                if right == 0 {
+                    return Err(ErrorKind::DivisionByZero);
+                }
+                // signed division truncates toward zero, MIN / -1 wraps
+                let (signed_left, signed_right) = (sign_extend(left, input0.size), sign_extend(right, input1.size));
+                let result = match pcode.opcode {
+                    Opcode::IntDiv => left / right,
+                    Opcode::IntRem => left % right,
+                    Opcode::IntSDiv => signed_left.wrapping_div(signed_right) as u128,
+                    _ => signed_left.wrapping_rem(signed_right) as u128,
+                };
+                self.write_native(output, result)?;
+            }
+            Opcode::IntLeft | Opcode::IntRight | Opcode::IntSRight => {
+                let [input0, input1] = pcode.vars.as_slice() else {
+                    return Err(decode_error("expected 2 inputs"));
+                };
+                let output = output_of(pcode)?;
+
+                expect_size(output, input0.size)?;
+                let value = self.read_native(input0)?;
+                let amount = self.read_native(input1)?;
+                // shifting out every bit leaves zeros, or copies of the sign bit
+                let result = match (pcode.opcode, u32::try_from(amount).ok().filter(|amount| *amount < input0.size * 8)) {
+                    (Opcode::IntLeft, Some(amount)) => value << amount,
+                    (Opcode::IntRight, Some(amount)) => value >> amount,
+                    (Opcode::IntSRight, Some(amount)) => (sign_extend(value, input0.size) >> amount) as u128,
+                    (Opcode::IntSRight, None) => (sign_extend(value, input0.size) >> 127) as u128,
+                    _ => 0,
+                };
+                self.write_native(output, result)?;
+            }
+            Opcode::IntEqual | Opcode::IntNotEqual | Opcode::IntLess | Opcode::IntLessEqual
+            | Opcode::IntSLess | Opcode::IntSLessEqual => {
+                let [input0, input1] = pcode.vars.as_slice() else {
+                    return Err(decode_error("expected 2 inputs"));
+                };
+                let output = output_of(pcode)?;
+
+                expect_size(input1, input0.size)?;
+                expect_size(output, 1)?;
+                let left = self.read_native(input0)?;
+                let right = self.read_native(input1)?;
+                let (signed_left, signed_right) = (sign_extend(left, input0.size), sign_extend(right, input1.size));
+                let result = match pcode.opcode {
+                    Opcode::IntEqual => left == right,
+                    Opcode::IntNotEqual => left != right,
+                    Opcode::IntLess => left < right,
+                    Opcode::IntLessEqual => left <= right,
+                    Opcode::IntSLess => signed_left < signed_right,
+                    _ => signed_left <= signed_right,
+                };
+                self.write_native(output, u128::from(result))?;
+            }
+            Opcode::IntCarry | Opcode::IntSCarry | Opcode::IntSBorrow => {
+                let [input0, input1] = pcode.vars.as_slice() else {
+                    return Err(decode_error("expected 2 inputs"));
+                };
+                let output = output_of(pcode)?;
+
+                expect_size(input1, input0.size)?;
+                expect_size(output, 1)?;
+                let size = input0.size;
+                let left = self.read_native(input0)?;
+                let right = self.read_native(input1)?;
+                let (signed_left, signed_right) = (sign_extend(left, size), sign_extend(right, size));
+                let result = match pcode.opcode {
+                    // the unsigned sum doesn't fit in `size` bytes
+                    Opcode::IntCarry => {
+                        let (sum, overflow) = left.overflowing_add(right);
+                        overflow || sum > mask(size)
+                    }
+                    // the signed sum or difference doesn't fit in `size` bytes
+                    Opcode::IntSCarry => {
+                        let (sum, overflow) = signed_left.overflowing_add(signed_right);
+                        overflow || sign_extend(sum as u128, size) != sum
+                    }
+                    _ => {
+                        let (difference, overflow) = signed_left.overflowing_sub(signed_right);
+                        overflow || sign_extend(difference as u128, size) != difference
+                    }
+                };
+                self.write_native(output, u128::from(result))?;
+            }
+            Opcode::Piece => {
+                let [input0, input1] = pcode.vars.as_slice() else {
+                    return Err(decode_error("expected 2 inputs"));
+                };
+                let output = output_of(pcode)?;
+
+                expect_size(output, input0.size + input1.size)?;
+                let high = self.read_native(input0)?;
+                let low = self.read_native(input1)?;
+                let result = high.checked_shl(input1.size * 8).unwrap_or(0) | low;
+                self.write_native(output, result)?;
+            }
+            Opcode::SubPiece => {
+                let [input0, input1] = pcode.vars.as_slice() else {
+                    return Err(decode_error("expected 2 inputs"));
+                };
+                let output = output_of(pcode)?;
+
+                if input1.space.type_ != SpaceType::Constant {
+                    return Err(decode_error("expected input1 to be a constant"));
+                }
+                let value = self.read_native(input0)?;
+                let result = u32::try_from(input1.offset * 8).ok()
+                    .and_then(|amount| value.checked_shr(amount))
+                    .unwrap_or(0);
+                self.write_native(output, result)?;
+            }
+            Opcode::Load => {
+                let [input0, input1] = pcode.vars.as_slice() else {
+                    return Err(decode_error("expected 2 inputs"));
+                };
+                let output = output_of(pcode)?;
+
+                let space = self.get_space_from_const(input0)?;
+                let offset = pointer_offset(&space, self.read_native(input1)? as u64);
+                let varnode = VarnodeData { space, offset, size: output.size };
+                let value = self.read_native(&varnode)?;
+                self.write_native(output, value)?;
+            }
+            Opcode::Store => {
+                let [input0, input1, input2] = pcode.vars.as_slice() else {
+                    return Err(decode_error("expected 3 inputs"));
+                };
+
+                let space = self.get_space_from_const(input0)?;
+                let offset = pointer_offset(&space, self.read_native(input1)? as u64);
+                let value = self.read_native(input2)?;
+                let varnode = VarnodeData { space, offset, size: input2.size };
+                self.write_native(&varnode, value)?;
+            }
+            _ => return Err(ErrorKind::UnimplementedOpcode(pcode.opcode)),
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sign_extend() {
+        assert_eq!(sign_extend(0xFF, 1), -1);
+        assert_eq!(sign_extend(0x7F, 1), 127);
+        assert_eq!(sign_extend(0x8000_0000, 4), i32::MIN as i128);
+        assert_eq!(sign_extend(0x1_0000_0000, 4), 0);
+        assert_eq!(sign_extend(u128::MAX, 16), -1);
+    }
+
+    #[test]
+    fn test_mask() {
+        assert_eq!(mask(1), 0xFF);
+        assert_eq!(mask(8), u64::MAX as u128);
+        assert_eq!(mask(16), u128::MAX);
+    }
+}
@@ -0,0 +1,129 @@
+//!
+//! Access to registers by name.
+//!
+//! Registers are varnodes in the register space, so a register that overlaps another (AL, AX
+//! and EAX on x86) is just a smaller range of the same bytes. Overlaps are resolved by comparing
+//! those ranges rather than relying on sleigh having a name for every piece.
+
+use std::fmt::Write;
+use sleigh::VarnodeData;
+use crate::emulator::{Emulator, ErrorKind, space};
+
+/// Whether `inner` lies entirely within `outer`
+fn contains(outer: &VarnodeData, inner: &VarnodeData) -> bool {
+    outer.space == inner.space
+        && outer.offset <= inner.offset
+        && inner.offset + u64::from(inner.size) <= outer.offset + u64::from(outer.size)
+}
+
+/// Whether two varnodes share any bytes
+fn overlaps(a: &VarnodeData, b: &VarnodeData) -> bool {
+    a.space == b.space
+        && a.offset < b.offset + u64::from(b.size)
+        && b.offset < a.offset + u64::from(a.size)
+}
+
+impl<'a, 'b> Emulator<'a, 'b> {
+    /// The varnode of the register called `name`, which is matched case-insensitively if there's
+    /// no exact match
+    pub fn register(&self, name: &str) -> Result<&VarnodeData, ErrorKind> {
+        self.get_register(name)
+            .or_else(|| self.emulator.folded_registers.get(&name.to_ascii_lowercase()))
+            .ok_or_else(|| ErrorKind::UnknownRegister(name.to_string()))
+    }
+
+    /// Reads the register called `name`
+    pub fn reg<T: space::Read>(&self, name: &str) -> Result<T, ErrorKind> {
+        self.read(self.register(name)?)
+    }
+
+    /// Writes the register called `name`, truncating the value to the register's size
+    pub fn set_reg<T: space::Write>(&self, name: &str, value: T) -> Result<(), ErrorKind> {
+        self.write(self.register(name)?, value)
+    }
+
+    /// The names of the registers that share bytes with the register called `name`, smallest
+    /// first. Writing any of them changes the value of `name`.
+    pub fn register_aliases(&self, name: &str) -> Result<Vec<&str>, ErrorKind> {
+        let register = self.register(name)?;
+        let mut aliases = self.emulator.register_names.iter()
+            .filter(|(node, _)| *node != register && overlaps(node, register))
+            .map(|(node, name)| (node.size, node.offset, name.as_str()))
+            .collect::<Vec<_>>();
+        aliases.sort_unstable();
+        Ok(aliases.into_iter().map(|(_, _, name)| name).collect())
+    }
+
+    /// The smallest named register that contains all of `node`
+    pub fn containing_register(&self, node: &VarnodeData) -> Option<(&str, &VarnodeData)> {
+        self.emulator.register_names.iter()
+            .filter(|(register, _)| contains(register, node))
+            .min_by_key(|(register, name)| (register.size, name.as_str()))
+            .map(|(register, name)| (name.as_str(), register))
+    }
+
+    /// The values of the general purpose registers, in the machine's order
+    pub fn general_registers(&self) -> Result<Vec<(&str, u128)>, ErrorKind> {
+        self.emulator.general_registers.iter()
+            .map(|name| Ok((name.as_str(), self.reg(name)?)))
+            .collect()
+    }
+
+    /// The general purpose registers and flags, formatted as a table
+    pub fn dump_registers(&self) -> Result<String, ErrorKind> {
+        let mut out = String::new();
+        for row in self.general_registers()?.chunks(4) {
+            let line = row.iter()
+                .map(|(name, value)| {
+                    let width = self.register(name).map_or(8, |node| node.size as usize * 2);
+                    format!("{: >4} {:0>width$X}", name, value, width = width)
+                })
+                .collect::<Vec<_>>()
+                .join("  ");
+            let _ = writeln!(out, "{}", line);
+        }
+
+        let flags = self.emulator.flag_registers.iter()
+            .map(|name| Ok(format!("{}={}", name, self.reg::<u8>(name)?)))
+            .collect::<Result<Vec<_>, ErrorKind>>()?;
+        if !flags.is_empty() {
+            let _ = writeln!(out, "{}", flags.join(" "));
+        }
+        Ok(out)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::emulator::fixture::{emulator, unique};
+
+    #[test]
+    fn test_registers() {
+        emulator!(emulator);
+
+        emulator.set_reg("EAX", 0x1234_5678u32).unwrap();
+        assert_eq!(emulator.reg::<u32>("EAX").unwrap(), 0x1234_5678);
+        assert_eq!(emulator.reg::<u32>("eax").unwrap(), 0x1234_5678);
+        assert_eq!(emulator.reg::<u32>("eAx").unwrap(), 0x1234_5678);
+        assert_eq!(emulator.reg::<u32>(emulator.emulator.architecture.return_register()).unwrap(), 0x1234_5678);
+        assert_eq!(emulator.reg::<u16>("AX").unwrap(), 0x5678);
+        assert_eq!(emulator.reg::<u8>("AH").unwrap(), 0x56);
+
+        emulator.set_reg("AL", 0xFFu8).unwrap();
+        assert_eq!(emulator.reg::<u32>("EAX").unwrap(), 0x1234_56FF);
+
+        let aliases = emulator.register_aliases("EAX").unwrap();
+        assert!(["AL", "AH", "AX"].iter().all(|name| aliases.contains(name)));
+        assert!(!aliases.contains(&"ECX"));
+
+        let eax = emulator.register("EAX").unwrap().clone();
+        let high = VarnodeData { offset: eax.offset + 2, size: 2, ..eax };
+        assert_eq!(emulator.nameof(&high), "EAX[2:2]");
+        // temporaries aren't searched for a containing register
+        assert_eq!(emulator.nameof(&unique(0x100, 4)), "unique:100+4");
+
+        assert!(matches!(emulator.reg::<u32>("NOPE"), Err(ErrorKind::UnknownRegister(_))));
+        assert!(emulator.dump_registers().unwrap().contains("EAX 123456FF"));
+    }
+}
@@ -1,61 +1,811 @@
-use std::cell::{Ref, RefCell};
+use std::cell::RefCell;
 use std::collections::BTreeMap;
-use num::{BigInt, BigUint, Zero};
-use num::bigint::Sign;
+use std::ops::Range;
+use std::rc::Rc;
+use std::fmt::{Display, Formatter};
+use hashbrown::{HashMap, HashSet};
+use sleigh::AddrSpace;
+use num::{BigInt, BigUint, Integer, One};
 
-#[derive(Default, Debug)]
+/// the number of address bits covered by a single page
+pub const PAGE_BITS: u32 = 12;
+/// the size of a page in bytes
+pub const PAGE_SIZE: usize = 1 << PAGE_BITS;
+
+/// Pages are shared between a space and its snapshots, and copied the first time they're written
+type Page = Rc<[u8; PAGE_SIZE]>;
+
+/// A kind of memory access
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Access {
+    Read,
+    Write,
+    Execute,
+}
+
+impl Display for Access {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Access::Read => write!(f, "read"),
+            Access::Write => write!(f, "write"),
+            Access::Execute => write!(f, "execute"),
+        }
+    }
+}
+
+/// The accesses allowed on a mapped region
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Permissions {
+    pub read: bool,
+    pub write: bool,
+    pub execute: bool,
+}
+
+impl Permissions {
+    pub const READ: Self = Self { read: true, write: false, execute: false };
+    pub const READ_WRITE: Self = Self { read: true, write: true, execute: false };
+    pub const READ_EXECUTE: Self = Self { read: true, write: false, execute: true };
+    pub const ALL: Self = Self { read: true, write: true, execute: true };
+
+    pub fn allows(&self, access: Access) -> bool {
+        match access {
+            Access::Read => self.read,
+            Access::Write => self.write,
+            Access::Execute => self.execute,
+        }
+    }
+}
+
+impl Display for Permissions {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let flag = |allowed: bool, c: char| if allowed { c } else { '-' };
+        write!(f, "{}{}{}", flag(self.read, 'r'), flag(self.write, 'w'), flag(self.execute, 'x'))
+    }
+}
+
+/// What happens when an address outside of every mapped region is accessed
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum UnmappedPolicy {
+    /// reads are zero and writes succeed
+    #[default]
+    ZeroFill,
+    /// the access faults
+    Fault,
+}
+
+/// An access that isn't allowed, at the first offending address
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Fault {
+    /// the address isn't in any mapped region
+    Unmapped(u64),
+    /// the address is mapped, but without permission for the access
+    Denied(u64),
+}
+
+/// flat spaces written past this many bytes move their contents into pages
+const FLAT_LIMIT: usize = 1 << 20;
+
+/// the number of snapshots stacked on each other before the next one holds every page again
+const MAX_DEPTH: usize = 64;
+
+/// How the bytes of a space are held
+#[derive(Debug)]
+enum Storage {
+    /// a map of page number to page, for large sparse spaces like ram
+    Paged(Paged),
+    /// one buffer starting at offset zero, for small dense spaces like registers
+    Flat(Flat),
+}
+
+#[derive(Debug, Default)]
+struct Paged {
+    pages: HashMap<u64, Page>,
+    /// the pages written or cleared since the last snapshot
+    dirty: HashSet<u64>,
+}
+
+#[derive(Debug, Default)]
+struct Flat {
+    bytes: Vec<u8>,
+    /// the bytes written since the last clear
+    written: Range<usize>,
+    /// the bytes written or cleared since the last snapshot
+    dirty: Range<usize>,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Region {
+    /// the exclusive end of the region
+    end: u64,
+    permissions: Permissions,
+}
+
+/// A flat address space. Memory is normally stored in 4 KiB pages that are allocated the first
+/// time they're written to, and anything that has never been written reads as zero. Small, dense
+/// spaces can instead be held in a single buffer, see `Space::flat`.
+#[derive(Debug)]
 pub struct Space {
-    /// whether the space is big endian or little endian
-    #[allow(unused)]
+    /// the name of the space in the language
+    name: String,
+    /// whether values in the space are big endian or little endian
     big_endian: bool,
-    /// a map of address to byte
-    inner: RefCell<BTreeMap<u64, u8>>,
-    /// an owned buffer to use as temporary storage for get_out
-    buffer: RefCell<Vec<u8>>,
+    /// the number of bytes in an addressable unit
+    wordsize: u32,
+    /// the number of bytes in an address
+    addrsize: u32,
+    /// the highest byte offset, accesses past it wrap around to zero
+    highest: u64,
+    /// the contents of the space
+    storage: RefCell<Storage>,
+    /// the mapped regions, by start address
+    regions: BTreeMap<u64, Region>,
+    /// what to do on an access outside of every region
+    policy: UnmappedPolicy,
+    /// the pages as of the last snapshot taken or restored
+    history: RefCell<Option<Rc<Layer>>>,
+}
+
+impl Default for Space {
+    fn default() -> Self {
+        Self::new(false)
+    }
+}
+
+impl From<&AddrSpace> for Space {
+    fn from(space: &AddrSpace) -> Self {
+        Self {
+            name: space.name.clone(),
+            big_endian: space.is_big_endian,
+            wordsize: space.wordsize,
+            addrsize: space.addrsize,
+            highest: space.highest,
+            ..Self::default()
+        }
+    }
 }
 
 impl Space {
+    /// A byte addressed space covering every 64 bit offset
     pub fn new(big_endian: bool) -> Self {
         Self {
+            name: String::new(),
             big_endian,
-            inner: RefCell::default(),
-            buffer: RefCell::new(vec![0; 4]),
+            wordsize: 1,
+            addrsize: 8,
+            highest: u64::MAX,
+            storage: RefCell::new(Storage::Paged(Paged::default())),
+            regions: BTreeMap::new(),
+            policy: UnmappedPolicy::ZeroFill,
+            history: RefCell::new(None),
+        }
+    }
+
+    /// A space held in one buffer of `size` bytes, which is much cheaper to access than pages for
+    /// small spaces like registers and unique. The buffer grows if it's written past the end.
+    pub fn flat(space: &AddrSpace, size: usize) -> Self {
+        let flat = Flat { bytes: vec![0; size], ..Flat::default() };
+        Self {
+            storage: RefCell::new(Storage::Flat(flat)),
+            ..Self::from(space)
+        }
+    }
+
+    pub fn is_flat(&self) -> bool {
+        matches!(*self.storage.borrow(), Storage::Flat(_))
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn is_big_endian(&self) -> bool {
+        self.big_endian
+    }
+
+    pub fn wordsize(&self) -> u32 {
+        self.wordsize
+    }
+
+    pub fn addrsize(&self) -> u32 {
+        self.addrsize
+    }
+
+    pub fn highest(&self) -> u64 {
+        self.highest
+    }
+
+    /// Wraps an offset into the space
+    #[inline]
+    pub fn wrap(&self, offset: u64) -> u64 {
+        wrap(offset, 0, self.highest)
+    }
+
+    /// Reads a value of `size` bytes at `addr` in the space's byte order
+    pub fn read_value<T: Read>(&self, addr: u64, size: usize) -> Option<T> {
+        T::read(self.big_endian, &self.get_bytes(addr, size as u64))
+    }
+
+    /// Writes a value as `size` bytes at `addr` in the space's byte order
+    pub fn write_value<T: Write>(&self, addr: u64, size: usize, value: T) {
+        let mut bytes = vec![0; size];
+        value.write(self.big_endian, &mut bytes);
+        self.write_from(addr, &bytes);
+    }
+
+    /// Maps `size` bytes at `start` with the given permissions, replacing whatever was mapped
+    /// there before
+    pub fn map(&mut self, start: u64, size: u64, permissions: Permissions) {
+        let end = start.saturating_add(size);
+        if start == end {
+            return;
+        }
+
+        let overlapping = self.regions.range(..end)
+            .filter(|(_, region)| region.end > start)
+            .map(|(start, region)| (*start, *region))
+            .collect::<Vec<_>>();
+        for (old_start, old) in overlapping {
+            self.regions.remove(&old_start);
+            if old_start < start {
+                self.regions.insert(old_start, Region { end: start, ..old });
+            }
+            if old.end > end {
+                self.regions.insert(end, old);
+            }
+        }
+        self.regions.insert(start, Region { end, permissions });
+    }
+
+    /// The permissions of the region containing `addr`, if it's mapped
+    pub fn permissions(&self, addr: u64) -> Option<Permissions> {
+        self.region(addr).map(|(_, region)| region.permissions)
+    }
+
+    pub fn set_unmapped_policy(&mut self, policy: UnmappedPolicy) {
+        self.policy = policy;
+    }
+
+    pub fn unmapped_policy(&self) -> UnmappedPolicy {
+        self.policy
+    }
+
+    /// Checks that `len` bytes starting at `addr` can be accessed. Bytes outside of every region
+    /// are allowed or not according to the unmapped policy.
+    pub fn check(&self, addr: u64, len: u64, access: Access) -> Result<(), Fault> {
+        if self.regions.is_empty() && self.policy == UnmappedPolicy::ZeroFill {
+            return Ok(());
+        }
+
+        // an access running past the highest offset continues from zero
+        let addr = self.wrap(addr);
+        let room = (self.highest - addr).saturating_add(1);
+        if len > room {
+            self.check(addr, room, access)?;
+            return self.check(0, len - room, access);
+        }
+
+        let end = addr.saturating_add(len);
+        let mut addr = addr;
+        while addr < end {
+            match self.region(addr) {
+                Some((_, region)) if !region.permissions.allows(access) => return Err(Fault::Denied(addr)),
+                Some((_, region)) => addr = region.end,
+                None if self.policy == UnmappedPolicy::Fault => return Err(Fault::Unmapped(addr)),
+                None => {
+                    addr = self.regions.range(addr..)
+                        .next()
+                        .map_or(end, |(start, _)| *start);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn region(&self, addr: u64) -> Option<(u64, &Region)> {
+        self.regions.range(..=addr)
+            .next_back()
+            .filter(|(_, region)| region.end > addr)
+            .map(|(start, region)| (*start, region))
+    }
+
+    /// Reads `size` bytes starting at `addr`
+    pub fn get_bytes(&self, addr: u64, size: u64) -> Vec<u8> {
+        let mut bytes = vec![0; size as usize];
+        self.read_into(addr, &mut bytes);
+        bytes
+    }
+
+    /// Fills `buffer` with the bytes starting at `addr`, without checking permissions
+    pub fn read_into(&self, addr: u64, buffer: &mut [u8]) {
+        let storage = self.storage.borrow();
+        let pages = match &*storage {
+            Storage::Paged(paged) => &paged.pages,
+            Storage::Flat(flat) => return flat.read_into(addr, buffer, self.highest),
+        };
+        for_each_chunk(addr, buffer.len(), self.highest, |page, range, chunk| {
+            let dest = &mut buffer[chunk];
+            match pages.get(&page) {
+                Some(page) => dest.copy_from_slice(&page[range]),
+                None => dest.fill(0),
+            }
+        });
+    }
+
+    /// Writes `bytes` starting at `addr`, allocating any pages they land on. Permissions aren't
+    /// checked, so this is also how read-only memory is loaded.
+    pub fn write_from(&self, addr: u64, bytes: &[u8]) {
+        let mut storage = self.storage.borrow_mut();
+        if let Storage::Flat(flat) = &mut *storage {
+            if flat.write_from(addr, bytes, self.highest) {
+                return;
+            }
+            // too far out (or wrapped around) to keep in one buffer
+            *storage = Storage::Paged(Paged { pages: flat.pages(), dirty: flat.dirty_pages().collect() });
+        }
+        if let Storage::Paged(paged) = &mut *storage {
+            for_each_chunk(addr, bytes.len(), self.highest, |page, range, chunk| {
+                paged.dirty.insert(page);
+                let page = paged.pages.entry(page).or_insert_with(|| Rc::new([0; PAGE_SIZE]));
+                Rc::make_mut(page)[range].copy_from_slice(&bytes[chunk]);
+            });
+        }
+    }
+
+    /// Zeroes the whole space. A flat space only zeroes what was written since it was last
+    /// cleared, so clearing a scratch space that's barely used is cheap.
+    pub fn clear(&self) {
+        match &mut *self.storage.borrow_mut() {
+            Storage::Paged(paged) => {
+                paged.dirty.extend(paged.pages.drain().map(|(page, _)| page));
+            }
+            Storage::Flat(flat) => {
+                let written = std::mem::take(&mut flat.written);
+                flat.dirty = union(&flat.dirty, written.clone());
+                flat.bytes[written].fill(0);
+            }
+        }
+    }
+
+    /// The number of pages that have been allocated, or that the buffer of a flat space spans
+    pub fn page_count(&self) -> usize {
+        match &*self.storage.borrow() {
+            Storage::Paged(paged) => paged.pages.len(),
+            Storage::Flat(flat) => flat.bytes.len().div_ceil(PAGE_SIZE),
+        }
+    }
+
+    /// Every page that has been written, copying them out of the buffer of a flat space
+    fn all_pages(&self) -> HashMap<u64, Page> {
+        match &*self.storage.borrow() {
+            Storage::Paged(paged) => paged.pages.clone(),
+            Storage::Flat(flat) => flat.pages(),
+        }
+    }
+
+    /// The pages written or cleared since the last snapshot was taken or restored
+    fn dirty(&self) -> HashSet<u64> {
+        match &*self.storage.borrow() {
+            Storage::Paged(paged) => paged.dirty.clone(),
+            Storage::Flat(flat) => flat.dirty_pages().collect(),
+        }
+    }
+
+    fn reset_dirty(&self) {
+        match &mut *self.storage.borrow_mut() {
+            Storage::Paged(paged) => paged.dirty.clear(),
+            Storage::Flat(flat) => flat.dirty = 0..0,
+        }
+    }
+
+    /// The pages that might have changed since `checkpoint`, or `None` if it isn't an earlier
+    /// snapshot of the space (one taken after a snapshot that was restored since, say)
+    fn touched_since(&self, checkpoint: &Layer) -> Option<HashSet<u64>> {
+        let mut pages = self.history.borrow().as_ref()?.touched_since(checkpoint)?;
+        pages.extend(self.dirty());
+        Some(pages)
+    }
+
+    /// The pages that have changed since `checkpoint`, in address order
+    fn changed_pages(&self, checkpoint: &SpaceSnapshot) -> Vec<u64> {
+        let candidates = self.touched_since(&checkpoint.layer).unwrap_or_else(|| {
+            let mut pages = checkpoint.layer.materialize().into_keys().collect::<HashSet<_>>();
+            pages.extend(self.all_pages().into_keys());
+            pages
+        });
+        dirty_pages(candidates, &*checkpoint.layer, self)
+    }
+
+    /// Captures the contents and memory map of the space. A snapshot holds the pages written
+    /// since the one before it, which it shares with the space until one side writes to them, so
+    /// taking one costs a reference count per page that changed. Flat spaces copy the pages that
+    /// changed, which is cheap because they're small.
+    pub fn snapshot(&self) -> SpaceSnapshot {
+        let parent = self.history.borrow().clone();
+        let layer = match parent {
+            Some(parent) if parent.depth < MAX_DEPTH => {
+                let pages = self.dirty().into_iter()
+                    .map(|page| (page, Pages::page(self, page)))
+                    .collect();
+                Layer { depth: parent.depth + 1, parent: Some(parent), pages }
+            }
+            // every so often start over, so looking a page up never walks too many snapshots
+            _ => Layer {
+                parent: None,
+                pages: self.all_pages().into_iter().map(|(page, data)| (page, Some(data))).collect(),
+                depth: 0,
+            },
+        };
+        self.reset_dirty();
+        let layer = Rc::new(layer);
+        self.history.replace(Some(layer.clone()));
+        SpaceSnapshot {
+            layer,
+            regions: self.regions.clone(),
+            policy: self.policy,
+        }
+    }
+
+    /// The pages whose contents have changed since `checkpoint` was taken, in address order
+    pub fn dirty_pages(&self, checkpoint: &SpaceSnapshot) -> Vec<u64> {
+        self.changed_pages(checkpoint)
+    }
+
+    /// The ranges of bytes whose values have changed since `checkpoint` was taken, in address
+    /// order. Bytes that were written with the value they already had aren't included.
+    pub fn dirty_ranges(&self, checkpoint: &SpaceSnapshot) -> Vec<Range<u64>> {
+        changed_ranges(&self.changed_pages(checkpoint), &*checkpoint.layer, self)
+    }
+
+    /// The bytes that have changed since `checkpoint` was taken
+    pub fn diff(&self, checkpoint: &SpaceSnapshot) -> Vec<Change> {
+        changes(&self.changed_pages(checkpoint), &*checkpoint.layer, self)
+    }
+
+    /// Puts the space back the way it was when `snapshot` was taken. Going back to an earlier
+    /// snapshot only puts back the pages that changed since.
+    pub fn restore(&mut self, snapshot: &SpaceSnapshot) {
+        let layer = &snapshot.layer;
+        match self.touched_since(layer) {
+            Some(pages) => {
+                let storage = self.storage.get_mut();
+                for page in pages {
+                    storage.set_page(page, layer.page(page));
+                }
+            }
+            None => match self.storage.get_mut() {
+                Storage::Paged(paged) => paged.pages = layer.materialize(),
+                Storage::Flat(flat) => flat.restore(&layer.materialize()),
+            },
         }
+        self.reset_dirty();
+        self.history.replace(Some(layer.clone()));
+        self.regions = snapshot.regions.clone();
+        self.policy = snapshot.policy;
     }
+}
 
-    pub fn get_bytes(&self, addr: u64, size: u64) -> Ref<[u8]> {
-        let inner = self.inner.borrow_mut();
-        let mut buffer = self.buffer.borrow_mut();
-        buffer.resize(size as usize, 0u8); // fill the rest with 0
+impl Pages for Space {
+    fn page(&self, page: u64) -> Option<Page> {
+        match &*self.storage.borrow() {
+            Storage::Paged(paged) => paged.pages.get(&page).cloned(),
+            Storage::Flat(flat) => flat.page(page),
+        }
+    }
+}
 
-        let start = addr;
-        let end = start + size;
-        let mut last_key = start;
-        // fill the buffer with the bytes from the map, we manually fill the gaps with 0
-        for (key, value) in inner.range(start..end) {
-            buffer[0..(key - last_key) as usize].fill(0u8);
-            buffer[(key - start) as usize] = *value;
-            last_key = key + 1;
+impl Storage {
+    /// Replaces the contents of page number `page`, zeroing it for `None`
+    fn set_page(&mut self, page: u64, data: Option<Page>) {
+        match self {
+            Storage::Paged(paged) => match data {
+                Some(data) => {
+                    paged.pages.insert(page, data);
+                }
+                None => {
+                    paged.pages.remove(&page);
+                }
+            },
+            Storage::Flat(flat) => flat.set_page(page, data.as_ref()),
+        }
+    }
+}
+
+impl Flat {
+    #[inline]
+    fn read_into(&self, addr: u64, buffer: &mut [u8], highest: u64) {
+        match contiguous(addr, buffer.len(), highest) {
+            Some(range) if range.end <= self.bytes.len() => buffer.copy_from_slice(&self.bytes[range]),
+            _ => {
+                // past the end of the buffer, or wrapping around
+                for (i, byte) in buffer.iter_mut().enumerate() {
+                    let offset = wrap(addr, i as u64, highest);
+                    *byte = usize::try_from(offset).ok()
+                        .and_then(|offset| self.bytes.get(offset))
+                        .map_or(0, |byte| *byte);
+                }
+            }
+        }
+    }
+
+    /// Writes `bytes` at `addr`, growing the buffer if needed. Returns false without writing
+    /// anything if they don't fit in a buffer of at most `FLAT_LIMIT` bytes.
+    #[inline]
+    fn write_from(&mut self, addr: u64, bytes: &[u8], highest: u64) -> bool {
+        let Some(range) = contiguous(addr, bytes.len(), highest).filter(|range| range.end <= FLAT_LIMIT) else {
+            return false;
+        };
+        if range.end > self.bytes.len() {
+            self.bytes.resize(range.end, 0);
         }
-        buffer.resize(size as usize, 0u8); // fill the rest with 0
-        // fill the rest of the buffer with 0
-        buffer[(last_key - start) as usize..size as usize].fill(0u8);
-        drop(buffer);
+        self.bytes[range.clone()].copy_from_slice(bytes);
+        self.written = union(&self.written, range.clone());
+        self.dirty = union(&self.dirty, range);
+        true
+    }
 
-        Ref::map(self.buffer.borrow(), |vec| &vec[..size as usize])
+    /// The pages holding the bytes written since the last snapshot
+    fn dirty_pages(&self) -> impl Iterator<Item = u64> {
+        let pages = if self.dirty.is_empty() {
+            0..0
+        } else {
+            self.dirty.start / PAGE_SIZE..(self.dirty.end - 1) / PAGE_SIZE + 1
+        };
+        pages.map(|page| page as u64)
     }
 
-    pub fn set_bytes(&self, addr: u64, bytes: &[u8]) {
-        let mut inner = self.inner.borrow_mut();
-        let start = addr;
-        for (i, byte) in bytes.iter().enumerate() {
-            inner.insert(start + i as u64, *byte);
+    /// A copy of page number `page`, or `None` if it's all zero
+    fn page(&self, page: u64) -> Option<Page> {
+        let start = usize::try_from(page).ok()?.checked_mul(PAGE_SIZE)?;
+        let chunk = self.bytes.get(start..)?;
+        let chunk = &chunk[..chunk.len().min(PAGE_SIZE)];
+        chunk.iter().any(|byte| *byte != 0).then(|| {
+            let mut data = [0; PAGE_SIZE];
+            data[..chunk.len()].copy_from_slice(chunk);
+            Rc::new(data)
+        })
+    }
+
+    /// The contents of the buffer as pages, leaving out pages that are all zero
+    fn pages(&self) -> HashMap<u64, Page> {
+        (0..self.bytes.len().div_ceil(PAGE_SIZE) as u64)
+            .filter_map(|page| Some((page, self.page(page)?)))
+            .collect()
+    }
+
+    /// Replaces the contents of page number `page`, zeroing it for `None`
+    fn set_page(&mut self, page: u64, data: Option<&Page>) {
+        let start = page as usize * PAGE_SIZE;
+        match data {
+            Some(data) => {
+                if self.bytes.len() < start + PAGE_SIZE {
+                    self.bytes.resize(start + PAGE_SIZE, 0);
+                }
+                self.bytes[start..start + PAGE_SIZE].copy_from_slice(&data[..]);
+                self.written = union(&self.written, start..start + PAGE_SIZE);
+            }
+            None => {
+                if let Some(chunk) = self.bytes.get_mut(start..) {
+                    let len = chunk.len().min(PAGE_SIZE);
+                    chunk[..len].fill(0);
+                }
+            }
+        }
+    }
+
+    fn restore(&mut self, pages: &HashMap<u64, Page>) {
+        self.bytes.fill(0);
+        for (page, data) in pages {
+            let start = *page as usize * PAGE_SIZE;
+            if self.bytes.len() < start + PAGE_SIZE {
+                self.bytes.resize(start + PAGE_SIZE, 0);
+            }
+            self.bytes[start..start + PAGE_SIZE].copy_from_slice(&data[..]);
         }
+        self.written = 0..self.bytes.len();
     }
 }
 
-pub trait Read {
-    fn read(is_big_endian: bool, src: &[u8]) -> Self;
+/// The smallest range covering both `a` and `b`
+fn union(a: &Range<usize>, b: Range<usize>) -> Range<usize> {
+    if a.is_empty() {
+        b
+    } else if b.is_empty() {
+        a.clone()
+    } else {
+        a.start.min(b.start)..a.end.max(b.end)
+    }
+}
+
+/// The range of offsets holding `len` bytes from `addr`, unless they wrap around past `highest`
+#[inline]
+fn contiguous(addr: u64, len: usize, highest: u64) -> Option<Range<usize>> {
+    let start = wrap(addr, 0, highest);
+    if len as u64 > (highest - start).saturating_add(1) {
+        return None;
+    }
+    let start = usize::try_from(start).ok()?;
+    Some(start..start.checked_add(len)?)
+}
+
+/// The state of a `Space` at some point, see `Space::snapshot`
+#[derive(Debug, Clone)]
+pub struct SpaceSnapshot {
+    layer: Rc<Layer>,
+    regions: BTreeMap<u64, Region>,
+    policy: UnmappedPolicy,
+}
+
+impl SpaceSnapshot {
+    /// The bytes that changed between this snapshot and a later one
+    pub fn diff(&self, newer: &SpaceSnapshot) -> Vec<Change> {
+        let candidates = newer.layer.touched_since(&self.layer).unwrap_or_else(|| {
+            let mut pages = self.layer.materialize().into_keys().collect::<HashSet<_>>();
+            pages.extend(newer.layer.materialize().into_keys());
+            pages
+        });
+        changes(&dirty_pages(candidates, &*self.layer, &*newer.layer), &*self.layer, &*newer.layer)
+    }
+}
+
+/// The pages written between one snapshot of a space and the snapshot before it
+#[derive(Debug)]
+struct Layer {
+    parent: Option<Rc<Layer>>,
+    /// the contents of each page written since the parent, `None` for pages that were cleared
+    pages: HashMap<u64, Option<Page>>,
+    /// the number of layers below this one
+    depth: usize,
+}
+
+impl Layer {
+    /// The pages that might differ between `older` and this layer, or `None` if `older` isn't
+    /// below it
+    fn touched_since(&self, older: &Layer) -> Option<HashSet<u64>> {
+        let mut pages = HashSet::new();
+        let mut layer = Some(self);
+        while let Some(current) = layer {
+            if std::ptr::eq(current, older) {
+                return Some(pages);
+            }
+            pages.extend(current.pages.keys());
+            layer = current.parent.as_deref();
+        }
+        None
+    }
+
+    /// Every page as of this layer
+    fn materialize(&self) -> HashMap<u64, Page> {
+        let mut seen = HashSet::new();
+        let mut pages = HashMap::new();
+        let mut layer = Some(self);
+        // newer layers come first, so their pages win
+        while let Some(current) = layer {
+            for (page, data) in &current.pages {
+                if let (true, Some(data)) = (seen.insert(*page), data) {
+                    pages.insert(*page, data.clone());
+                }
+            }
+            layer = current.parent.as_deref();
+        }
+        pages
+    }
+}
+
+impl Pages for Layer {
+    fn page(&self, page: u64) -> Option<Page> {
+        let mut layer = Some(self);
+        while let Some(current) = layer {
+            if let Some(data) = current.pages.get(&page) {
+                return data.clone();
+            }
+            layer = current.parent.as_deref();
+        }
+        None
+    }
+}
+
+/// Somewhere pages can be looked up: a space, or one of its snapshots
+trait Pages {
+    /// The contents of page number `page`, or `None` if it was never written
+    fn page(&self, page: u64) -> Option<Page>;
+}
+
+/// A run of bytes that changed between two snapshots
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Change {
+    pub address: u64,
+    pub old: Vec<u8>,
+    pub new: Vec<u8>,
+}
+
+/// The pages out of `candidates` whose contents differ between `old` and `new`, in order. Pages
+/// that are shared haven't been written to since, so they don't need comparing.
+fn dirty_pages(candidates: HashSet<u64>, old: &impl Pages, new: &impl Pages) -> Vec<u64> {
+    let mut pages = candidates.into_iter()
+        .filter(|page| match (old.page(*page), new.page(*page)) {
+            (Some(old), Some(new)) if Rc::ptr_eq(&old, &new) => false,
+            (old, new) => contents(old.as_ref()) != contents(new.as_ref()),
+        })
+        .collect::<Vec<_>>();
+    pages.sort_unstable();
+    pages
+}
+
+/// The ranges of bytes in `pages` that differ between `old` and `new`, in order
+fn changed_ranges(pages: &[u64], old: &impl Pages, new: &impl Pages) -> Vec<Range<u64>> {
+    let mut ranges: Vec<Range<u64>> = Vec::new();
+    for &page in pages {
+        let base = page << PAGE_BITS;
+        let (old, new) = (old.page(page), new.page(page));
+        let (old, new) = (contents(old.as_ref()), contents(new.as_ref()));
+        for (i, _) in old.iter().zip(new).enumerate().filter(|(_, (old, new))| old != new) {
+            let address = base + i as u64;
+            // extend the last range if this byte follows it, even across pages
+            match ranges.last_mut() {
+                Some(last) if last.end == address => last.end += 1,
+                _ => ranges.push(address..address + 1),
+            }
+        }
+    }
+    ranges
+}
+
+/// The bytes in `pages` that differ between `old` and `new`
+fn changes(pages: &[u64], old: &impl Pages, new: &impl Pages) -> Vec<Change> {
+    changed_ranges(pages, old, new).into_iter()
+        .map(|range| Change {
+            address: range.start,
+            old: read_pages(old, range.clone()),
+            new: read_pages(new, range),
+        })
+        .collect()
+}
+
+/// The contents of a page, which is all zeros if it was never allocated
+fn contents(page: Option<&Page>) -> &[u8; PAGE_SIZE] {
+    const ZERO: &[u8; PAGE_SIZE] = &[0; PAGE_SIZE];
+    page.map_or(ZERO, |data| data)
+}
+
+fn read_pages(pages: &impl Pages, range: Range<u64>) -> Vec<u8> {
+    let mut bytes = vec![0; (range.end - range.start) as usize];
+    for_each_chunk(range.start, bytes.len(), u64::MAX, |page, range, chunk| {
+        if let Some(page) = pages.page(page) {
+            bytes[chunk].copy_from_slice(&page[range]);
+        }
+    });
+    bytes
+}
+
+/// `addr + delta` in a space whose offsets wrap past `highest`
+#[inline]
+pub(crate) fn wrap(addr: u64, delta: u64, highest: u64) -> u64 {
+    if highest == u64::MAX {
+        addr.wrapping_add(delta)
+    } else {
+        ((u128::from(addr) + u128::from(delta)) % (u128::from(highest) + 1)) as u64
+    }
+}
+
+/// Splits `len` bytes starting at `addr` into the pieces that fall in each page, calling `f` with
+/// the page number, the range within that page and the range within the `len` bytes. Addresses
+/// wrap around past `highest`.
+#[inline]
+fn for_each_chunk(addr: u64, len: usize, highest: u64, mut f: impl FnMut(u64, Range<usize>, Range<usize>)) {
+    let mut done = 0;
+    while done < len {
+        let addr = wrap(addr, done as u64, highest);
+        let start = (addr % PAGE_SIZE as u64) as usize;
+        let room = usize::try_from((highest - addr).saturating_add(1)).unwrap_or(usize::MAX);
+        let chunk = (PAGE_SIZE - start).min(len - done).min(room);
+        f(addr >> PAGE_BITS, start..start + chunk, done..done + chunk);
+        done += chunk;
+    }
+}
+
+pub trait Read: Sized {
+    /// Decodes a value from bytes, or `None` if it doesn't fit in `Self`
+    fn read(is_big_endian: bool, src: &[u8]) -> Option<Self>;
 }
 
 pub trait Write {
@@ -63,11 +813,11 @@ pub trait Write {
 }
 
 impl Read for BigUint {
-    fn read(is_big_endian: bool, out: &[u8]) -> Self {
+    fn read(is_big_endian: bool, out: &[u8]) -> Option<Self> {
         if is_big_endian {
-            BigUint::from_bytes_be(out)
+            Some(BigUint::from_bytes_be(out))
         } else {
-            BigUint::from_bytes_le(out)
+            Some(BigUint::from_bytes_le(out))
         }
     }
 }
@@ -106,11 +856,11 @@ impl Write for &'_ BigUint {
 }
 
 impl Read for BigInt {
-    fn read(is_big_endian: bool, src: &[u8]) -> Self {
+    fn read(is_big_endian: bool, src: &[u8]) -> Option<Self> {
         if is_big_endian {
-            BigInt::from_signed_bytes_be(src)
+            Some(BigInt::from_signed_bytes_be(src))
         } else {
-            BigInt::from_signed_bytes_le(src)
+            Some(BigInt::from_signed_bytes_le(src))
         }
     }
 }
@@ -124,39 +874,12 @@ impl Write for BigInt {
 
 impl Write for &'_ BigInt {
     fn write(self, is_big_endian: bool, dest: &mut [u8]) {
-        if is_big_endian {
-            let (sign, bytes) = self.to_bytes_be();
-            if self.bits() as usize >= dest.len() * 8 {
-                let split = bytes.len() - dest.len();
-                dest.copy_from_slice(&bytes[split..]);
-            } else {
-                let split = dest.len() - bytes.len();
-                dest[split..].copy_from_slice(&bytes);
-                dest[..split].fill(0);
-                if matches!(sign, Sign::Minus) {
-                    for byte in dest.iter_mut() {
-                        *byte = !*byte;
-                    }
-                    let last = dest.len() - 1;
-                    dest[last] = dest[last].overflowing_add(1).0;
-                }
-            }
-        } else {
-            let (sign, bytes) = self.to_bytes_le();
-            if bytes.len() > dest.len() {
-                dest.copy_from_slice(&bytes[..dest.len()]);
-            } else {
-                let split = bytes.len();
-                dest[..split].copy_from_slice(&bytes);
-                dest[split..].fill(0);
-                if matches!(sign, Sign::Minus) {
-                    for byte in dest.iter_mut() {
-                        *byte = !*byte;
-                    }
-                    dest[0] = dest[0].overflowing_add(1).0;
-                }
-            }
-        }
+        // wrap into the unsigned range of the destination, i.e. two's complement
+        let modulus = BigInt::one() << (dest.len() * 8);
+        let value = self.mod_floor(&modulus)
+            .to_biguint()
+            .expect("mod_floor with a positive modulus is never negative");
+        value.write(is_big_endian, dest)
     }
 }
 
@@ -168,9 +891,8 @@ impl Write for bool {
 }
 
 impl Read for bool {
-    fn read(is_big_endian: bool, src: &[u8]) -> Self {
-        let value: BigUint = Read::read(is_big_endian, src);
-        value != BigUint::zero()
+    fn read(_is_big_endian: bool, src: &[u8]) -> Option<Self> {
+        Some(src.iter().any(|byte| *byte != 0))
     }
 }
 
@@ -178,10 +900,10 @@ macro_rules! primitive {
     ($($n:ty),+; $($n2:ty),+) => {
         $(
         impl Read for $n {
-            fn read(is_big_endian: bool, src: &[u8]) -> Self {
-                BigUint::read(is_big_endian, src)
+            fn read(is_big_endian: bool, src: &[u8]) -> Option<Self> {
+                BigUint::read(is_big_endian, src)?
                 .try_into()
-                .expect("unable to convert to primitive")
+                .ok()
             }
         }
 
@@ -193,10 +915,10 @@ macro_rules! primitive {
         )+
         $(
         impl Read for $n2 {
-            fn read(is_big_endian: bool, src: &[u8]) -> Self {
-                BigInt::read(is_big_endian, src)
+            fn read(is_big_endian: bool, src: &[u8]) -> Option<Self> {
+                BigInt::read(is_big_endian, src)?
                 .try_into()
-                .expect("unable to convert to primitive")
+                .ok()
             }
         }
 
@@ -209,4 +931,252 @@ macro_rules! primitive {
     };
 }
 
-primitive!(u8, u16, u32, u64, u128; i8, i16, i32, i64, i128);
\ No newline at end of file
+primitive!(u8, u16, u32, u64, u128; i8, i16, i32, i64, i128);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_write_negative_wraps() {
+        let mut dest = [0u8; 2];
+        BigInt::from(-256).write(false, &mut dest);
+        assert_eq!(dest, [0x00, 0xFF]);
+
+        BigInt::from(-256).write(true, &mut dest);
+        assert_eq!(dest, [0xFF, 0x00]);
+
+        let mut dest = [0u8; 1];
+        BigInt::from(-129).write(false, &mut dest);
+        assert_eq!(dest, [0x7F]);
+
+        let mut dest = [0u8; 4];
+        BigInt::from(1u64 << 31).write(false, &mut dest);
+        assert_eq!(i32::from_le_bytes(dest), i32::MIN);
+    }
+
+    #[test]
+    fn test_unwritten_reads_zero() {
+        let space = Space::new(false);
+        assert_eq!(space.get_bytes(0x1234, 4), [0; 4]);
+        assert_eq!(space.page_count(), 0);
+    }
+
+    #[test]
+    fn test_read_write_across_pages() {
+        let space = Space::new(false);
+        let bytes = (0..=255).collect::<Vec<u8>>();
+        let addr = PAGE_SIZE as u64 * 3 - 100;
+        space.write_from(addr, &bytes);
+        assert_eq!(space.page_count(), 2);
+        assert_eq!(space.get_bytes(addr, 256), bytes);
+
+        // partially written reads are padded with zeros
+        let mut buffer = [0xAA; 8];
+        space.read_into(addr - 4, &mut buffer);
+        assert_eq!(buffer, [0, 0, 0, 0, 0, 1, 2, 3]);
+
+        // two reads can be held at once
+        let first = space.get_bytes(addr, 2);
+        let second = space.get_bytes(addr + 2, 2);
+        assert_eq!((first.as_slice(), second.as_slice()), (&[0, 1][..], &[2, 3][..]));
+    }
+
+    #[test]
+    fn test_snapshot_copies_on_write() {
+        let mut space = Space::new(false);
+        space.write_from(0x10, &[1, 2, 3, 4]);
+        space.write_from(PAGE_SIZE as u64, &[5]);
+        let snapshot = space.snapshot();
+
+        space.write_from(0x10, &[0xFF]);
+        space.write_from(PAGE_SIZE as u64 * 8, &[6]);
+        space.map(0, 0x100, Permissions::READ);
+        // only the page that was written to is copied
+        let shared = [0, 1].into_iter()
+            .filter(|page| Rc::ptr_eq(&snapshot.layer.page(*page).unwrap(), &space.page(*page).unwrap()))
+            .count();
+        assert_eq!(shared, 1);
+
+        space.restore(&snapshot);
+        assert_eq!(space.get_bytes(0x10, 4), [1, 2, 3, 4]);
+        assert_eq!(space.get_bytes(PAGE_SIZE as u64 * 8, 1), [0]);
+        assert_eq!(space.page_count(), 2);
+        assert_eq!(space.permissions(0), None);
+    }
+
+    #[test]
+    fn test_dirty_tracking() {
+        let space = Space::new(false);
+        space.write_from(0x10, &[1, 2, 3, 4]);
+        let checkpoint = space.snapshot();
+        assert!(space.dirty_ranges(&checkpoint).is_empty());
+
+        // rewriting the same values isn't a change
+        space.write_from(0x10, &[1, 2, 0xFF, 4]);
+        space.write_from(PAGE_SIZE as u64 - 2, &[7, 7, 7, 7]);
+        space.write_from(PAGE_SIZE as u64 * 4, &[0]);
+        assert_eq!(space.dirty_pages(&checkpoint), [0, 1]);
+        assert_eq!(
+            space.dirty_ranges(&checkpoint),
+            [0x12..0x13, PAGE_SIZE as u64 - 2..PAGE_SIZE as u64 + 2],
+        );
+        assert_eq!(space.diff(&checkpoint), [
+            Change { address: 0x12, old: vec![3], new: vec![0xFF] },
+            Change { address: PAGE_SIZE as u64 - 2, old: vec![0; 4], new: vec![7; 4] },
+        ]);
+
+        let later = space.snapshot();
+        space.write_from(0x13, &[0]);
+        assert_eq!(checkpoint.diff(&later).len(), 2);
+        assert_eq!(later.diff(&space.snapshot()), [Change { address: 0x13, old: vec![4], new: vec![0] }]);
+    }
+
+    #[test]
+    fn test_snapshots_hold_what_changed() {
+        let mut space = Space::new(false);
+        for page in 0..100 {
+            space.write_from(page * PAGE_SIZE as u64, &[1]);
+        }
+        let first = space.snapshot();
+        space.write_from(0x10, &[2]);
+        let second = space.snapshot();
+        assert_eq!(second.layer.pages.len(), 1);
+        assert_eq!(space.touched_since(&first.layer).unwrap().len(), 1);
+        assert_eq!(first.diff(&second), [Change { address: 0x10, old: vec![0], new: vec![2] }]);
+
+        // going back only puts back the pages that changed, and a later snapshot can be
+        // restored after that too
+        space.restore(&first);
+        assert_eq!(space.get_bytes(0x10, 1), [0]);
+        space.write_from(PAGE_SIZE as u64 * 200, &[3]);
+        let sibling = space.snapshot();
+        space.restore(&second);
+        assert_eq!(space.get_bytes(0x10, 1), [2]);
+        assert_eq!(space.get_bytes(PAGE_SIZE as u64 * 200, 1), [0]);
+        assert_eq!(space.page_count(), 100);
+        assert_eq!(second.diff(&sibling), [
+            Change { address: 0x10, old: vec![2], new: vec![0] },
+            Change { address: PAGE_SIZE as u64 * 200, old: vec![0], new: vec![3] },
+        ]);
+
+        // a flat space only copies out the pages written since the last snapshot
+        let registers = AddrSpace {
+            name: "register".to_string(),
+            index: 2,
+            type_: sleigh::SpaceType::Internal,
+            wordsize: 1,
+            addrsize: 4,
+            highest: 0xFFFF_FFFF,
+            is_big_endian: false,
+        };
+        let mut space = Space::flat(&registers, PAGE_SIZE * 16);
+        space.write_from(0, &[1]);
+        let before = space.snapshot();
+        space.write_from(PAGE_SIZE as u64 * 3, &[4, 4]);
+        space.write_from(PAGE_SIZE as u64 * 5, &[5]);
+        assert_eq!(space.snapshot().layer.pages.len(), 3);
+        space.clear();
+        space.write_from(PAGE_SIZE as u64 * 5, &[5]);
+        assert_eq!(space.diff(&before), [
+            Change { address: 0, old: vec![1], new: vec![0] },
+            Change { address: PAGE_SIZE as u64 * 5, old: vec![0], new: vec![5] },
+        ]);
+        space.restore(&before);
+        assert_eq!(space.get_bytes(0, 1), [1]);
+        assert_eq!(space.get_bytes(PAGE_SIZE as u64 * 5, 1), [0]);
+    }
+
+    #[test]
+    fn test_permissions() {
+        let mut space = Space::new(false);
+        space.map(0x1000, 0x2000, Permissions::READ_EXECUTE);
+        space.map(0x4000, 0x1000, Permissions::READ_WRITE);
+        assert_eq!(space.check(0x1000, 0x2000, Access::Execute), Ok(()));
+        assert_eq!(space.check(0x1FFE, 4, Access::Write), Err(Fault::Denied(0x1FFE)));
+        // unmapped memory is allowed until the policy says otherwise
+        assert_eq!(space.check(0x2FFC, 0x1008, Access::Read), Ok(()));
+        space.set_unmapped_policy(UnmappedPolicy::Fault);
+        assert_eq!(space.check(0x2FFC, 0x1008, Access::Read), Err(Fault::Unmapped(0x3000)));
+        assert_eq!(space.check(0, 1, Access::Read), Err(Fault::Unmapped(0)));
+        assert_eq!(space.check(0x4000, 0x1000, Access::Write), Ok(()));
+
+        // remapping the middle of a region splits it
+        space.map(0x1800, 0x100, Permissions::READ_WRITE);
+        assert_eq!(space.permissions(0x17FF), Some(Permissions::READ_EXECUTE));
+        assert_eq!(space.permissions(0x1800), Some(Permissions::READ_WRITE));
+        assert_eq!(space.permissions(0x1900), Some(Permissions::READ_EXECUTE));
+        assert_eq!(space.permissions(0x3000), None);
+    }
+
+    #[test]
+    fn test_from_addr_space() {
+        let ram = AddrSpace {
+            name: "ram".to_string(),
+            index: 1,
+            type_: sleigh::SpaceType::Processor,
+            wordsize: 1,
+            addrsize: 2,
+            highest: 0xFFFF,
+            is_big_endian: true,
+        };
+        let space = Space::from(&ram);
+        assert!(space.is_big_endian());
+        space.write_value(0xFFFE, 4, 0x1122_3344u32);
+        // the write wraps at the highest offset of the space, not of a u64
+        assert_eq!(space.get_bytes(0, 2), [0x33, 0x44]);
+        assert_eq!(space.get_bytes(0x1_0000, 2), [0x33, 0x44]);
+        assert_eq!(space.read_value::<u32>(0xFFFE, 4), Some(0x1122_3344));
+
+        let mut space = space;
+        space.set_unmapped_policy(UnmappedPolicy::Fault);
+        space.map(0xFFF0, 0x10, Permissions::READ);
+        assert_eq!(space.check(0xFFFE, 4, Access::Read), Err(Fault::Unmapped(0)));
+    }
+
+    #[test]
+    fn test_wraps_at_end_of_space() {
+        let space = Space::new(false);
+        space.write_from(u64::MAX - 1, &[1, 2, 3, 4]);
+        assert_eq!(space.get_bytes(0, 2), [3, 4]);
+        assert_eq!(space.get_bytes(u64::MAX - 1, 4), [1, 2, 3, 4]);
+    }
+
+    #[test]
+    fn test_flat_storage() {
+        let register = AddrSpace {
+            name: "register".to_string(),
+            index: 2,
+            type_: sleigh::SpaceType::Processor,
+            wordsize: 1,
+            addrsize: 4,
+            highest: 0xFFFF_FFFF,
+            is_big_endian: false,
+        };
+        let mut space = Space::flat(&register, 0x10);
+        assert!(space.is_flat());
+        space.write_value(0x8, 4, 0x1122_3344u32);
+        assert_eq!(space.read_value::<u32>(0x8, 4), Some(0x1122_3344));
+        // reads straddling the end of the buffer are padded with zeros, and writes grow it
+        assert_eq!(space.get_bytes(0xE, 4), [0; 4]);
+        space.write_from(0x20, &[1, 2]);
+        assert_eq!(space.get_bytes(0x1F, 4), [0, 1, 2, 0]);
+
+        let checkpoint = space.snapshot();
+        space.write_from(0x9, &[0xFF]);
+        assert_eq!(space.diff(&checkpoint), [Change { address: 0x9, old: vec![0x33], new: vec![0xFF] }]);
+        space.restore(&checkpoint);
+        assert_eq!(space.read_value::<u32>(0x8, 4), Some(0x1122_3344));
+
+        space.clear();
+        assert_eq!(space.get_bytes(0x8, 4), [0; 4]);
+        assert_eq!(space.get_bytes(0x20, 2), [0; 2]);
+
+        // writes far past the end move the contents into pages
+        space.write_from(0x4, &[5]);
+        space.write_from(0x8000_0000, &[6]);
+        assert!(!space.is_flat());
+        assert_eq!(space.get_bytes(0x4, 1), [5]);
+        assert_eq!(space.get_bytes(0x8000_0000, 1), [6]);
+    }
+}
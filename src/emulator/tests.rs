@@ -0,0 +1,911 @@
+//!
+//! Tests of the emulator: conformance tests for the pcode ops implemented by
+//! `Emulator::emulate_one`, and of what the emulator does around them.
+//!
+//! Every integer case runs through both the native implementation and the `BigUint` one that
+//! wide varnodes fall back to, and is checked against Rust's own fixed-width integer arithmetic.
+
+use anyhow::Context;
+use num::BigInt;
+use sleigh::{Instruction, Opcode, PCode, SpaceType, VarnodeData};
+use crate::emulator::{Access, Emulator, ErrorKind, FloatFormat, PCodeControl, Permissions, UnmappedPolicy};
+use crate::emulator::fixture::{constant, emulator, empty_binary, machine, space, unique};
+
+const SIZES: [u32; 4] = [1, 2, 4, 8];
+
+/// Edge values for a size, masked to it
+fn edge_values(size: u32) -> Vec<u64> {
+    let bits = size * 8;
+    let max = u64::MAX >> (64 - bits);
+    let sign = 1u64 << (bits - 1);
+    let mut values = vec![0, 1, 2, 3, 7, sign - 1, sign, sign + 1, max - 1, max, 0x5A5A_5A5A_5A5A_5A5A & max];
+    values.dedup();
+    values
+}
+
+/// Varnodes in the unique space for the given inputs
+fn variables(inputs: &[(u64, u32)]) -> Vec<VarnodeData> {
+    inputs.iter()
+        .enumerate()
+        .map(|(i, (_, size))| unique(0x100 * i as u64, *size))
+        .collect()
+}
+
+/// Runs a single op on `vars` with both the native and the `BigUint` implementation, returning
+/// the output (or error) of each. Inputs that aren't constants are set to their values first.
+fn run(emulator: &Emulator, opcode: Opcode, vars: &[VarnodeData], inputs: &[(u64, u32)], output_size: u32) -> [Result<u128, ErrorKind>; 2] {
+    let output = unique(0x1000, output_size);
+    let pcode = PCode { address: 0, opcode, vars: vars.to_vec(), outvar: Some(output.clone()) };
+
+    [Emulator::emulate_native, Emulator::emulate_wide].map(|emulate| {
+        for (node, (value, _)) in vars.iter().zip(inputs) {
+            if node.space.type_ != SpaceType::Constant {
+                emulator.write_native(node, u128::from(*value)).unwrap();
+            }
+        }
+        emulator.write_native(&output, 0).unwrap();
+        emulate(emulator, &pcode)?;
+        Ok(emulator.read_native(&output).unwrap())
+    })
+}
+
+/// Checks the results of both implementations against the expected value
+fn check(emulator: &Emulator, opcode: Opcode, inputs: &[(u64, u32)], output_size: u32, expected: Option<u128>) {
+    check_vars(emulator, opcode, &variables(inputs), inputs, output_size, expected)
+}
+
+/// Like `check`, with the given input varnodes
+fn check_vars(emulator: &Emulator, opcode: Opcode, vars: &[VarnodeData], inputs: &[(u64, u32)], output_size: u32, expected: Option<u128>) {
+    let [native, big] = run(emulator, opcode, vars, inputs, output_size);
+    for (name, result) in [("native", native), ("BigUint", big)] {
+        match (result, expected) {
+            (Ok(result), Some(expected)) => assert_eq!(
+                result, expected,
+                "{} {:?} {:X?} -> {:X}, expected {:X}", name, opcode, inputs, result, expected,
+            ),
+            (Err(error), None) => assert!(
+                matches!(error, ErrorKind::DivisionByZero),
+                "{} {:?} {:X?} failed with {}", name, opcode, inputs, error,
+            ),
+            (result, expected) => panic!("{} {:?} {:X?} -> {:?}, expected {:?}", name, opcode, inputs, result.ok(), expected),
+        }
+    }
+}
+
+/// The expected result of a binary op on two values of the same size, or `None` if it faults,
+/// computed with the fixed-width integer types of that size.
+macro_rules! binary_reference {
+    ($opcode:expr, $left:expr, $right:expr, $u:ty, $s:ty) => {{
+        let (left, right) = ($left as $u, $right as $u);
+        let (signed_left, signed_right) = (left as $s, right as $s);
+        let bits = <$u>::BITS as u64;
+        let result: Option<$u> = match $opcode {
+            Opcode::IntAdd => Some(left.wrapping_add(right)),
+            Opcode::IntSub => Some(left.wrapping_sub(right)),
+            Opcode::IntMult => Some(left.wrapping_mul(right)),
+            Opcode::IntDiv => left.checked_div(right),
+            Opcode::IntRem => left.checked_rem(right),
+            Opcode::IntSDiv => (right != 0).then(|| signed_left.wrapping_div(signed_right) as $u),
+            Opcode::IntSRem => (right != 0).then(|| signed_left.wrapping_rem(signed_right) as $u),
+            Opcode::IntAnd => Some(left & right),
+            Opcode::IntOr => Some(left | right),
+            Opcode::IntXor => Some(left ^ right),
+            Opcode::IntLeft => Some(if $right >= bits { 0 } else { left << $right }),
+            Opcode::IntRight => Some(if $right >= bits { 0 } else { left >> $right }),
+            Opcode::IntSRight => Some(if $right >= bits { (signed_left >> (bits - 1)) as $u } else { (signed_left >> $right) as $u }),
+            Opcode::IntEqual => Some((left == right) as $u),
+            Opcode::IntNotEqual => Some((left != right) as $u),
+            Opcode::IntLess => Some((left < right) as $u),
+            Opcode::IntLessEqual => Some((left <= right) as $u),
+            Opcode::IntSLess => Some((signed_left < signed_right) as $u),
+            Opcode::IntSLessEqual => Some((signed_left <= signed_right) as $u),
+            Opcode::IntCarry => Some(left.overflowing_add(right).1 as $u),
+            Opcode::IntSCarry => Some(signed_left.overflowing_add(signed_right).1 as $u),
+            Opcode::IntSBorrow => Some(signed_left.overflowing_sub(signed_right).1 as $u),
+            opcode => unreachable!("no reference for {:?}", opcode),
+        };
+        result.map(u128::from)
+    }};
+}
+
+fn binary_expected(opcode: Opcode, size: u32, left: u64, right: u64) -> Option<u128> {
+    match size {
+        1 => binary_reference!(opcode, left, right, u8, i8),
+        2 => binary_reference!(opcode, left, right, u16, i16),
+        4 => binary_reference!(opcode, left, right, u32, i32),
+        8 => binary_reference!(opcode, left, right, u64, i64),
+        _ => unreachable!(),
+    }
+}
+
+fn is_comparison(opcode: Opcode) -> bool {
+    matches!(
+        opcode,
+        Opcode::IntEqual | Opcode::IntNotEqual | Opcode::IntLess | Opcode::IntLessEqual
+            | Opcode::IntSLess | Opcode::IntSLessEqual | Opcode::IntCarry | Opcode::IntSCarry
+            | Opcode::IntSBorrow
+    )
+}
+
+fn check_binary(opcodes: &[Opcode]) {
+    emulator!(emulator);
+    for &opcode in opcodes {
+        for size in SIZES {
+            let output_size = if is_comparison(opcode) { 1 } else { size };
+            for left in edge_values(size) {
+                for right in edge_values(size) {
+                    let expected = binary_expected(opcode, size, left, right);
+                    check(&emulator, opcode, &[(left, size), (right, size)], output_size, expected);
+                }
+            }
+        }
+    }
+}
+
+#[test]
+fn test_arithmetic() {
+    check_binary(&[Opcode::IntAdd, Opcode::IntSub, Opcode::IntMult]);
+}
+
+#[test]
+fn test_division() {
+    check_binary(&[Opcode::IntDiv, Opcode::IntRem, Opcode::IntSDiv, Opcode::IntSRem]);
+}
+
+#[test]
+fn test_division_edge_cases() {
+    emulator!(emulator);
+    for size in SIZES {
+        let bits = size * 8;
+        let max = u64::MAX >> (64 - bits);
+        let min = 1u64 << (bits - 1);
+        let negative = |value: i64| value as u64 & max;
+        let cases = [
+            // anything divided by zero faults, signed or not
+            (Opcode::IntDiv, 7, 0, None),
+            (Opcode::IntRem, 7, 0, None),
+            (Opcode::IntSDiv, min, 0, None),
+            (Opcode::IntSRem, 0, 0, None),
+            // MIN / -1 overflows and wraps back to MIN, leaving no remainder
+            (Opcode::IntSDiv, min, max, Some(min)),
+            (Opcode::IntSRem, min, max, Some(0)),
+            // signed division truncates toward zero and the remainder takes the sign of the dividend
+            (Opcode::IntSDiv, negative(-7), 2, Some(negative(-3))),
+            (Opcode::IntSRem, negative(-7), 2, Some(negative(-1))),
+            (Opcode::IntSDiv, 7, negative(-2), Some(negative(-3))),
+            (Opcode::IntSRem, 7, negative(-2), Some(1)),
+            // the same bits divided unsigned
+            (Opcode::IntDiv, max, 2, Some(max >> 1)),
+            (Opcode::IntRem, max, 2, Some(1)),
+            (Opcode::IntDiv, min, max, Some(0)),
+            // products keep only their low bits
+            (Opcode::IntMult, min, 2, Some(0)),
+            (Opcode::IntMult, max, max, Some(1)),
+            (Opcode::IntMult, 3, negative(-5), Some(negative(-15))),
+        ];
+        for (opcode, left, right, expected) in cases {
+            check(&emulator, opcode, &[(left, size), (right, size)], size, expected.map(u128::from));
+        }
+    }
+}
+
+#[test]
+fn test_bitwise() {
+    check_binary(&[Opcode::IntAnd, Opcode::IntOr, Opcode::IntXor]);
+}
+
+#[test]
+fn test_shifts() {
+    check_binary(&[Opcode::IntLeft, Opcode::IntRight, Opcode::IntSRight]);
+
+    // the shift amount is often a different size from the value
+    emulator!(emulator);
+    for size in SIZES {
+        for value in edge_values(size) {
+            for amount in [0, 1, 7, 31, 32, 63, 64, 200, 255] {
+                for opcode in [Opcode::IntLeft, Opcode::IntRight, Opcode::IntSRight] {
+                    let expected = binary_expected(opcode, size, value, amount);
+                    check(&emulator, opcode, &[(value, size), (amount, 1)], size, expected);
+                }
+            }
+        }
+    }
+}
+
+#[test]
+fn test_comparisons() {
+    check_binary(&[
+        Opcode::IntEqual, Opcode::IntNotEqual, Opcode::IntLess, Opcode::IntLessEqual,
+        Opcode::IntSLess, Opcode::IntSLessEqual,
+    ]);
+}
+
+#[test]
+fn test_carry() {
+    check_binary(&[Opcode::IntCarry, Opcode::IntSCarry, Opcode::IntSBorrow]);
+}
+
+#[test]
+fn test_unary() {
+    emulator!(emulator);
+    for size in SIZES {
+        let mask = u128::MAX >> (128 - size * 8);
+        for value in edge_values(size) {
+            let signed = (i128::from(value as i64) << (128 - size * 8)) >> (128 - size * 8);
+            let value128 = u128::from(value);
+            check(&emulator, Opcode::Copy, &[(value, size)], size, Some(value128));
+            check(&emulator, Opcode::Int2Comp, &[(value, size)], size, Some(value128.wrapping_neg() & mask));
+            check(&emulator, Opcode::IntNegate, &[(value, size)], size, Some(!value128 & mask));
+            check(&emulator, Opcode::PopCount, &[(value, size)], 1, Some(u128::from(value.count_ones())));
+            check(&emulator, Opcode::LzCount, &[(value, size)], 1, Some(u128::from(value.leading_zeros() - (64 - size * 8))));
+            check(&emulator, Opcode::IntZExt, &[(value, size)], size * 2, Some(value128));
+            let wide_mask = u128::MAX >> (128 - size * 16);
+            check(&emulator, Opcode::IntSExt, &[(value, size)], size * 2, Some(signed as u128 & wide_mask));
+        }
+    }
+}
+
+#[test]
+fn test_pieces() {
+    emulator!(emulator);
+    for size in SIZES {
+        for high in edge_values(size) {
+            for low in edge_values(size) {
+                let expected = (u128::from(high) << (size * 8)) | u128::from(low);
+                check(&emulator, Opcode::Piece, &[(high, size), (low, size)], size * 2, Some(expected));
+            }
+        }
+    }
+
+    // SubPiece throws away input1 bytes, then truncates to the output size
+    let value = 0x8877_6655_4433_2211u64;
+    let cases = [(0, 1, 0x11), (1, 1, 0x22), (2, 2, 0x4433), (4, 4, 0x8877_6655), (0, 8, value), (7, 1, 0x88), (6, 4, 0x8877)];
+    for (offset, size, expected) in cases {
+        let [native, big] = {
+            let input0 = unique(0, 8);
+            let output = unique(0x1000, size);
+            let pcode = PCode {
+                address: 0,
+                opcode: Opcode::SubPiece,
+                vars: vec![input0.clone(), constant(offset, 4)],
+                outvar: Some(output.clone()),
+            };
+            [Emulator::emulate_native, Emulator::emulate_wide].map(|emulate| {
+                emulator.write_native(&input0, u128::from(value)).unwrap();
+                emulate(&emulator, &pcode).unwrap();
+                emulator.read_native(&output).unwrap()
+            })
+        };
+        assert_eq!(native, u128::from(expected), "native SubPiece {} {}", offset, size);
+        assert_eq!(big, u128::from(expected), "BigUint SubPiece {} {}", offset, size);
+    }
+}
+
+#[test]
+fn test_booleans() {
+    emulator!(emulator);
+    for left in [false, true] {
+        check(&emulator, Opcode::BoolNegate, &[(left as u64, 1)], 1, Some(u128::from(!left)));
+        for right in [false, true] {
+            let inputs = [(left as u64, 1), (right as u64, 1)];
+            check(&emulator, Opcode::BoolAnd, &inputs, 1, Some(u128::from(left & right)));
+            check(&emulator, Opcode::BoolOr, &inputs, 1, Some(u128::from(left | right)));
+            check(&emulator, Opcode::BoolXor, &inputs, 1, Some(u128::from(left ^ right)));
+        }
+    }
+}
+
+#[test]
+fn test_constant_operands() {
+    emulator!(emulator);
+
+    // constants are truncated to their size and read like any other varnode of that size
+    assert_eq!(emulator.read::<i32>(&constant(0xFFFF_FFFF, 4)).unwrap(), -1);
+    assert_eq!(emulator.read::<BigInt>(&constant(0xFFFF_FFFF, 4)).unwrap(), BigInt::from(-1));
+    assert_eq!(emulator.read::<i8>(&constant(u64::MAX, 1)).unwrap(), -1);
+    assert_eq!(emulator.read::<u16>(&constant(0x1234_5678, 2)).unwrap(), 0x5678);
+    assert_eq!(emulator.read::<u128>(&constant(u64::MAX, 16)).unwrap(), u128::from(u64::MAX));
+
+    let opcodes = [
+        Opcode::IntAdd, Opcode::IntSub, Opcode::IntMult, Opcode::IntSDiv, Opcode::IntSRem, Opcode::IntLess,
+        Opcode::IntSLess, Opcode::IntSLessEqual, Opcode::IntEqual, Opcode::IntSCarry, Opcode::IntSBorrow,
+    ];
+    for opcode in opcodes {
+        for size in SIZES {
+            let output_size = if is_comparison(opcode) { 1 } else { size };
+            for left in edge_values(size) {
+                for right in edge_values(size) {
+                    let vars = [unique(0, size), constant(right, size)];
+                    let expected = binary_expected(opcode, size, left, right);
+                    check_vars(&emulator, opcode, &vars, &[(left, size), (right, size)], output_size, expected);
+                }
+            }
+        }
+    }
+
+    // shift amounts are usually constants of a different size
+    for size in SIZES {
+        for value in edge_values(size) {
+            for amount in [0, 1, 7, 31, 63, 200] {
+                let vars = [unique(0, size), constant(amount, 4)];
+                let expected = binary_expected(Opcode::IntSRight, size, value, amount);
+                check_vars(&emulator, Opcode::IntSRight, &vars, &[(value, size), (amount, 4)], size, expected);
+            }
+        }
+    }
+}
+
+/// Emulates `opcode` on `vars`, returning the value of a varnode of `output_size` bytes
+fn emulate(emulator: &Emulator, opcode: Opcode, vars: &[VarnodeData], output_size: u32) -> u128 {
+    let output = unique(0x1000, output_size);
+    let pcode = PCode { address: 0, opcode, vars: vars.to_vec(), outvar: Some(output.clone()) };
+    emulator.emulate_one(&pcode).unwrap();
+    emulator.read_native(&output).unwrap()
+}
+
+/// Checks the float ops against Rust's own arithmetic on `$f`, the float type of `$size` bytes
+macro_rules! check_floats {
+    ($emulator:expr, $f:ty, $size:expr) => {{
+        let emulator = $emulator;
+        let values: [$f; 12] = [
+            0.0, -0.0, 1.0, -1.5, 0.1, 2.5, -2.5, <$f>::MAX, <$f>::MIN_POSITIVE / 4.0,
+            <$f>::INFINITY, <$f>::NEG_INFINITY, <$f>::NAN,
+        ];
+        let (left, right) = (unique(0, $size), unique(0x10, $size));
+        let same = |result: u128, expected: $f| {
+            let result = <$f>::from_bits(result as _);
+            (result.is_nan() && expected.is_nan()) || result.to_bits() == expected.to_bits()
+        };
+
+        for a in values {
+            emulator.write_native(&left, u128::from(a.to_bits())).unwrap();
+            let unary: [(Opcode, $f); 6] = [
+                (Opcode::FloatNeg, -a), (Opcode::FloatAbs, a.abs()), (Opcode::FloatSqrt, a.sqrt()),
+                (Opcode::FloatCeil, a.ceil()), (Opcode::FloatFloor, a.floor()), (Opcode::FloatRound, a.round()),
+            ];
+            for (opcode, expected) in unary {
+                let result = emulate(emulator, opcode, std::slice::from_ref(&left), $size);
+                assert!(same(result, expected), "{:?} {} -> {:X}, expected {}", opcode, a, result, expected);
+            }
+            assert_eq!(emulate(emulator, Opcode::FloatNan, std::slice::from_ref(&left), 1), u128::from(a.is_nan()));
+
+            for b in values {
+                emulator.write_native(&right, u128::from(b.to_bits())).unwrap();
+                let inputs = [left.clone(), right.clone()];
+                let arithmetic: [(Opcode, $f); 4] = [
+                    (Opcode::FloatAdd, a + b), (Opcode::FloatSub, a - b),
+                    (Opcode::FloatMult, a * b), (Opcode::FloatDiv, a / b),
+                ];
+                for (opcode, expected) in arithmetic {
+                    let result = emulate(emulator, opcode, &inputs, $size);
+                    assert!(same(result, expected), "{:?} {} {} -> {:X}, expected {}", opcode, a, b, result, expected);
+                }
+                let comparisons = [
+                    (Opcode::FloatEqual, a == b), (Opcode::FloatNotEqual, a != b),
+                    (Opcode::FloatLess, a < b), (Opcode::FloatLessEqual, a <= b),
+                ];
+                for (opcode, expected) in comparisons {
+                    assert_eq!(emulate(emulator, opcode, &inputs, 1), u128::from(expected), "{:?} {} {}", opcode, a, b);
+                }
+            }
+        }
+    }};
+}
+
+#[test]
+fn test_float_ops() {
+    emulator!(emulator);
+    check_floats!(&emulator, f32, 4);
+    check_floats!(&emulator, f64, 8);
+
+    // conversions between float sizes round to nearest
+    let value = 0.1f64;
+    emulator.write_native(&unique(0, 8), u128::from(value.to_bits())).unwrap();
+    let single = emulate(&emulator, Opcode::FloatFloat2Float, &[unique(0, 8)], 4);
+    assert_eq!(single, u128::from((value as f32).to_bits()));
+    emulator.write_native(&unique(0, 4), single).unwrap();
+    let double = emulate(&emulator, Opcode::FloatFloat2Float, &[unique(0, 4)], 8);
+    assert_eq!(double, u128::from(f64::from(value as f32).to_bits()));
+}
+
+#[test]
+fn test_float_conversions() {
+    emulator!(emulator);
+    let integer = unique(0, 8);
+
+    // integers round straight to the output's format, without going through a double first
+    let value = (1i64 << 60) + (1 << 36) + 1;
+    emulator.write_native(&integer, value as u128).unwrap();
+    let single = emulate(&emulator, Opcode::FloatInt2Float, std::slice::from_ref(&integer), 4);
+    assert_eq!(single, u128::from((((1u64 << 60) + (1 << 37)) as f32).to_bits()));
+    assert_eq!(emulate(&emulator, Opcode::FloatInt2Float, std::slice::from_ref(&integer), 10), 0x403B_8000_0080_0000_0008);
+    emulator.write_native(&unique(0, 4), u128::from(-7i32 as u32)).unwrap();
+    let double = emulate(&emulator, Opcode::FloatInt2Float, &[unique(0, 4)], 8);
+    assert_eq!(double, u128::from((-7f64).to_bits()));
+
+    // truncation toward zero, with out of range values and nans giving the most negative integer
+    // of the output's size
+    let cases: [(f64, u32, u64); 8] = [
+        (-2.75, 4, 0xFFFF_FFFE),
+        (2.75, 4, 2),
+        (3e9, 4, 0x8000_0000),
+        (-2147483648.0, 4, 0x8000_0000),
+        (f64::NAN, 4, 0x8000_0000),
+        (f64::INFINITY, 8, 0x8000_0000_0000_0000),
+        (1e19, 8, 0x8000_0000_0000_0000),
+        (40000.0, 2, 0x8000),
+    ];
+    for (value, size, expected) in cases {
+        emulator.write_native(&unique(0x10, 8), u128::from(value.to_bits())).unwrap();
+        let result = emulate(&emulator, Opcode::FloatTrunc, &[unique(0x10, 8)], size);
+        assert_eq!(result, u128::from(expected), "trunc {} to {} bytes", value, size);
+    }
+}
+
+#[test]
+fn test_extended_floats() {
+    emulator!(emulator);
+    let (left, right) = (unique(0, 10), unique(0x10, 10));
+    // 1 + 2^-63, which a double can't hold
+    let value = 0x3FFF_8000_0000_0000_0001;
+    emulator.write_native(&left, value).unwrap();
+    emulator.write_native(&right, value).unwrap();
+
+    // arithmetic and comparisons keep the bits a double would lose
+    assert_eq!(emulate(&emulator, Opcode::FloatAdd, &[left.clone(), right.clone()], 10), 0x4000_8000_0000_0000_0001);
+    emulator.write_native(&right, FloatFormat::Extended.encode(1.0)).unwrap();
+    assert_eq!(emulate(&emulator, Opcode::FloatSub, &[left.clone(), right.clone()], 10), 0x3FC0_8000_0000_0000_0000);
+    assert_eq!(emulate(&emulator, Opcode::FloatLess, &[right.clone(), left.clone()], 1), 1);
+    assert_eq!(emulate(&emulator, Opcode::FloatEqual, &[right.clone(), left.clone()], 1), 0);
+
+    // conversions, sign changes and nan checks are exact
+    assert_eq!(emulate(&emulator, Opcode::FloatNeg, std::slice::from_ref(&left), 10), value | 1 << 79);
+    assert_eq!(emulate(&emulator, Opcode::FloatNan, std::slice::from_ref(&left), 1), 0);
+    assert_eq!(emulate(&emulator, Opcode::FloatFloat2Float, std::slice::from_ref(&left), 8), u128::from(1f64.to_bits()));
+    assert_eq!(emulate(&emulator, Opcode::FloatTrunc, std::slice::from_ref(&left), 8), 1);
+    emulator.write_native(&unique(0x20, 8), u128::from(0.1f64.to_bits())).unwrap();
+    let extended = emulate(&emulator, Opcode::FloatFloat2Float, &[unique(0x20, 8)], 10);
+    assert_eq!(extended, FloatFormat::Extended.encode(0.1));
+}
+
+#[test]
+fn test_x87_arithmetic() {
+    let binary = empty_binary();
+    let mut machine = machine(&binary);
+    // pi as loaded by FLDPI, and e. The expected values are the exact results rounded to nearest
+    // even, which a double can't hold.
+    let (pi, e) = (0x4000_C90F_DAA2_2168_C235, 0x4000_ADF8_5458_A2BB_4A9A);
+    let cases: [(&str, &[u8], u128); 5] = [
+        ("fadd st0, st1", &[0xD8, 0xC1], 0x4001_BB84_177D_6212_0668),
+        ("fsub st0, st1", &[0xD8, 0xE1], 0x3FFD_D8BC_324B_F56B_BCD8),
+        ("fmul st0, st1", &[0xD8, 0xC9], 0x4002_88A2_C05A_2EA3_A4F3),
+        ("fdiv st0, st1", &[0xD8, 0xF1], 0x3FFF_93EE_DFB1_38ED_EF80),
+        ("fsqrt", &[0xD9, 0xFA], 0x3FFF_E2DF_C48D_A77B_553D),
+    ];
+    // sleigh caches decoded instructions by address, so each gets its own
+    let mut address = 0;
+    let pcodes = cases.map(|(_, code, _)| {
+        address += 0x10;
+        machine.decompiler.translate(code, address, code.len() as u64).1
+    });
+
+    let emulator = Emulator::new(&machine, 0, 0).unwrap();
+    let (st0, st1) = (&machine.named_registers["ST0"], &machine.named_registers["ST1"]);
+    for ((name, _, expected), pcodes) in cases.into_iter().zip(pcodes) {
+        emulator.write_native(st0, pi).unwrap();
+        emulator.write_native(st1, e).unwrap();
+        for pcode in &pcodes {
+            emulator.emulate_one(pcode).unwrap();
+        }
+        let result = emulator.read_native(st0).unwrap();
+        assert_eq!(result, expected, "{} gave {:X}", name, result);
+    }
+}
+
+#[test]
+fn test_control_flow() {
+    let binary = empty_binary();
+    let mut machine = machine(&binary);
+    for address in [0x10, 0x14, 0x20] {
+        machine.instructions.insert(address, Instruction { address, mnemonic: "NOP".to_string(), body: String::new() });
+    }
+    let emulator = Emulator::new(&machine, 0, 0).unwrap();
+    let ram = |offset| VarnodeData { space: space("ram"), offset, size: 4 };
+    let target = unique(0, 4);
+    emulator.write_native(&target, 0x1234).unwrap();
+    let op = |opcode, vars: Vec<VarnodeData>| PCode { address: 0x10, opcode, vars, outvar: None };
+    let control = |pcode: PCode| emulator.emulate_one(&pcode).unwrap();
+
+    assert_eq!(control(op(Opcode::Branch, vec![ram(0x20)])), PCodeControl::Branch(0x20));
+    assert_eq!(control(op(Opcode::CBranch, vec![ram(0x20), constant(1, 1)])), PCodeControl::Branch(0x20));
+    assert_eq!(control(op(Opcode::CBranch, vec![ram(0x20), constant(0, 1)])), PCodeControl::Continue);
+    assert_eq!(control(op(Opcode::BranchInd, vec![target.clone()])), PCodeControl::Branch(0x1234));
+    assert_eq!(
+        control(op(Opcode::Call, vec![ram(0x20)])),
+        PCodeControl::Call { target: 0x20, return_address: 0x14 },
+    );
+    assert_eq!(
+        control(op(Opcode::CallInd, vec![target.clone()])),
+        PCodeControl::Call { target: 0x1234, return_address: 0x14 },
+    );
+    assert_eq!(control(op(Opcode::Return, vec![target])), PCodeControl::Return(0x1234));
+    assert_eq!(control(op(Opcode::Return, vec![constant(0xFFFF_FFF0, 4)])), PCodeControl::Return(0xFFFF_FFF0));
+}
+
+#[test]
+fn test_call_return() {
+    let binary = empty_binary();
+    let mut machine = machine(&binary);
+    let eax = machine.named_registers["EAX"].clone();
+    let ecx = machine.named_registers["ECX"].clone();
+    let ram = |offset| VarnodeData { space: space("ram"), offset, size: 4 };
+    let op = |address, opcode, vars| PCode { address, opcode, vars, outvar: None };
+    // the call is 7 bytes long, and the callee jumps through ECX before returning to EAX
+    machine.pcodes.insert(0x10, vec![op(0x10, Opcode::Call, vec![ram(0x40)])]);
+    machine.pcodes.insert(0x17, vec![]);
+    machine.pcodes.insert(0x40, vec![op(0x40, Opcode::BranchInd, vec![ecx])]);
+    machine.pcodes.insert(0x50, vec![op(0x50, Opcode::Return, vec![eax.clone()])]);
+    for address in [0x10, 0x17, 0x40, 0x50] {
+        machine.instructions.insert(address, Instruction { address, mnemonic: "NOP".to_string(), body: String::new() });
+    }
+
+    let mut emulator = Emulator::new(&machine, 0x10, 0x17).unwrap();
+    emulator.set_reg("ECX", 0x50u32).unwrap();
+    emulator.set_reg("EAX", 0x17u32).unwrap();
+    let mut controls = Vec::new();
+    while let Some(next) = emulator.next() {
+        let (_, pcode) = next.unwrap();
+        let control = emulator.emulate_one(pcode).unwrap();
+        controls.push((emulator.address, control));
+        emulator.apply(control).unwrap();
+    }
+    // the return address is the next instruction, even though its pcode is empty
+    assert_eq!(controls, [
+        (0x10, PCodeControl::Call { target: 0x40, return_address: 0x17 }),
+        (0x40, PCodeControl::Branch(0x50)),
+        (0x50, PCodeControl::Return(0x17)),
+    ]);
+    assert_eq!(emulator.address, 0x17);
+
+    // calls from the last instruction have nowhere to return to
+    for call in [op(0x50, Opcode::Call, vec![ram(0x40)]), op(0x50, Opcode::CallInd, vec![eax])] {
+        let error = emulator.emulate_one(&call).unwrap_err();
+        assert!(matches!(error.kind, ErrorKind::EndOfCode), "{}", error);
+        assert_eq!(error.address, 0x50);
+    }
+}
+
+#[test]
+fn test_relative_branches() {
+    let binary = empty_binary();
+    let mut machine = machine(&binary);
+    let eax = machine.named_registers["EAX"].clone();
+    let (counter, flag, skipped) = (unique(0, 4), unique(0x10, 1), unique(0x20, 4));
+    let op = |opcode, vars, outvar| PCode { address: 0x10, opcode, vars, outvar };
+    // counts to 3 with a backward branch, then jumps forward over a copy
+    machine.pcodes.insert(0x10, vec![
+        op(Opcode::IntAdd, vec![counter.clone(), constant(1, 4)], Some(counter.clone())),
+        op(Opcode::IntLess, vec![counter.clone(), constant(3, 4)], Some(flag.clone())),
+        op(Opcode::CBranch, vec![constant(-2i32 as u32 as u64, 4), flag], None),
+        op(Opcode::Branch, vec![constant(2, 4)], None),
+        op(Opcode::Copy, vec![constant(0xBAD, 4)], Some(skipped.clone())),
+        op(Opcode::Copy, vec![counter], Some(eax)),
+    ]);
+    machine.pcodes.insert(0x20, vec![op(Opcode::Branch, vec![constant(1, 4)], None)]);
+    machine.pcodes.insert(0x30, vec![]);
+
+    let mut emulator = Emulator::new(&machine, 0x10, 0x30).unwrap();
+    let mut indices = Vec::new();
+    while let Some(next) = emulator.next() {
+        let (i, pcode) = next.unwrap();
+        indices.push((emulator.address, i));
+        if emulator.address == 0x10 && i == 5 {
+            assert_eq!(emulator.read_native(&skipped).unwrap(), 0);
+        }
+        let control = emulator.emulate_one(pcode).unwrap();
+        emulator.apply(control).unwrap();
+    }
+    assert_eq!(emulator.reg::<u32>("EAX").unwrap(), 3);
+    // branching just past the last op of 0x20 falls through to the next instruction
+    assert_eq!(indices, [
+        (0x10, 0), (0x10, 1), (0x10, 2), (0x10, 0), (0x10, 1), (0x10, 2), (0x10, 0), (0x10, 1), (0x10, 2),
+        (0x10, 3), (0x10, 5), (0x20, 0),
+    ]);
+    assert_eq!(emulator.address, 0x30);
+
+    // targets outside the instruction are errors, not wrap-arounds
+    let mut emulator = Emulator::new(&machine, 0x10, 0x30).unwrap();
+    emulator.next().unwrap().unwrap();
+    for offset in [-1, 7, i64::MAX, i64::MIN] {
+        let error = emulator.branch_relative(offset).unwrap_err();
+        assert!(matches!(error.kind, ErrorKind::Decode(_)), "{}", error);
+        assert_eq!((error.address, error.index), (0x10, Some(0)));
+    }
+    emulator.branch_relative(6).unwrap();
+    assert_eq!(emulator.next().unwrap().unwrap().0, 0);
+    assert_eq!(emulator.address, 0x20);
+}
+
+#[test]
+fn test_call_other() {
+    emulator!(emulator);
+    let machine = emulator.emulator;
+    let index = |name| machine.user_ops.iter().position(|op| op == name).expect("no such user op") as u64;
+
+    // rdtsc is handled by default, and advances every time it's read
+    let rdtsc = PCode {
+        address: 0,
+        opcode: Opcode::CallOther,
+        vars: vec![constant(index("rdtsc"), 4)],
+        outvar: Some(unique(0, 8)),
+    };
+    assert_eq!(emulator.emulate_one(&rdtsc).unwrap(), PCodeControl::Continue);
+    assert_eq!(emulator.read_native(&unique(0, 8)).unwrap(), 1);
+    emulator.emulate_one(&rdtsc).unwrap();
+    assert_eq!(emulator.read_native(&unique(0, 8)).unwrap(), 2);
+
+    // the inputs after the op index are passed to the handler
+    emulator.write_native(&unique(0x10, 4), 0x1122_3344).unwrap();
+    let swap = PCode {
+        address: 0,
+        opcode: Opcode::CallOther,
+        vars: vec![constant(index("swap_bytes"), 4), unique(0x10, 4)],
+        outvar: Some(unique(0x20, 4)),
+    };
+    emulator.emulate_one(&swap).unwrap();
+    assert_eq!(emulator.read_native(&unique(0x20, 4)).unwrap(), 0x4433_2211);
+
+    let unknown = PCode { address: 0, opcode: Opcode::CallOther, vars: vec![constant(10_000, 4)], outvar: None };
+    assert!(matches!(emulator.emulate_one(&unknown).unwrap_err().kind, ErrorKind::Decode(_)));
+}
+
+#[test]
+fn test_user_op_handlers() {
+    emulator!(mut emulator);
+    let machine = emulator.emulator;
+    let index = |name| machine.user_ops.iter().position(|op| op == name).expect("no such user op") as u64;
+    let call = |name, output| PCode { address: 0, opcode: Opcode::CallOther, vars: vec![constant(index(name), 4)], outvar: output };
+
+    // operating system entry points have no default handler
+    let error = emulator.emulate_one(&call("sysenter", None)).unwrap_err();
+    assert!(matches!(&error.kind, ErrorKind::UnhandledUserOp(name) if name == "sysenter"), "{}", error);
+    assert_eq!(error.to_string(), "unhandled user-defined op: sysenter at 00000000");
+
+    // registering a handler replaces the default, and its control is passed on
+    emulator.register_user_op("rdtsc", |emulator, _, output| {
+        emulator.write(output.context("expected output")?, 0x1234u64)?;
+        Ok(PCodeControl::Continue)
+    });
+    emulator.register_user_op("sysenter", |_, _, _| Ok(PCodeControl::Branch(0x80)));
+    assert_eq!(emulator.emulate_one(&call("rdtsc", Some(unique(0, 8)))).unwrap(), PCodeControl::Continue);
+    assert_eq!(emulator.read_native(&unique(0, 8)).unwrap(), 0x1234);
+    assert_eq!(emulator.emulate_one(&call("sysenter", None)).unwrap(), PCodeControl::Branch(0x80));
+
+    // errors from the handler name the op
+    emulator.register_user_op("syscall", |_, _, _| anyhow::bail!("no kernel"));
+    let error = emulator.emulate_one(&call("syscall", None)).unwrap_err();
+    assert!(matches!(&error.kind, ErrorKind::UserOp { name, .. } if name == "syscall"), "{}", error);
+    assert_eq!(error.to_string(), "user-defined op syscall failed: no kernel at 00000000");
+}
+
+#[test]
+fn test_size_checks() {
+    emulator!(emulator);
+    let op = |opcode, vars: Vec<VarnodeData>, output| PCode { address: 0, opcode, vars, outvar: Some(output) };
+    let cases = [
+        op(Opcode::Copy, vec![unique(0, 4)], unique(0x100, 2)),
+        op(Opcode::IntAdd, vec![unique(0, 4), unique(0x10, 2)], unique(0x100, 4)),
+        op(Opcode::IntLess, vec![unique(0, 4), unique(0x10, 4)], unique(0x100, 4)),
+        op(Opcode::IntCarry, vec![unique(0, 4), unique(0x10, 4)], unique(0x100, 4)),
+        op(Opcode::IntLeft, vec![unique(0, 4), unique(0x10, 1)], unique(0x100, 8)),
+        op(Opcode::Piece, vec![unique(0, 4), unique(0x10, 4)], unique(0x100, 4)),
+    ];
+    for pcode in &cases {
+        for emulate in [Emulator::emulate_native, Emulator::emulate_wide] {
+            let error = emulate(&emulator, pcode).unwrap_err();
+            assert!(matches!(error, ErrorKind::SizeMismatch { .. }), "{:?}: {}", pcode.opcode, error);
+        }
+        assert!(emulator.emulate_one(pcode).is_err());
+    }
+}
+
+#[test]
+fn test_wide_varnodes() {
+    emulator!(emulator);
+    // 32 byte varnodes, like AVX registers, are too wide to emulate natively
+    let (left, right, output) = (unique(0, 32), unique(0x20, 32), unique(0x40, 32));
+    emulator.set_bytes(&left, &[0xFF; 32]).unwrap();
+    let mut one = [0; 32];
+    one[0] = 1;
+    emulator.set_bytes(&right, &one).unwrap();
+    let add = PCode { address: 0, opcode: Opcode::IntAdd, vars: vec![left.clone(), right], outvar: Some(output.clone()) };
+    emulator.emulate_one(&add).unwrap();
+    assert_eq!(emulator.get_bytes(&output).unwrap(), [0; 32]);
+
+    let zext = PCode { address: 0, opcode: Opcode::IntZExt, vars: vec![unique(0, 8)], outvar: Some(output.clone()) };
+    emulator.emulate_one(&zext).unwrap();
+    assert_eq!(emulator.get_bytes(&output).unwrap(), [[0xFF; 8], [0; 8], [0; 8], [0; 8]].concat());
+}
+
+#[test]
+fn test_error_location() {
+    let binary = empty_binary();
+    let mut machine = machine(&binary);
+    let output = unique(0, 4);
+    let copy = PCode { address: 0x10, opcode: Opcode::Copy, vars: vec![constant(1, 4)], outvar: Some(output.clone()) };
+    let divide = PCode {
+        address: 0x10,
+        opcode: Opcode::IntDiv,
+        vars: vec![output.clone(), constant(0, 4)],
+        outvar: Some(output.clone()),
+    };
+    machine.pcodes.insert(0x10, vec![copy, divide]);
+
+    let mut emulator = Emulator::new(&machine, 0x10, 0x10).unwrap();
+    let mut error = None;
+    while let Some(next) = emulator.next() {
+        let (_, pcode) = next.unwrap();
+        match emulator.emulate_one(pcode) {
+            Ok(control) => emulator.apply(control).unwrap(),
+            Err(e) => {
+                error = Some(e);
+                break;
+            }
+        }
+    }
+    let error = error.expect("dividing by zero should fail");
+    assert!(matches!(error.kind, ErrorKind::DivisionByZero));
+    assert_eq!((error.address, error.index), (0x10, Some(1)));
+    assert_eq!(error.to_string(), "division by zero at 00000010.01");
+
+    // writing to the constant space is an error rather than a panic
+    let store = PCode { address: 0x10, opcode: Opcode::Copy, vars: vec![output], outvar: Some(constant(0, 4)) };
+    let error = emulator.emulate_one(&store).unwrap_err();
+    assert!(matches!(error.kind, ErrorKind::InvalidSpace(_)));
+}
+
+#[test]
+fn test_load_store() {
+    emulator!(emulator);
+    let ram = constant(space("ram").index as u64, 4);
+    let pointer = unique(0x100, 4);
+    let value = unique(0x200, 4);
+    let loaded = unique(0x300, 4);
+    let store = PCode { address: 0, opcode: Opcode::Store, vars: vec![ram.clone(), pointer.clone(), value.clone()], outvar: None };
+    let load = PCode { address: 0, opcode: Opcode::Load, vars: vec![ram, pointer.clone()], outvar: Some(loaded.clone()) };
+
+    for emulate in [Emulator::emulate_native, Emulator::emulate_wide] {
+        emulator.write_native(&pointer, 0x1234).unwrap();
+        emulator.write_native(&value, 0xDEAD_BEEF).unwrap();
+        emulate(&emulator, &store).unwrap();
+        assert_eq!(emulator.ram().get_bytes(0x1234, 4), [0xEF, 0xBE, 0xAD, 0xDE]);
+        emulator.write_native(&loaded, 0).unwrap();
+        emulate(&emulator, &load).unwrap();
+        assert_eq!(emulator.read_native(&loaded).unwrap(), 0xDEAD_BEEF);
+    }
+
+    let bad = PCode { address: 0, opcode: Opcode::Load, vars: vec![constant(1000, 4), pointer], outvar: Some(loaded) };
+    assert!(matches!(emulator.emulate_one(&bad).unwrap_err().kind, ErrorKind::InvalidSpace(_)));
+}
+
+#[test]
+fn test_word_addressed_load_store() {
+    // pointers into a space of 4 byte words are scaled to bytes, wrapping like any other address
+    let binary = empty_binary();
+    let mut machine = machine(&binary);
+    let index = machine.spaces.iter().position(|space| space.name == "ram").unwrap();
+    machine.spaces[index].wordsize = 4;
+    let emulator = Emulator::new(&machine, 0, 0).unwrap();
+
+    let ram = constant(space("ram").index as u64, 4);
+    let pointer = unique(0x100, 8);
+    let value = unique(0x200, 4);
+    let store = PCode { address: 0, opcode: Opcode::Store, vars: vec![ram, pointer.clone(), value.clone()], outvar: None };
+    emulator.write_native(&value, 0xDEAD_BEEF).unwrap();
+    for emulate in [Emulator::emulate_native, Emulator::emulate_wide] {
+        for (word, byte) in [(0x4000_0000_0000_0401, 0x1004), (0x4000_0002, 8)] {
+            emulator.write_native(&pointer, word).unwrap();
+            emulate(&emulator, &store).unwrap();
+            assert_eq!(emulator.ram().get_bytes(byte, 4), [0xEF, 0xBE, 0xAD, 0xDE], "{:X}", word);
+        }
+    }
+}
+
+#[test]
+fn test_memory_faults() {
+    emulator!(mut emulator);
+    let ram = |offset| VarnodeData { space: space("ram"), offset, size: 4 };
+    let copy = |from, to| PCode { address: 0, opcode: Opcode::Copy, vars: vec![from], outvar: Some(to) };
+
+    // unmapped memory reads as zero by default
+    emulator.emulate_one(&copy(ram(0), unique(0, 4))).unwrap();
+
+    emulator.map_memory(0x1000, 0x1000, Permissions::READ);
+    let error = emulator.emulate_one(&copy(constant(1, 4), ram(0x1FFE))).unwrap_err();
+    assert!(matches!(
+        error.kind,
+        ErrorKind::AccessViolation { offset: 0x1FFE, access: Access::Write, .. }
+    ), "{}", error);
+
+    emulator.set_unmapped_policy(UnmappedPolicy::Fault);
+    let error = emulator.emulate_one(&copy(ram(0x1FFE), unique(0, 4))).unwrap_err();
+    assert!(matches!(
+        error.kind,
+        ErrorKind::UnmappedMemory { offset: 0x2000, access: Access::Read, .. }
+    ), "{}", error);
+    emulator.emulate_one(&copy(ram(0x1000), unique(0, 4))).unwrap();
+}
+
+#[test]
+fn test_snapshot_restore() {
+    let binary = empty_binary();
+    let mut machine = machine(&binary);
+    let output = unique(0, 4);
+    let add = PCode {
+        address: 0x10,
+        opcode: Opcode::IntAdd,
+        vars: vec![output.clone(), constant(1, 4)],
+        outvar: Some(output.clone()),
+    };
+    machine.pcodes.insert(0x10, vec![add.clone(), add]);
+    machine.pcodes.insert(0x20, vec![]);
+
+    let mut emulator = Emulator::new(&machine, 0x10, 0x20).unwrap();
+    let step = |emulator: &mut Emulator| {
+        let (_, pcode) = emulator.next().unwrap().unwrap();
+        let control = emulator.emulate_one(pcode).unwrap();
+        emulator.apply(control).unwrap();
+    };
+    step(&mut emulator);
+    let snapshot = emulator.snapshot();
+    step(&mut emulator);
+    assert_eq!(emulator.read_native(&output).unwrap(), 2);
+
+    emulator.restore(&snapshot);
+    assert_eq!(emulator.read_native(&output).unwrap(), 1);
+    // the second add runs again from the restored position
+    step(&mut emulator);
+    assert_eq!(emulator.read_native(&output).unwrap(), 2);
+    assert_eq!(emulator.address, 0x10);
+}
+
+#[test]
+fn test_spaces_by_index() {
+    emulator!(emulator);
+    for name in ["ram", "register", "unique"] {
+        let index = space(name).index;
+        assert_eq!(emulator.get_space_by_index(index).map(|space| space.name()), Some(name));
+    }
+    assert_eq!(emulator.ram().name(), "ram");
+    assert!(emulator.get_space_by_index(space("const").index).is_none());
+
+    // a varnode in a space the language doesn't have
+    let mut node = unique(0, 4);
+    node.space.index = 1000;
+    let error = emulator.emulate_one(&PCode { address: 0, opcode: Opcode::Copy, vars: vec![node], outvar: Some(unique(0, 4)) })
+        .unwrap_err();
+    assert!(matches!(error.kind, ErrorKind::InvalidSpace(_)));
+}
+
+#[test]
+fn test_unique_cleared_between_instructions() {
+    let binary = empty_binary();
+    let mut machine = machine(&binary);
+    let temp = unique(0x80, 4);
+    let copy = |value| PCode { address: 0, opcode: Opcode::Copy, vars: vec![constant(value, 4)], outvar: Some(temp.clone()) };
+    machine.pcodes.insert(0, vec![copy(1)]);
+    machine.pcodes.insert(4, vec![]);
+    let mut emulator = Emulator::new(&machine, 0, 4).unwrap();
+
+    let eax = machine.named_registers["EAX"].clone();
+    assert!(emulator.get_space_by_index(eax.space.index).unwrap().is_flat());
+    assert!(emulator.get_space_by_index(temp.space.index).unwrap().is_flat());
+
+    let (_, pcode) = emulator.next().unwrap().unwrap();
+    emulator.emulate_one(pcode).unwrap();
+    // values in unique survive until the end of the instruction
+    assert_eq!(emulator.read_native(&temp).unwrap(), 1);
+    assert!(emulator.next().is_none());
+    assert_eq!(emulator.address, 4);
+    assert_eq!(emulator.read_native(&temp).unwrap(), 0);
+}
@@ -0,0 +1,97 @@
+//!
+//! Handlers for user-defined pcode ops (CALLOTHER).
+//!
+//! Sleigh uses user ops for instructions whose effect it can't describe in pcode. Every emulator
+//! starts with handlers for the x86 ones ordinary user-mode code runs into, and anything else can
+//! be handled with `Emulator::register_user_op`.
+//!
+//! Some common instructions are deliberately left to the caller:
+//! - `sysenter`, `syscall` and `swi` (INT n) enter the operating system, so what they do depends
+//!   entirely on which kernel is being emulated, if any.
+//! - CPUID is split into `cpuid_*` ops that each return a pointer to the EAX, EBX, EDX and ECX
+//!   results in memory. Which processor to claim to be, and where that memory lives in the
+//!   emulated address space, are choices only the caller can make.
+//!
+//! Running into one of them without a handler fails with `ErrorKind::UnhandledUserOp`.
+
+use std::cell::Cell;
+use anyhow::{bail, Context};
+use num::BigUint;
+use sleigh::VarnodeData;
+use crate::emulator::{Emulator, FloatFormat, PCodeControl};
+
+/// A handler for a user-defined pcode op (CALLOTHER), given the op's inputs (excluding the op
+/// index) and its output, if it has one.
+pub type UserOpHandler = dyn Fn(&Emulator<'_, '_>, &[VarnodeData], Option<&VarnodeData>) -> anyhow::Result<PCodeControl>;
+
+/// The handlers installed on every emulator, for the x86 user ops most programs run into
+pub(crate) fn defaults() -> Vec<(&'static str, Box<UserOpHandler>)> {
+    // a fake timestamp counter that advances every time it's read
+    let timestamp = Cell::new(0u64);
+
+    vec![
+        // LOCK prefixes only matter with more than one processor
+        ("LOCK", Box::new(|_, _, _| Ok(PCodeControl::Continue))),
+        // memory is flat, so segment:offset is just the offset
+        ("segment", Box::new(|emulator, inputs, output| {
+            let [_segment, offset] = inputs else {
+                bail!("expected 2 inputs");
+            };
+            let output = output.context("expected output")?;
+            let offset: BigUint = emulator.read(offset)?;
+            emulator.write(output, offset)?;
+            Ok(PCodeControl::Continue)
+        })),
+        ("rdtsc", Box::new(move |emulator, _, output| {
+            let output = output.context("expected output")?;
+            timestamp.set(timestamp.get() + 1);
+            emulator.write(output, timestamp.get())?;
+            Ok(PCodeControl::Continue)
+        })),
+        ("swap_bytes", Box::new(|emulator, inputs, output| {
+            let [input0] = inputs else {
+                bail!("expected 1 input");
+            };
+            let output = output.context("expected output")?;
+            let mut bytes = emulator.get_bytes(input0)?.to_vec();
+            bytes.reverse();
+            emulator.set_bytes(output, &bytes)?;
+            Ok(PCodeControl::Continue)
+        })),
+        ("fsin", float_op(f64::sin)),
+        ("fcos", float_op(f64::cos)),
+        ("f2xm1", float_op(|value| value.exp2() - 1.0)),
+        ("fscale", Box::new(|emulator, inputs, output| {
+            let [input0, input1] = inputs else {
+                bail!("expected 2 inputs");
+            };
+            let output = output.context("expected output")?;
+            let value = read_x87(emulator, input0)?;
+            let scale = read_x87(emulator, input1)?;
+            emulator.write_float(output, value * scale.trunc().exp2())?;
+            Ok(PCodeControl::Continue)
+        })),
+    ]
+}
+
+/// A handler for an x87 op that computes `f` on its only input
+fn float_op(f: fn(f64) -> f64) -> Box<UserOpHandler> {
+    Box::new(move |emulator, inputs, output| {
+        let [input0] = inputs else {
+            bail!("expected 1 input");
+        };
+        let output = output.context("expected output")?;
+        let value = read_x87(emulator, input0)?;
+        emulator.write_float(output, f(value))?;
+        Ok(PCodeControl::Continue)
+    })
+}
+
+/// Reads an x87 register as a host double. The transcendental instructions aren't correctly
+/// rounded on real hardware either, so unlike the float pcode ops they're approximated in
+/// double precision.
+fn read_x87(emulator: &Emulator<'_, '_>, node: &VarnodeData) -> anyhow::Result<f64> {
+    let format = FloatFormat::from_size(node.size).context("expected a float")?;
+    let bits: u128 = emulator.read(node)?;
+    Ok(format.decode(bits))
+}
@@ -2,7 +2,7 @@
 
 use anyhow::Context;
 use pcode::binary::Binary;
-use pcode::emulator::{Machine, PCodeControl};
+use pcode::emulator::{Machine, PCodeControl, UnmappedPolicy};
 use crate::cli::{CLI, Command};
 
 mod util;
@@ -15,33 +15,63 @@ fn main() {
 fn run() -> anyhow::Result<()> {
     let args = <CLI as clap::Parser>::parse();
     match args.command {
-        Command::Emulate { binary, .. } => {
-            let binary = Binary::new(binary)?;
+        Command::Emulate { binary: path, fault_unmapped, cross_check, trace } => {
+            let binary = Binary::new(&path)?;
+            if cross_check {
+                binary.cross_check(&path)?;
+            }
             let mut machine = Machine::new(&binary)?;
 
             let mut emulator = machine.emulate("main")?;
+            if fault_unmapped {
+                emulator.set_unmapped_policy(UnmappedPolicy::Fault);
+            }
+            emulator.set_trace(trace);
 
+            let checkpoint = emulator.snapshot();
             println!("-=- Emulating -=-");
-            while let Some((i, pcode)) = emulator.next() {
+            // the return addresses of the calls we've made, to catch returns that don't match
+            let mut call_stack = Vec::new();
+            while let Some(next) = emulator.next() {
+                let (i, pcode) = next?;
                 let instruction = emulator.emulator.instructions.get(&pcode.address)
-                    .expect("no instruction for pcode");
-                println!("emulating {:0>8X}.{:0>2X} {: <20?} - ({}) {}", pcode.address, i, pcode.opcode, instruction.mnemonic, instruction.body);
+                    .with_context(|| format!("no instruction at {:0>8X}", pcode.address))?;
+                let symbol = emulator.symbolize(pcode.address);
+                println!("emulating {:0>8X}.{:0>2X} {: <20} {: <20?} - ({}) {}", pcode.address, i, symbol, pcode.opcode, instruction.mnemonic, instruction.body);
                 let control = emulator.emulate_one(pcode)
-                    .context("emulation failed")?;
+                    .with_context(|| format!("emulation failed in {}", symbol))?;
                 println!();
 
                 match control {
-                    PCodeControl::Branch(target) => {
-                        emulator.set_address(target);
+                    PCodeControl::Call { return_address, .. } => {
+                        call_stack.push(return_address);
+                    }
+                    PCodeControl::Return(target) => {
+                        if let Some(expected) = call_stack.pop() {
+                            if expected != target {
+                                println!(
+                                    "warning: returning to {:0>8X} ({}), expected a return to {:0>8X} ({})",
+                                    target, emulator.symbolize(target), expected, emulator.symbolize(expected),
+                                );
+                            }
+                        }
                     }
-                    PCodeControl::Continue => {}
+                    _ => {}
                 };
+                emulator.apply(control)?;
             }
 
             println!("-=- Done -=-");
-            let eax = emulator.emulator.named_registers.get("EAX").expect("no eax");
-            let value = emulator.read::<i32>(eax);
-            println!("$eax = {}", value);
+            print!("{}", emulator.dump_registers()?);
+            let register = emulator.emulator.architecture.return_register();
+            if let Ok(value) = emulator.reg::<i64>(register) {
+                println!("${} = {}", register.to_lowercase(), value);
+            }
+
+            println!("-=- Memory changes -=-");
+            for change in emulator.memory_changes(&checkpoint) {
+                println!("{:0>8X}: {:02X?} -> {:02X?}", change.address, change.old, change.new);
+            }
         }
     };
 
@@ -1,7 +1,7 @@
 use std::ffi::OsStr;
 use std::fs::File;
 use std::io;
-use std::io::{BufReader, BufWriter, ErrorKind, Read, Seek, SeekFrom};
+use std::io::{BufReader, BufWriter, Read, Seek, SeekFrom};
 use std::path::Path;
 use std::process::{Command};
 
@@ -33,15 +33,14 @@ pub trait ExecUtil {
     fn exec_and_get_stdout_as_string(self) -> io::Result<String>;
 }
 
-impl<'a> ExecUtil for &'a mut Command {
+impl ExecUtil for &mut Command {
     fn exec_and_get_stdout_as_string(self) -> io::Result<String> {
         let output = self.output()?;
         if !output.status.success() {
-            Err(io::Error::new(ErrorKind::Other, ""))
+            Err(io::Error::other(""))
         } else {
             String::from_utf8(output.stdout)
-                .map_err(|e| io::Error::new(
-                    ErrorKind::Other,
+                .map_err(|e| io::Error::other(
                     format!("unable to run `{:?}`: {}", self, e),
                 ))
         }
@@ -0,0 +1,211 @@
+#!/usr/bin/env python3
+"""
+Builds the PE fixtures in this directory by hand, so they can be regenerated without a Windows
+toolchain:
+
+  x86.exe  a 32 bit executable at 0x400000 exporting `next_value`, which reads a relocated global
+  x64.dll  a 64 bit DLL at 0x180000000 exporting `add`, `sub` and a forwarder to msvcrt
+
+Both import from a DLL by name and by ordinal, and carry base relocations.
+
+    $ python3 tests/pe/build.py
+"""
+
+import os
+import struct
+
+FILE_ALIGNMENT = 0x200
+SECTION_ALIGNMENT = 0x1000
+HEADERS_SIZE = 0x400
+
+CNT_CODE = 0x20
+CNT_INITIALIZED_DATA = 0x40
+CNT_UNINITIALIZED_DATA = 0x80
+MEM_DISCARDABLE = 0x02000000
+MEM_EXECUTE = 0x20000000
+MEM_READ = 0x40000000
+MEM_WRITE = 0x80000000
+
+TEXT = CNT_CODE | MEM_EXECUTE | MEM_READ
+RDATA = CNT_INITIALIZED_DATA | MEM_READ
+DATA = CNT_INITIALIZED_DATA | MEM_READ | MEM_WRITE
+BSS = CNT_UNINITIALIZED_DATA | MEM_READ | MEM_WRITE
+RELOC = CNT_INITIALIZED_DATA | MEM_READ | MEM_DISCARDABLE
+
+
+def align(value, alignment):
+    return (value + alignment - 1) // alignment * alignment
+
+
+class Rdata:
+    """Lays out the export and import tables of a `.rdata` section at `rva`"""
+
+    def __init__(self, rva, wide):
+        self.rva = rva
+        self.wide = wide
+        self.data = bytearray()
+
+    def here(self):
+        return self.rva + len(self.data)
+
+    def put(self, data, alignment=4):
+        self.data += b"\0" * (align(len(self.data), alignment) - len(self.data))
+        rva = self.here()
+        self.data += data
+        return rva
+
+    def string(self, text):
+        return self.put(text.encode() + b"\0", 1)
+
+    def exports(self, dll, functions):
+        """`functions` is a list of (name, rva or forwarder string), exported in ordinal order.
+        Everything goes inside the directory's extent, which is how forwarders are told apart."""
+        start = self.put(b"\0" * 40)
+        names = sorted((name, ordinal) for ordinal, (name, _) in enumerate(functions))
+        address_table = self.put(b"\0" * 4 * len(functions))
+        name_table = self.put(b"\0" * 4 * len(names))
+        ordinal_table = self.put(b"".join(struct.pack("<H", ordinal) for _, ordinal in names))
+        addresses = [self.string(target) if isinstance(target, str) else target for _, target in functions]
+        name_rvas = [self.string(name) for name, _ in names]
+        dll_name = self.string(dll)
+
+        def patch(rva, data):
+            offset = rva - self.rva
+            self.data[offset:offset + len(data)] = data
+
+        patch(address_table, b"".join(struct.pack("<I", rva) for rva in addresses))
+        patch(name_table, b"".join(struct.pack("<I", rva) for rva in name_rvas))
+        patch(start, struct.pack(
+            "<IIHHIIIIIII", 0, 0, 0, 0, dll_name, 1, len(functions), len(names),
+            address_table, name_table, ordinal_table,
+        ))
+        return start, self.here() - start
+
+    def imports(self, dll, entries):
+        """`entries` are names or ordinals, returns the import directory and the IAT"""
+        thunk, flag = ("<Q", 1 << 63) if self.wide else ("<I", 1 << 31)
+        thunks = []
+        for entry in entries:
+            if isinstance(entry, int):
+                thunks.append(flag | entry)
+            else:
+                thunks.append(self.put(struct.pack("<H", 0) + entry.encode() + b"\0", 2))
+        table = b"".join(struct.pack(thunk, value) for value in thunks + [0])
+        lookup = self.put(table, 8)
+        iat = self.put(table, 8)
+        dll_name = self.string(dll)
+        directory = struct.pack("<IIIII", lookup, 0, 0, dll_name, iat) + b"\0" * 20
+        return (self.put(directory), len(directory)), (iat, len(table))
+
+
+def relocations(pages):
+    """A `.reloc` section from a map of page rva to a list of (type, offset)"""
+    out = bytearray()
+    for page, entries in sorted(pages.items()):
+        words = [kind << 12 | offset for kind, offset in entries]
+        if len(words) % 2:
+            words.append(0)
+        out += struct.pack("<II", page, 8 + 2 * len(words))
+        out += b"".join(struct.pack("<H", word) for word in words)
+    return bytes(out)
+
+
+def image(machine, wide, dll, image_base, entry, sections, directories):
+    """`sections` is a list of (name, rva, virtual size, data, characteristics)"""
+    header = bytearray(b"MZ" + b"\0" * 0x3A + struct.pack("<I", 0x80))
+    header += b"\0" * (0x80 - len(header))
+    header += b"PE\0\0"
+
+    characteristics = 0x0002 | (0x2000 if dll else 0) | (0x0020 if wide else 0x0100)
+    optional_size = 240 if wide else 224
+    header += struct.pack("<HHIIIHH", machine, len(sections), 0, 0, 0, optional_size, characteristics)
+
+    size_of_code = sum(align(len(data), FILE_ALIGNMENT) for _, _, _, data, flags in sections if flags & CNT_CODE)
+    size_of_data = sum(align(len(data), FILE_ALIGNMENT) for _, _, _, data, flags in sections if flags & CNT_INITIALIZED_DATA)
+    size_of_bss = sum(align(size, FILE_ALIGNMENT) for _, _, size, _, flags in sections if flags & CNT_UNINITIALIZED_DATA)
+    size_of_image = align(max(rva + size for _, rva, size, _, _ in sections), SECTION_ALIGNMENT)
+
+    optional = struct.pack("<HBBIIIII", 0x20B if wide else 0x10B, 14, 0, size_of_code, size_of_data, size_of_bss, entry, 0x1000)
+    if wide:
+        optional += struct.pack("<Q", image_base)
+    else:
+        optional += struct.pack("<II", 0x2000, image_base)
+    optional += struct.pack(
+        "<IIHHHHHHIIIIHH", SECTION_ALIGNMENT, FILE_ALIGNMENT, 6, 0, 0, 0, 6, 0, 0,
+        size_of_image, HEADERS_SIZE, 0, 3, 0x0140,
+    )
+    optional += struct.pack("<QQQQ" if wide else "<IIII", 0x100000, 0x1000, 0x100000, 0x1000)
+    optional += struct.pack("<II", 0, 16)
+    for index in range(16):
+        optional += struct.pack("<II", *directories.get(index, (0, 0)))
+    assert len(optional) == optional_size
+    header += optional
+
+    offset = HEADERS_SIZE
+    body = bytearray()
+    for name, rva, size, data, flags in sections:
+        raw_size = align(len(data), FILE_ALIGNMENT)
+        header += struct.pack(
+            "<8sIIIIIIHHI", name.encode(), size, rva, raw_size, offset if raw_size else 0, 0, 0, 0, 0, flags,
+        )
+        body += data + b"\0" * (raw_size - len(data))
+        offset += raw_size
+    assert len(header) <= HEADERS_SIZE
+    return bytes(header) + b"\0" * (HEADERS_SIZE - len(header)) + bytes(body)
+
+
+def x86():
+    base = 0x400000
+    # next_value: mov eax, [0x403000]; add eax, 1; ret
+    text = bytes([0xA1]) + struct.pack("<I", base + 0x3000) + bytes([0x83, 0xC0, 0x01, 0xC3])
+
+    rdata = Rdata(0x2000, False)
+    (imports, iat) = rdata.imports("KERNEL32.dll", ["ExitProcess", 17])
+    exports = rdata.exports("x86.exe", [("next_value", 0x1000)])
+
+    # the value read by next_value, and a pointer to it
+    data = struct.pack("<II", 41, base + 0x3000)
+    reloc = relocations({0x1000: [(3, 0x001)], 0x3000: [(3, 0x004)]})
+    sections = [
+        (".text", 0x1000, len(text), text, TEXT),
+        (".rdata", 0x2000, len(rdata.data), bytes(rdata.data), RDATA),
+        (".data", 0x3000, len(data), data, DATA),
+        (".bss", 0x4000, 0x100, b"", BSS),
+        (".reloc", 0x5000, len(reloc), reloc, RELOC),
+    ]
+    directories = {0: exports, 1: imports, 5: (0x5000, len(reloc)), 12: iat}
+    return image(0x14C, False, False, base, 0x1000, sections, directories)
+
+
+def x64():
+    base = 0x180000000
+    # add: lea eax, [rcx + rdx]; ret
+    # sub: mov eax, ecx; sub eax, edx; ret
+    text = bytes([0x8D, 0x04, 0x11, 0xC3, 0x89, 0xC8, 0x29, 0xD0, 0xC3])
+
+    rdata = Rdata(0x2000, True)
+    (imports, iat) = rdata.imports("msvcrt.dll", ["puts", 7])
+    exports = rdata.exports("x64.dll", [("add", 0x1000), ("sub", 0x1004), ("print", "msvcrt.printf")])
+
+    # a pointer to add, followed by room that's only in memory
+    data = struct.pack("<Q", base + 0x1000)
+    reloc = relocations({0x3000: [(10, 0x000)]})
+    sections = [
+        (".text", 0x1000, len(text), text, TEXT),
+        (".rdata", 0x2000, len(rdata.data), bytes(rdata.data), RDATA),
+        (".data", 0x3000, 0x20, data, DATA),
+        (".reloc", 0x4000, len(reloc), reloc, RELOC),
+    ]
+    directories = {0: exports, 1: imports, 5: (0x4000, len(reloc)), 12: iat}
+    return image(0x8664, True, True, base, 0, sections, directories)
+
+
+def main():
+    here = os.path.dirname(os.path.abspath(__file__))
+    for name, build in [("x86.exe", x86), ("x64.dll", x64)]:
+        with open(os.path.join(here, name), "wb") as file:
+            file.write(build())
+
+
+if __name__ == "__main__":
+    main()